@@ -0,0 +1,593 @@
+use ark_bls12_381::Fr;
+use ark_ff::{BigInteger, PrimeField, Zero};
+use blake2::{Blake2b512, Digest};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Hashed into every string and byte blob, so attribute messages never collide with other uses
+/// of the key.
+const ATTRIBUTE_TAG: &[u8] = b"verisso-attribute";
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AttributeError {
+    /// Two attributes of a schema share the name.
+    Duplicate(String),
+    /// The schema has no attribute of that name.
+    Unknown(String),
+    WrongType(String, AttributeType),
+    /// More attributes than messages to put them in.
+    TooMany(usize, u32),
+    InvalidDate(String),
+    /// A revealed message that is no valid encoding of its attribute.
+    Undecodable(usize, String),
+    /// A claimed value for an attribute that was not revealed.
+    NotRevealed(String),
+    /// A revealed string or blob, which only decodes against a claimed value.
+    Unclaimed(String),
+    /// A claimed value that is not what was revealed.
+    Mismatch(String),
+    /// A schema listing attribute `name` at another index than its position.
+    WrongIndex(String, usize),
+    /// A link secret that the schema lets holders reveal.
+    RevealableSecret(String),
+}
+
+impl std::fmt::Display for AttributeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Duplicate(name) => write!(f, "Attribute {} appears twice", name),
+            Self::Unknown(name) => write!(f, "No attribute {} in the schema", name),
+            Self::WrongType(name, kind) => write!(f, "Attribute {} is a {:?}", name, kind),
+            Self::TooMany(attributes, messages) => write!(
+                f,
+                "{} messages cannot hold {} attributes",
+                messages, attributes
+            ),
+            Self::InvalidDate(date) => write!(f, "Invalid date {}, expected YYYY-MM-DD", date),
+            Self::Undecodable(index, reason) => write!(f, "Message {}: {}", index, reason),
+            Self::NotRevealed(name) => write!(f, "Attribute {} was not revealed", name),
+            Self::Unclaimed(name) => write!(f, "No value claimed for attribute {}", name),
+            Self::Mismatch(name) => {
+                write!(f, "Attribute {} does not match the revealed message", name)
+            }
+            Self::WrongIndex(name, index) => {
+                write!(f, "Attribute {} is listed at index {}", name, index)
+            }
+            Self::RevealableSecret(name) => {
+                write!(f, "Link secret {} must not be revealable", name)
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AttributeType {
+    String,
+    Bytes,
+    Integer,
+    Date,
+    Boolean,
+    /// The holder's secret that ties its credentials together. It has no value to claim: the
+    /// holder puts it in the message itself and the issuer never learns it.
+    #[serde(rename = "link_secret")]
+    LinkSecret,
+}
+
+/// A calendar date, serialized as YYYY-MM-DD.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(try_from = "String", into = "String")]
+pub struct Date {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+}
+
+impl Date {
+    pub fn new(year: u16, month: u8, day: u8) -> Result<Self, AttributeError> {
+        let leap =
+            year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400));
+        let days = match month {
+            2 if leap => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            1..=12 => 31,
+            _ => 0,
+        };
+        if !(1..=9999).contains(&year) || day == 0 || day > days {
+            return Err(AttributeError::InvalidDate(format!(
+                "{:04}-{:02}-{:02}",
+                year, month, day
+            )));
+        }
+        Ok(Self { year, month, day })
+    }
+
+    /// YYYYMMDD, which orders like the dates do.
+    fn to_number(self) -> u64 {
+        self.year as u64 * 10000 + self.month as u64 * 100 + self.day as u64
+    }
+
+    fn from_number(number: u64) -> Result<Self, AttributeError> {
+        let year = u16::try_from(number / 10000)
+            .map_err(|_| AttributeError::InvalidDate(number.to_string()))?;
+        Self::new(year, (number / 100 % 100) as u8, (number % 100) as u8)
+    }
+}
+
+impl TryFrom<String> for Date {
+    type Error = AttributeError;
+
+    fn try_from(date: String) -> Result<Self, Self::Error> {
+        let invalid = || AttributeError::InvalidDate(date.clone());
+        let parts = date.split('-').collect::<Vec<_>>();
+        let [year, month, day] = parts[..] else {
+            return Err(invalid());
+        };
+        if year.len() != 4 || month.len() != 2 || day.len() != 2 {
+            return Err(invalid());
+        }
+        Self::new(
+            year.parse().map_err(|_| invalid())?,
+            month.parse().map_err(|_| invalid())?,
+            day.parse().map_err(|_| invalid())?,
+        )
+    }
+}
+
+impl From<Date> for String {
+    fn from(date: Date) -> Self {
+        format!("{:04}-{:02}-{:02}", date.year, date.month, date.day)
+    }
+}
+
+impl std::fmt::Display for Date {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&String::from(*self))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum AttributeValue {
+    String(String),
+    Bytes(Vec<u8>),
+    Integer(i64),
+    Date(Date),
+    Boolean(bool),
+}
+
+impl AttributeValue {
+    pub fn kind(&self) -> AttributeType {
+        match self {
+            Self::String(_) => AttributeType::String,
+            Self::Bytes(_) => AttributeType::Bytes,
+            Self::Integer(_) => AttributeType::Integer,
+            Self::Date(_) => AttributeType::Date,
+            Self::Boolean(_) => AttributeType::Boolean,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attribute {
+    pub name: String,
+    pub kind: AttributeType,
+    /// Whether a presentation may reveal the claim, rather than only prove things about it.
+    pub revealable: bool,
+}
+
+/// An attribute as a stored schema lists it, along with its message index.
+#[derive(Serialize, Deserialize)]
+struct IndexedAttribute {
+    index: usize,
+    name: String,
+    #[serde(rename = "type")]
+    kind: AttributeType,
+    revealable: bool,
+}
+
+/// Which claim goes into which message, and how it is encoded. Message `i` holds attribute `i`,
+/// messages past the last attribute are zero.
+///
+/// Zero stands for an absent attribute, so no encoding is ever zero:
+/// - strings and byte blobs are hashed along with the attribute name,
+/// - integers are offset by `2^63 + 1`,
+/// - dates are YYYYMMDD,
+/// - booleans are 1 for false and 2 for true.
+///
+/// Integers and dates keep their order, so a range proof on a message proves the same range of
+/// the claim once the bounds are encoded with `encode_value` too.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "Vec<IndexedAttribute>", into = "Vec<IndexedAttribute>")]
+pub struct AttributeSchema {
+    attributes: Vec<Attribute>,
+}
+
+impl TryFrom<Vec<IndexedAttribute>> for AttributeSchema {
+    type Error = AttributeError;
+
+    fn try_from(attributes: Vec<IndexedAttribute>) -> Result<Self, Self::Error> {
+        let attributes = attributes
+            .into_iter()
+            .enumerate()
+            .map(|(position, attribute)| {
+                if attribute.index != position {
+                    return Err(AttributeError::WrongIndex(attribute.name, attribute.index));
+                }
+                Ok(Attribute {
+                    name: attribute.name,
+                    kind: attribute.kind,
+                    revealable: attribute.revealable,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Self::new(attributes)
+    }
+}
+
+impl From<AttributeSchema> for Vec<IndexedAttribute> {
+    fn from(schema: AttributeSchema) -> Self {
+        schema
+            .attributes
+            .into_iter()
+            .enumerate()
+            .map(|(index, attribute)| IndexedAttribute {
+                index,
+                name: attribute.name,
+                kind: attribute.kind,
+                revealable: attribute.revealable,
+            })
+            .collect()
+    }
+}
+
+impl AttributeSchema {
+    pub fn new(attributes: Vec<Attribute>) -> Result<Self, AttributeError> {
+        for (index, attribute) in attributes.iter().enumerate() {
+            if attributes[..index]
+                .iter()
+                .any(|other| other.name == attribute.name)
+            {
+                return Err(AttributeError::Duplicate(attribute.name.clone()));
+            }
+            if attribute.kind == AttributeType::LinkSecret && attribute.revealable {
+                return Err(AttributeError::RevealableSecret(attribute.name.clone()));
+            }
+        }
+        Ok(Self { attributes })
+    }
+
+    /// Shorthand for a schema of `(name, type)` pairs, all of them revealable.
+    pub fn of(attributes: &[(&str, AttributeType)]) -> Result<Self, AttributeError> {
+        Self::new(
+            attributes
+                .iter()
+                .map(|(name, kind)| Attribute {
+                    name: name.to_string(),
+                    kind: *kind,
+                    revealable: true,
+                })
+                .collect(),
+        )
+    }
+
+    pub fn attributes(&self) -> &[Attribute] {
+        &self.attributes
+    }
+
+    pub fn len(&self) -> usize {
+        self.attributes.len()
+    }
+
+    /// The message index of attribute `name`.
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.attributes
+            .iter()
+            .position(|attribute| attribute.name == name)
+    }
+
+    /// The message index of the schema's link secret, if it has one.
+    pub fn link_secret(&self) -> Option<usize> {
+        self.attributes
+            .iter()
+            .position(|attribute| attribute.kind == AttributeType::LinkSecret)
+    }
+
+    /// Encodes `value` as a message of attribute `name`.
+    pub fn encode_value(&self, name: &str, value: &AttributeValue) -> Result<Fr, AttributeError> {
+        let index = self
+            .index_of(name)
+            .ok_or_else(|| AttributeError::Unknown(name.to_string()))?;
+        let kind = self.attributes[index].kind;
+        if value.kind() != kind {
+            return Err(AttributeError::WrongType(name.to_string(), kind));
+        }
+        Ok(match value {
+            AttributeValue::String(value) => hash(name, kind, value.as_bytes()),
+            AttributeValue::Bytes(value) => hash(name, kind, value),
+            AttributeValue::Integer(value) => {
+                Fr::from((*value as i128 - i64::MIN as i128 + 1) as u128)
+            }
+            AttributeValue::Date(date) => Fr::from(date.to_number()),
+            AttributeValue::Boolean(value) => Fr::from(*value as u64 + 1),
+        })
+    }
+
+    /// The `message_count` messages of `claims`. Attributes without a claim are absent, and so
+    /// is the link secret until the holder fills it in.
+    pub fn encode(
+        &self,
+        claims: &BTreeMap<String, AttributeValue>,
+        message_count: u32,
+    ) -> Result<Vec<Fr>, AttributeError> {
+        if self.len() > message_count as usize {
+            return Err(AttributeError::TooMany(self.len(), message_count));
+        }
+        if let Some(name) = claims.keys().find(|name| self.index_of(name).is_none()) {
+            return Err(AttributeError::Unknown(name.clone()));
+        }
+        let mut messages = self
+            .attributes
+            .iter()
+            .map(|attribute| match claims.get(&attribute.name) {
+                Some(value) => self.encode_value(&attribute.name, value),
+                None => Ok(Fr::zero()),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        messages.resize(message_count as usize, Fr::zero());
+        Ok(messages)
+    }
+
+    /// Decodes the `revealed` messages of a proof into the claims they hold, `None` for absent
+    /// ones. Integers, dates and booleans decode on their own. Strings and blobs are hashed, so
+    /// the holder sends them along in `claimed` and they are checked against their messages.
+    pub fn decode(
+        &self,
+        revealed: &BTreeMap<usize, Fr>,
+        claimed: &BTreeMap<String, AttributeValue>,
+    ) -> Result<BTreeMap<String, Option<AttributeValue>>, AttributeError> {
+        for name in claimed.keys() {
+            let index = self
+                .index_of(name)
+                .ok_or_else(|| AttributeError::Unknown(name.clone()))?;
+            if !revealed.contains_key(&index) {
+                return Err(AttributeError::NotRevealed(name.clone()));
+            }
+        }
+
+        let mut claims = BTreeMap::new();
+        for (index, message) in revealed {
+            let Some(attribute) = self.attributes.get(*index) else {
+                if !message.is_zero() {
+                    return Err(AttributeError::Undecodable(
+                        *index,
+                        "Past the last attribute, but not zero".into(),
+                    ));
+                }
+                continue;
+            };
+            let name = &attribute.name;
+            let claim = claimed.get(name);
+            if message.is_zero() {
+                if claim.is_some() {
+                    return Err(AttributeError::Mismatch(name.clone()));
+                }
+                claims.insert(name.clone(), None);
+                continue;
+            }
+
+            let value = match (attribute.kind, claim) {
+                (AttributeType::String | AttributeType::Bytes, None) => {
+                    return Err(AttributeError::Unclaimed(name.clone()))
+                }
+                (AttributeType::String | AttributeType::Bytes, Some(claim)) => claim.clone(),
+                (AttributeType::Integer, _) => {
+                    let value = to_u128(message)
+                        .and_then(|n| i64::try_from(n as i128 - 1 + i64::MIN as i128).ok())
+                        .ok_or_else(|| {
+                            AttributeError::Undecodable(*index, "Not an integer".into())
+                        })?;
+                    AttributeValue::Integer(value)
+                }
+                (AttributeType::Date, _) => {
+                    let date = to_u128(message)
+                        .and_then(|n| u64::try_from(n).ok())
+                        .ok_or(AttributeError::Undecodable(*index, "Not a date".into()))
+                        .and_then(Date::from_number)
+                        .map_err(|e| AttributeError::Undecodable(*index, e.to_string()))?;
+                    AttributeValue::Date(date)
+                }
+                (AttributeType::Boolean, _) => match to_u128(message) {
+                    Some(1) => AttributeValue::Boolean(false),
+                    Some(2) => AttributeValue::Boolean(true),
+                    _ => return Err(AttributeError::Undecodable(*index, "Not a boolean".into())),
+                },
+                (AttributeType::LinkSecret, _) => {
+                    return Err(AttributeError::Undecodable(
+                        *index,
+                        "A link secret is never revealed".into(),
+                    ))
+                }
+            };
+            // A claim sent along must be what was revealed, whatever the type
+            if claim.is_some_and(|claim| claim != &value)
+                || self.encode_value(name, &value)? != *message
+            {
+                return Err(AttributeError::Mismatch(name.clone()));
+            }
+            claims.insert(name.clone(), Some(value));
+        }
+        Ok(claims)
+    }
+}
+
+fn hash(name: &str, kind: AttributeType, value: &[u8]) -> Fr {
+    let digest = Blake2b512::new()
+        .chain_update(ATTRIBUTE_TAG)
+        .chain_update((name.len() as u64).to_be_bytes())
+        .chain_update(name.as_bytes())
+        .chain_update([kind as u8])
+        .chain_update((value.len() as u64).to_be_bytes())
+        .chain_update(value)
+        .finalize();
+    Fr::from_le_bytes_mod_order(&digest)
+}
+
+/// The message as a number, if it is below `2^128`.
+fn to_u128(message: &Fr) -> Option<u128> {
+    let bytes = message.into_bigint().to_bytes_le();
+    if bytes[16..].iter().any(|b| *b != 0) {
+        return None;
+    }
+    let mut low = [0u8; 16];
+    low.copy_from_slice(&bytes[..16]);
+    Some(u128::from_le_bytes(low))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema() -> AttributeSchema {
+        AttributeSchema::of(&[
+            ("name", AttributeType::String),
+            ("age", AttributeType::Integer),
+            ("born", AttributeType::Date),
+            ("adult", AttributeType::Boolean),
+        ])
+        .unwrap()
+    }
+
+    /// Encodes `value` as the claim `name` and decodes it back, with `claimed` sent along.
+    fn round_trip(
+        name: &str,
+        value: AttributeValue,
+        claimed: bool,
+    ) -> Result<Option<AttributeValue>, AttributeError> {
+        let schema = schema();
+        let claims = BTreeMap::from([(name.to_string(), value)]);
+        let messages = schema.encode(&claims, 4)?;
+        let index = schema.index_of(name).unwrap();
+        let revealed = BTreeMap::from([(index, messages[index])]);
+        let claimed = if claimed { claims } else { BTreeMap::new() };
+        let mut decoded = schema.decode(&revealed, &claimed)?;
+        Ok(decoded.remove(name).unwrap())
+    }
+
+    fn decode(index: usize, message: Fr) -> Result<Option<AttributeValue>, AttributeError> {
+        let name = schema().attributes()[index].name.clone();
+        let revealed = BTreeMap::from([(index, message)]);
+        Ok(schema()
+            .decode(&revealed, &BTreeMap::new())?
+            .remove(&name)
+            .unwrap())
+    }
+
+    #[test]
+    fn strings_are_hashed_and_checked_against_the_claim() {
+        let value = AttributeValue::String("alice".into());
+        assert_eq!(
+            round_trip("name", value.clone(), true),
+            Ok(Some(value.clone()))
+        );
+        assert_eq!(
+            round_trip("name", value.clone(), false),
+            Err(AttributeError::Unclaimed("name".into()))
+        );
+
+        let schema = schema();
+        let message = schema.encode_value("name", &value).unwrap();
+        assert_eq!(message, hash("name", AttributeType::String, b"alice"));
+        let other = BTreeMap::from([("name".to_string(), AttributeValue::String("bob".into()))]);
+        assert_eq!(
+            schema.decode(&BTreeMap::from([(0, message)]), &other),
+            Err(AttributeError::Mismatch("name".into()))
+        );
+    }
+
+    #[test]
+    fn integers_are_offset_by_2_pow_63_plus_1() {
+        let offset = |n: i128| Fr::from((n - i64::MIN as i128 + 1) as u128);
+        let schema = schema();
+        for n in [i64::MIN, -1, 0, 1, i64::MAX] {
+            let value = AttributeValue::Integer(n);
+            assert_eq!(schema.encode_value("age", &value), Ok(offset(n as i128)));
+            assert_eq!(
+                round_trip("age", value.clone(), false),
+                Ok(Some(value.clone()))
+            );
+            assert_eq!(round_trip("age", value.clone(), true), Ok(Some(value)));
+        }
+        assert_eq!(
+            schema.encode_value("age", &AttributeValue::Integer(i64::MIN)),
+            Ok(Fr::from(1u64))
+        );
+        assert_eq!(
+            schema.encode_value("age", &AttributeValue::Integer(i64::MAX)),
+            Ok(Fr::from(1u128 << 64))
+        );
+        assert!(matches!(
+            decode(1, offset(i64::MAX as i128 + 1)),
+            Err(AttributeError::Undecodable(1, _))
+        ));
+        assert!(matches!(
+            decode(1, -Fr::from(1u64)),
+            Err(AttributeError::Undecodable(1, _))
+        ));
+    }
+
+    #[test]
+    fn dates_are_yyyymmdd() {
+        let date = Date::new(2024, 2, 29).unwrap();
+        let value = AttributeValue::Date(date);
+        assert_eq!(
+            schema().encode_value("born", &value),
+            Ok(Fr::from(20240229u64))
+        );
+        assert_eq!(round_trip("born", value.clone(), false), Ok(Some(value)));
+        assert_eq!(Date::try_from("2024-02-29".to_string()), Ok(date));
+
+        for (year, month, day) in [(2023, 2, 29), (1900, 2, 29), (2024, 13, 1), (2024, 4, 31)] {
+            assert!(Date::new(year, month, day).is_err());
+        }
+        assert!(Date::new(0, 1, 1).is_err());
+        assert!(Date::new(2024, 1, 0).is_err());
+        for date in ["2024-1-01", "2024-01-01-01", "24-01-01", "2024-0a-01", ""] {
+            assert!(Date::try_from(date.to_string()).is_err());
+        }
+        for message in [20230229u64, 20241301, 20240100, 99999999999] {
+            assert!(matches!(
+                decode(2, Fr::from(message)),
+                Err(AttributeError::Undecodable(2, _))
+            ));
+        }
+    }
+
+    #[test]
+    fn booleans_are_1_and_2() {
+        let schema = schema();
+        for (value, message) in [(false, 1u64), (true, 2)] {
+            let value = AttributeValue::Boolean(value);
+            assert_eq!(schema.encode_value("adult", &value), Ok(Fr::from(message)));
+            assert_eq!(round_trip("adult", value.clone(), false), Ok(Some(value)));
+        }
+        assert!(matches!(
+            decode(3, Fr::from(3u64)),
+            Err(AttributeError::Undecodable(3, _))
+        ));
+    }
+
+    #[test]
+    fn zero_is_an_absent_claim() {
+        for index in 0..4 {
+            assert_eq!(decode(index, Fr::zero()), Ok(None));
+        }
+        assert_eq!(
+            schema().encode_value("age", &AttributeValue::Boolean(true)),
+            Err(AttributeError::WrongType(
+                "age".into(),
+                AttributeType::Integer
+            ))
+        );
+    }
+}
@@ -0,0 +1,422 @@
+use ark_bls12_381::Fr;
+use ark_serialize::CanonicalSerialize;
+use base64::{engine::general_purpose, Engine as _};
+use blake2::{Blake2b512, Digest};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::constant::SESSION_TIMEOUT_SECS;
+use crate::envelope::SigningKeys;
+use crate::helper::message::{ParticipantId, SessionId, NO_SESSION};
+
+const RECORD_TAG: &[u8] = b"verisso-audit-record";
+const MESSAGES_TAG: &[u8] = b"verisso-audit-messages";
+
+#[derive(Debug)]
+pub enum AuditError {
+    Io(std::io::Error),
+    /// A line that is not a record.
+    Format(usize, String),
+    /// The record at the index does not follow from the one before it.
+    Broken(u64, String),
+    /// The request that opened the session never arrived here, or the session was aborted.
+    NoRequest(SessionId),
+    /// The log was given no key to sign its records with.
+    NoKey,
+}
+
+impl std::fmt::Display for AuditError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Format(line, e) => write!(f, "Invalid record on line {}: {}", line, e),
+            Self::Broken(index, reason) => write!(f, "Record {}: {}", index, reason),
+            Self::NoRequest(session) => write!(f, "No request from the AS for session {}", session),
+            Self::NoKey => write!(f, "No key to sign records with"),
+        }
+    }
+}
+
+impl From<std::io::Error> for AuditError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// One completed session, as the signer that took part in it saw it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuditRecord {
+    /// Position in the log, from 0.
+    pub index: u64,
+    pub signer: ParticipantId,
+    pub session: SessionId,
+    pub participants: Vec<ParticipantId>,
+    /// Commitment to the messages signed in the session, see `messages_commitment`. Only
+    /// signing sessions have one.
+    pub messages: Option<String>,
+    pub outcome: String,
+    /// UNIX seconds.
+    pub timestamp: u64,
+    /// The envelope of the request that opened the session, as the AS signed it.
+    pub request: String,
+    /// Hash of the record before, empty for the first one.
+    pub prev_hash: String,
+    pub hash: String,
+    /// Signature of `hash` by the signer's envelope key, so only the signer can rewrite the
+    /// chain.
+    pub signature: String,
+}
+
+impl AuditRecord {
+    /// Hash over every field but `hash` and `signature`, so editing any of them breaks the chain from
+    /// this record on.
+    pub fn digest(&self) -> String {
+        let mut hasher = Blake2b512::new();
+        hasher.update(RECORD_TAG);
+        hasher.update(self.index.to_be_bytes());
+        hasher.update(self.signer.to_be_bytes());
+        hasher.update(self.session.to_be_bytes());
+        hasher.update((self.participants.len() as u64).to_be_bytes());
+        for participant in &self.participants {
+            hasher.update(participant.to_be_bytes());
+        }
+        hasher.update([self.messages.is_some() as u8]);
+        for field in [
+            self.messages.as_deref().unwrap_or_default(),
+            &self.outcome,
+            &self.request,
+            &self.prev_hash,
+        ] {
+            hasher.update((field.len() as u64).to_be_bytes());
+            hasher.update(field.as_bytes());
+        }
+        hasher.update(self.timestamp.to_be_bytes());
+        general_purpose::STANDARD.encode(hasher.finalize())
+    }
+}
+
+/// Commitment to the messages of a signature, the same on every signer that signed them.
+pub fn messages_commitment(messages: &[Fr]) -> String {
+    let mut bytes = MESSAGES_TAG.to_vec();
+    for message in messages {
+        message.serialize_compressed(&mut bytes).unwrap();
+    }
+    general_purpose::STANDARD.encode(Blake2b512::digest(&bytes))
+}
+
+/// Reads every record of the log at `path`, without checking them.
+pub fn read_log(path: impl AsRef<Path>) -> Result<Vec<AuditRecord>, AuditError> {
+    std::fs::read_to_string(path)?
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(number, line)| {
+            serde_json::from_str(line).map_err(|e| AuditError::Format(number + 1, e.to_string()))
+        })
+        .collect()
+}
+
+/// Checks that `records` form one unbroken chain of a single signer, starting at the first
+/// record of its log. Only catches a log cut short at the end by comparing it with the logs
+/// of other signers.
+pub fn check_chain(records: &[AuditRecord]) -> Result<(), AuditError> {
+    let mut prev_hash = String::new();
+    for (index, record) in records.iter().enumerate() {
+        let index = index as u64;
+        if record.index != index {
+            return Err(AuditError::Broken(
+                index,
+                format!(
+                    "Numbered {}, records are missing or reordered",
+                    record.index
+                ),
+            ));
+        }
+        if record.signer != records[0].signer {
+            return Err(AuditError::Broken(
+                index,
+                format!(
+                    "Written by signer {}, the log is of signer {}",
+                    record.signer, records[0].signer
+                ),
+            ));
+        }
+        if record.prev_hash != prev_hash {
+            return Err(AuditError::Broken(
+                index,
+                "Does not follow the record before it".into(),
+            ));
+        }
+        if record.digest() != record.hash {
+            return Err(AuditError::Broken(
+                index,
+                "Edited after it was written".into(),
+            ));
+        }
+        prev_hash = record.hash.clone();
+    }
+    Ok(())
+}
+
+/// Checks that the signer of each record signed it, with its key in `keys`.
+pub fn check_signatures(records: &[AuditRecord], keys: &SigningKeys) -> Result<(), AuditError> {
+    for record in records {
+        keys.verify_record(record.signer, &record.hash, &record.signature)
+            .map_err(|e| AuditError::Broken(record.index, e.to_string()))?;
+    }
+    Ok(())
+}
+
+/// A signer's append-only, hash-chained record of the sessions it completed.
+pub struct AuditLog {
+    path: PathBuf,
+    signer: ParticipantId,
+    next_index: u64,
+    last_hash: String,
+    /// The requests that opened the sessions still running here, with when they arrived.
+    requests: BTreeMap<SessionId, (String, Instant)>,
+    keys: Option<Arc<SigningKeys>>,
+}
+
+impl AuditLog {
+    pub fn new(path: impl AsRef<Path>, signer: ParticipantId) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            signer,
+            next_index: 0,
+            last_hash: String::new(),
+            requests: BTreeMap::new(),
+            keys: None,
+        }
+    }
+
+    /// Signs the records from now on with the signer's envelope key, and checks the ones
+    /// `load` reads with it.
+    pub fn sign_with(&mut self, keys: Arc<SigningKeys>) {
+        self.keys = Some(keys);
+    }
+
+    /// Checks the records, and their signatures, an earlier run wrote and continues the chain
+    /// after them. Returns how many there are.
+    pub fn load(&mut self) -> Result<u64, AuditError> {
+        if !self.path.exists() {
+            return Ok(0);
+        }
+        let records = read_log(&self.path)?;
+        check_chain(&records)?;
+        check_signatures(&records, self.keys.as_ref().ok_or(AuditError::NoKey)?)?;
+        if let Some(last) = records.last() {
+            if last.signer != self.signer {
+                return Err(AuditError::Broken(
+                    last.index,
+                    format!("Log belongs to signer {}", last.signer),
+                ));
+            }
+            self.next_index = last.index + 1;
+            self.last_hash = last.hash.clone();
+        }
+        Ok(self.next_index)
+    }
+
+    /// Keeps the `envelope` the AS opened `session` with, for the record of the session.
+    pub fn requested(&mut self, session: SessionId, envelope: &str) {
+        if session == NO_SESSION {
+            return;
+        }
+        let timeout = Duration::from_secs(SESSION_TIMEOUT_SECS);
+        self.requests
+            .retain(|_, (_, arrived)| arrived.elapsed() < timeout);
+        self.requests
+            .insert(session, (envelope.to_string(), Instant::now()));
+    }
+
+    /// Forgets the request of a session that ends without a record.
+    pub fn discard(&mut self, session: SessionId) {
+        self.requests.remove(&session);
+    }
+
+    /// Appends the record of a completed session and syncs it to disk.
+    pub fn append(
+        &mut self,
+        session: SessionId,
+        participants: &BTreeSet<ParticipantId>,
+        messages: Option<String>,
+        outcome: String,
+        timestamp: u64,
+    ) -> Result<(), AuditError> {
+        let keys = self.keys.as_ref().ok_or(AuditError::NoKey)?;
+        let (request, _) = self
+            .requests
+            .remove(&session)
+            .ok_or(AuditError::NoRequest(session))?;
+        let mut record = AuditRecord {
+            index: self.next_index,
+            signer: self.signer,
+            session,
+            participants: participants.iter().copied().collect(),
+            messages,
+            outcome,
+            timestamp,
+            request,
+            prev_hash: self.last_hash.clone(),
+            hash: String::new(),
+            signature: String::new(),
+        };
+        record.hash = record.digest();
+        record.signature = keys.sign_record(&record.hash)?;
+
+        let mut line = serde_json::to_vec(&record).map_err(std::io::Error::from)?;
+        line.push(b'\n');
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(&line)?;
+        file.sync_data()?;
+
+        self.next_index += 1;
+        self.last_hash = record.hash;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ark_std::rand::{rngs::StdRng, SeedableRng};
+
+    /// Keys of signers 1 and 2 of a deployment, written to a fresh directory for `test`.
+    fn keys(test: &str) -> (PathBuf, Arc<SigningKeys>, Arc<SigningKeys>) {
+        let dir =
+            std::env::temp_dir().join(format!("verisso-audit-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        SigningKeys::generate(&mut StdRng::seed_from_u64(0), &dir, 3).unwrap();
+        let load = |id| Arc::new(SigningKeys::load(&dir, id, 3, "test").unwrap());
+        (dir.clone(), load(1), load(2))
+    }
+
+    /// A log of signer 1 with three records.
+    fn log(dir: &Path, keys: &Arc<SigningKeys>) -> AuditLog {
+        let mut log = AuditLog::new(dir.join("node1.log"), 1);
+        log.sign_with(Arc::clone(keys));
+        for session in 1..=3 {
+            log.requested(session, "request");
+            log.append(
+                session,
+                &BTreeSet::from([1, 2]),
+                None,
+                "Done".into(),
+                session,
+            )
+            .unwrap();
+        }
+        log
+    }
+
+    #[test]
+    fn keeps_a_signed_chain() {
+        let (dir, keys, _) = keys("chain");
+        let log = log(&dir, &keys);
+        let records = read_log(&log.path).unwrap();
+        assert_eq!(records.len(), 3);
+        check_chain(&records).unwrap();
+        check_signatures(&records, &keys).unwrap();
+
+        let mut reloaded = AuditLog::new(&log.path, 1);
+        reloaded.sign_with(keys);
+        assert_eq!(reloaded.load().unwrap(), 3);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_tampered_chain() {
+        let (dir, keys, _) = keys("tampered");
+        let log = log(&dir, &keys);
+        let records = read_log(&log.path).unwrap();
+
+        let mut edited = records.clone();
+        edited[1].outcome = "Refused".into();
+        assert!(matches!(
+            check_chain(&edited),
+            Err(AuditError::Broken(1, _))
+        ));
+
+        let mut removed = records.clone();
+        removed.remove(1);
+        assert!(matches!(
+            check_chain(&removed),
+            Err(AuditError::Broken(1, _))
+        ));
+
+        let mut reordered = records.clone();
+        reordered.swap(1, 2);
+        assert!(matches!(
+            check_chain(&reordered),
+            Err(AuditError::Broken(1, _))
+        ));
+
+        let mut foreign = records.clone();
+        foreign[2].signer = 2;
+        foreign[2].hash = foreign[2].digest();
+        assert!(matches!(
+            check_chain(&foreign),
+            Err(AuditError::Broken(2, _))
+        ));
+
+        // Rewritten from the edit on with valid hashes, the chain still holds
+        let mut rewritten = records;
+        rewritten[1].outcome = "Refused".into();
+        for index in 1..rewritten.len() {
+            rewritten[index].prev_hash = rewritten[index - 1].hash.clone();
+            rewritten[index].hash = rewritten[index].digest();
+        }
+        check_chain(&rewritten).unwrap();
+        assert!(matches!(
+            check_signatures(&rewritten, &keys),
+            Err(AuditError::Broken(1, _))
+        ));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_records_signed_by_another_node() {
+        let (dir, keys, other) = keys("other");
+        let log = log(&dir, &keys);
+        let mut records = read_log(&log.path).unwrap();
+        records[0].signature = other.sign_record(&records[0].hash).unwrap();
+        assert!(matches!(
+            check_signatures(&records, &keys),
+            Err(AuditError::Broken(0, _))
+        ));
+
+        // A log resumes only if every record of it verifies
+        let mut file = std::fs::File::create(&log.path).unwrap();
+        for record in &records {
+            writeln!(file, "{}", serde_json::to_string(record).unwrap()).unwrap();
+        }
+        let mut reloaded = AuditLog::new(&log.path, 1);
+        reloaded.sign_with(keys);
+        assert!(matches!(reloaded.load(), Err(AuditError::Broken(0, _))));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_to_append_unsigned() {
+        let (dir, _, _) = keys("unsigned");
+        let mut log = AuditLog::new(dir.join("node1.log"), 1);
+        log.requested(1, "request");
+        assert!(matches!(
+            log.append(1, &BTreeSet::from([1]), None, "Done".into(), 1),
+            Err(AuditError::NoKey)
+        ));
+        assert!(!log.path.exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
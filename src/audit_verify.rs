@@ -0,0 +1,207 @@
+mod helper {
+    pub mod message;
+}
+mod attributes;
+mod audit;
+mod constant;
+mod envelope;
+mod token;
+mod transport;
+
+use audit::{check_chain, check_signatures, messages_commitment, read_log, AuditRecord};
+use envelope::{Envelope, SigningKeys};
+use helper::message::{Message, ParticipantId, SessionId};
+use std::collections::{BTreeMap, BTreeSet};
+use token::TokenAttributes;
+
+/// Checks that `record` is what the request in it asked for, and that the AS signed that
+/// request for this signer and session.
+fn check_record(
+    record: &AuditRecord,
+    keys: &SigningKeys,
+    message_count: u32,
+) -> Result<(), String> {
+    let envelope: Envelope =
+        serde_json::from_str(&record.request).map_err(|e| format!("Invalid request: {}", e))?;
+    if envelope.sender != 0 {
+        return Err(format!("Request comes from node {}", envelope.sender));
+    }
+    if envelope.receiver != record.signer {
+        return Err(format!("Request was sent to node {}", envelope.receiver));
+    }
+    if envelope.session != record.session {
+        return Err(format!("Request is of session {}", envelope.session));
+    }
+    let payload = keys.verify(envelope).map_err(|e| e.to_string())?;
+    if !record.participants.contains(&record.signer) {
+        return Err("The signer is not among the participants".into());
+    }
+
+    let participants = record.participants.iter().copied().collect::<BTreeSet<_>>();
+    let messages = match payload.msg {
+        Message::SignRequest {
+            quorum, attributes, ..
+        } => {
+            if participants != quorum.into_iter().collect() {
+                return Err("Participants are not the quorum of the request".into());
+            }
+            let attributes: TokenAttributes = serde_json::from_str(&attributes)
+                .map_err(|e| format!("Invalid token attributes: {}", e))?;
+            Some(messages_commitment(&attributes.to_messages(message_count)?))
+        }
+        Message::Round1Request { quorum, .. } => {
+            if participants != quorum.into_iter().collect() {
+                return Err("Participants are not the quorum of the request".into());
+            }
+            None
+        }
+        Message::ReshareRequest {
+            dealers, committee, ..
+        } => {
+            if participants != dealers.into_iter().chain(committee).collect() {
+                return Err("Participants are not the dealers and committee of the request".into());
+            }
+            None
+        }
+        Message::KeygenRequest | Message::RefreshRequest { .. } => None,
+        msg => return Err(format!("{} opens no session", msg.round())),
+    };
+    if record.messages != messages {
+        return Err("Messages do not match the request".into());
+    }
+    Ok(())
+}
+
+/// Compares what the signers of every session recorded about it. Signers may disagree on the
+/// outcome, one may refuse what others sign, but not on what they were asked. A signer that
+/// took part in a session but has no record of it is reported as a gap: it may have failed
+/// before completing it, or its log was cut short.
+fn cross_check(
+    logs: &BTreeMap<ParticipantId, Vec<AuditRecord>>,
+    problems: &mut Vec<String>,
+    gaps: &mut Vec<String>,
+) {
+    let mut sessions: BTreeMap<SessionId, Vec<&AuditRecord>> = BTreeMap::new();
+    for record in logs.values().flatten() {
+        sessions.entry(record.session).or_default().push(record);
+    }
+
+    for (session, records) in sessions {
+        let first = records[0];
+        let request_body = |record: &AuditRecord| {
+            serde_json::from_str::<Envelope>(&record.request)
+                .map(|envelope| envelope.body)
+                .ok()
+        };
+        for record in &records[1..] {
+            if record.participants != first.participants {
+                problems.push(format!(
+                    "Session {}: signers {} and {} name different participants",
+                    session, first.signer, record.signer
+                ));
+            }
+            if record.messages != first.messages || request_body(record) != request_body(first) {
+                problems.push(format!(
+                    "Session {}: signers {} and {} were asked different things",
+                    session, first.signer, record.signer
+                ));
+            }
+        }
+
+        let recorded_by = records
+            .iter()
+            .map(|record| record.signer)
+            .collect::<BTreeSet<_>>();
+        let participants = records
+            .iter()
+            .flat_map(|record| record.participants.iter().copied())
+            .collect::<BTreeSet<_>>();
+        for participant in participants.difference(&recorded_by) {
+            let Some(log) = logs.get(participant) else {
+                continue;
+            };
+            // Session IDs only grow, also across restarts of the AS
+            if log.iter().all(|record| record.session < session) {
+                gaps.push(format!(
+                    "Log of signer {} ends before session {}, which signers {:?} completed with it, it may be cut short",
+                    participant, session, recorded_by
+                ));
+            } else {
+                gaps.push(format!(
+                    "Signer {} has no record of session {}, which signers {:?} completed with it",
+                    participant, session, recorded_by
+                ));
+            }
+        }
+    }
+}
+
+/// Verifies the audit logs given as arguments: each must be one unbroken chain of records its
+/// signer signed, holding requests the AS signed, and the signers must agree on the sessions
+/// they shared. The keys of the AS and the signers are read from NODE_KEYS_DIR, and
+/// MESSAGE_COUNT must match the deployment.
+fn main() {
+    let paths = std::env::args().skip(1).collect::<Vec<_>>();
+    if paths.is_empty() {
+        eprintln!("Usage: audit_verify <audit log>...");
+        std::process::exit(1);
+    }
+    let dir = std::env::var("NODE_KEYS_DIR").unwrap_or_else(|_| "./node_keys".to_string());
+    let deployment_id = std::env::var("DEPLOYMENT_ID").unwrap_or_else(|_| "local".to_string());
+    let message_count: u32 = std::env::var("MESSAGE_COUNT").map_or(5, |s| {
+        s.parse::<u32>().unwrap_or_else(|_| {
+            eprintln!("MESSAGE_COUNT must be a number.");
+            std::process::exit(1);
+        })
+    });
+
+    let mut problems = vec![];
+    let mut gaps = vec![];
+    let mut read = vec![];
+    for path in &paths {
+        match read_log(path) {
+            Ok(records) if records.is_empty() => println!("{}: empty", path),
+            Ok(records) => read.push((path, records)),
+            Err(e) => problems.push(format!("{}: {}", path, e)),
+        }
+    }
+    let signers = read.iter().map(|(_, records)| records[0].signer);
+    let keys = SigningKeys::verifier(&dir, std::iter::once(0).chain(signers), &deployment_id)
+        .unwrap_or_else(|e| {
+            eprintln!("Failed to load the signing keys from {}: {}", dir, e);
+            std::process::exit(1);
+        });
+
+    let mut logs: BTreeMap<ParticipantId, Vec<AuditRecord>> = BTreeMap::new();
+    for (path, records) in read {
+        let signer = records[0].signer;
+        if let Err(e) = check_chain(&records) {
+            problems.push(format!("{}: {}", path, e));
+        }
+        if let Err(e) = check_signatures(&records, &keys) {
+            problems.push(format!("{}: {}", path, e));
+        }
+        for record in &records {
+            if let Err(e) = check_record(record, &keys, message_count) {
+                problems.push(format!("{}: record {}: {}", path, record.index, e));
+            }
+        }
+        println!("{}: {} records of signer {}", path, records.len(), signer);
+        if logs.insert(signer, records).is_some() {
+            problems.push(format!("More than one log of signer {}", signer));
+        }
+    }
+    cross_check(&logs, &mut problems, &mut gaps);
+
+    for gap in &gaps {
+        println!("Gap: {}", gap);
+    }
+    for problem in &problems {
+        println!("Problem: {}", problem);
+    }
+    if !problems.is_empty() {
+        println!("{} problems, {} gaps", problems.len(), gaps.len());
+        std::process::exit(1);
+    }
+    println!("Audit logs verified, {} gaps", gaps.len());
+}
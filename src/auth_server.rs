@@ -2,25 +2,37 @@ use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
-use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::net::TcpListener;
 
 mod helper {
     pub mod encoder;
     pub mod message;
 }
+mod attributes;
+mod audit;
 mod auth_service;
 mod config;
 mod constant;
+mod envelope;
 mod exp_utils;
+mod keystore;
 mod ot;
+mod policy;
+mod presignature;
+mod rng;
+mod rounds;
+mod schema;
 mod signer;
+mod token;
+mod transport;
 
 use config::Config;
+use envelope::{Envelope, EnvelopeWriter, SigningKeys};
 use helper::encoder::Encoder;
-use helper::message::{Message, Payload};
+use helper::message::{Message, Payload, CLIENT};
+use transport::{NodeKeys, Peer};
 
-use crate::auth_service::AuthenticationService;
+use crate::auth_service::{AuthenticationService, Client, StoredKey};
 
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 struct Round1CommitmentMsg {
@@ -29,8 +41,21 @@ struct Round1CommitmentMsg {
     commitment_zero_share: Vec<(u16, Vec<u8>)>,
 }
 
+/// Whether `peer` may send `payload`. Anyone may log in, signers only speak for themselves, and
+/// refresh and reshare are left to the operator, who holds the key of the AS.
+fn authorized(peer: Peer, payload: &Payload) -> bool {
+    match (&payload.msg, peer) {
+        (Message::Start { .. }, _) => true,
+        (Message::RefreshRequest { .. } | Message::Reshare { .. }, Peer::Node(0)) => true,
+        (_, Peer::Node(id)) => id != 0 && id == payload.sender,
+        (_, Peer::Client) => false,
+    }
+}
+
 async fn handle_listener(
     listener: TcpListener,
+    keys: Arc<NodeKeys>,
+    signing_keys: Arc<SigningKeys>,
     auth_service: Arc<Mutex<AuthenticationService>>,
 ) -> tokio::io::Result<()> {
     loop {
@@ -38,9 +63,27 @@ async fn handle_listener(
         println!("New connection from {}", addr);
 
         let auth_service = Arc::clone(&auth_service);
+        let keys = Arc::clone(&keys);
+        let signing_keys = Arc::clone(&signing_keys);
 
         tokio::task::spawn(async move {
-            let mut reader = BufReader::new(socket);
+            let (peer, mut reader, writer) = match keys.accept(socket).await {
+                Ok(connection) => connection,
+                Err(e) => {
+                    eprintln!("Handshake with {} failed: {}", addr, e);
+                    return;
+                }
+            };
+            // Clients get their login result back on the same connection
+            let receiver = match peer {
+                Peer::Node(id) => id,
+                Peer::Client => CLIENT,
+            };
+            let client: Client = Arc::new(Mutex::new(EnvelopeWriter::new(
+                writer,
+                receiver,
+                Arc::clone(&signing_keys),
+            )));
             let mut line = String::new();
 
             // println!("Buffer: {:?}", buffer);
@@ -58,15 +101,30 @@ async fn handle_listener(
                         if text.is_empty() {
                             continue;
                         }
-                        let payload: Payload = match serde_json::from_str(text) {
+                        let envelope: Envelope = match serde_json::from_str(text) {
+                            Ok(envelope) => envelope,
+                            Err(e) => {
+                                eprintln!("Failed to deserialize envelope from {}: {}", addr, e);
+                                continue;
+                            }
+                        };
+                        let payload = match signing_keys.open(envelope) {
                             Ok(payload) => payload,
                             Err(e) => {
-                                eprintln!("Failed to deserialize payload from {}: {}", addr, e);
+                                eprintln!("Rejected envelope from {}: {}", addr, e);
                                 continue;
                             }
                         };
+                        if !authorized(peer, &payload) {
+                            eprintln!(
+                                "Dropped message claiming sender {} from {:?} at {}",
+                                payload.sender, peer, addr
+                            );
+                            continue;
+                        }
 
-                        if let Err(e) = handle_payload(payload, &auth_service).await {
+                        if let Err(e) = handle_payload(payload, text, &auth_service, &client).await
+                        {
                             eprintln!("Failed to handle payload from {}: {}", addr, e);
                             continue;
                         };
@@ -81,59 +139,200 @@ async fn handle_listener(
     }
 }
 
-// made async so we can await the Tokio mutex
+// made async so we can await the Tokio mutex. `envelope` is the payload as it arrived, signed
+// by its sender.
 async fn handle_payload(
     payload: Payload,
+    envelope: &str,
     auth_service: &Arc<Mutex<AuthenticationService>>,
+    client: &Client,
 ) -> Result<(), String> {
     // Process the payload as needed
     match payload.msg {
-        Message::Start => {
+        Message::Start {
+            subject,
+            audience,
+            credential_proof,
+        } => {
             // println!("Received Start from {}", payload.sender);
             let mut auth_service = auth_service.lock().await;
-            auth_service.send_round1_request().await;
+            auth_service
+                .queue_login(Some(client.clone()), subject, audience, credential_proof)
+                .await;
+            Ok(())
+        }
+        Message::RefreshRequest { .. } => {
+            // On-demand refresh from an operator, the AS picks the next epoch itself
+            let mut auth_service = auth_service.lock().await;
+            auth_service.send_refresh_request().await;
+            Ok(())
+        }
+        Message::Heartbeat => {
+            let mut auth_service = auth_service.lock().await;
+            auth_service.mark_alive(payload.sender);
+            // A login queued while too few signers were live can go ahead now
+            auth_service.serve_logins().await;
+            Ok(())
+        }
+        Message::Reshare {
+            committee,
+            threshold,
+        } => {
+            let mut auth_service = auth_service.lock().await;
+            auth_service
+                .send_reshare_request(committee.into_iter().collect(), threshold)
+                .await;
+            Ok(())
+        }
+        Message::ReshareResponse {
+            epoch,
+            public_key_share,
+        } => {
+            let mut auth_service = auth_service.lock().await;
+
+            let public_key_share = match public_key_share {
+                Some(share) => Some(
+                    Encoder::decode_public_key(share.as_str())
+                        .map_err(|e| format!("Decode error: {}", e))?,
+                ),
+                None => None,
+            };
+
+            auth_service
+                .process_reshare_response(payload.session, payload.sender, epoch, public_key_share)
+                .await;
             Ok(())
         }
-        Message::Round1Response {
-            phase1,
-            commitments,
-            commitments_map,
+        Message::RefreshResponse {
+            epoch,
+            public_key_share,
         } => {
             let mut auth_service = auth_service.lock().await;
 
-            let phase1 = Encoder::decode_phase1(phase1.as_str())
+            let public_key_share = Encoder::decode_public_key(public_key_share.as_str())
                 .map_err(|e| format!("Decode error: {}", e))?;
-            let commitments = Encoder::decode_commitments(commitments.as_str())
+
+            auth_service
+                .process_refresh_response(payload.session, payload.sender, epoch, public_key_share)
+                .await;
+            Ok(())
+        }
+        Message::KeyStatus {
+            public_key,
+            public_key_share,
+            epoch,
+            threshold,
+            committee,
+        } => {
+            let mut auth_service = auth_service.lock().await;
+
+            let stored = match (public_key, public_key_share) {
+                (Some(public_key), Some(public_key_share)) => Some(StoredKey {
+                    public_key: Encoder::decode_public_key(public_key.as_str())
+                        .map_err(|e| format!("Decode error: {}", e))?,
+                    public_key_share: Encoder::decode_public_key(public_key_share.as_str())
+                        .map_err(|e| format!("Decode error: {}", e))?,
+                    epoch,
+                    threshold,
+                    committee: committee.into_iter().collect(),
+                }),
+                _ => None,
+            };
+
+            auth_service
+                .process_key_status(payload.session, payload.sender, stored)
+                .await;
+            Ok(())
+        }
+        Message::KeygenResponse {
+            public_key,
+            public_key_share,
+        } => {
+            let mut auth_service = auth_service.lock().await;
+
+            let public_key = Encoder::decode_public_key(public_key.as_str())
                 .map_err(|e| format!("Decode error: {}", e))?;
-            let commitments_map = Encoder::decode_map_commitments(commitments_map.as_str())
+            let public_key_share = Encoder::decode_public_key(public_key_share.as_str())
                 .map_err(|e| format!("Decode error: {}", e))?;
 
             auth_service
-                .process_round1_response(payload.sender, phase1, commitments, commitments_map)
+                .process_keygen_response(
+                    payload.session,
+                    payload.sender,
+                    public_key,
+                    public_key_share,
+                )
+                .await;
+            Ok(())
+        }
+        Message::Round1FinalResponse => {
+            let mut auth_service = auth_service.lock().await;
+            auth_service
+                .process_round1_final_response(payload.session, payload.sender)
                 .await;
             Ok(())
         }
-        Message::Round1FinalResponse { round1 } => {
+        Message::Round2FinalResponse { commitments } => {
             let mut auth_service = auth_service.lock().await;
 
-            let round1 = Encoder::decode_phase1_output(round1.as_str())
+            let commitments = Encoder::decode_batch_commitments(commitments.as_str())
                 .map_err(|e| format!("Decode error: {}", e))?;
 
             auth_service
-                .process_round1_final_response(payload.sender, round1)
+                .process_round2_final_response(payload.session, payload.sender, commitments)
                 .await;
             Ok(())
         }
-        Message::Round2Response { phase2, map } => {
+        Message::SignResponse { share } => {
             let mut auth_service = auth_service.lock().await;
 
-            let phase2 = Encoder::decode_phase2(phase2.as_str())
-                .map_err(|e| format!("Decode error: {}", e))?;
-            let map =
-                Encoder::decode_map(map.as_str()).map_err(|e| format!("Decode error: {}", e))?;
+            let share = match Encoder::decode_signature_share(share.as_str()) {
+                Ok(share) => share,
+                Err(e) => {
+                    let reason = format!("Decode error: {}", e);
+                    auth_service
+                        .process_invalid_sign_response(
+                            payload.session,
+                            payload.sender,
+                            reason.clone(),
+                        )
+                        .await;
+                    return Err(reason);
+                }
+            };
 
             auth_service
-                .process_round2_response(payload.sender, phase2, map)
+                .process_sign_response(payload.session, payload.sender, share)
+                .await;
+            Ok(())
+        }
+        Message::Blame {
+            culprit,
+            reason,
+            evidence,
+        } => {
+            let mut auth_service = auth_service.lock().await;
+            auth_service
+                .process_blame(payload.session, payload.sender, culprit, reason, evidence)
+                .await;
+            Ok(())
+        }
+        Message::Refusal { reason } => {
+            let mut auth_service = auth_service.lock().await;
+            auth_service
+                .process_refusal(
+                    payload.session,
+                    payload.sender,
+                    reason,
+                    envelope.to_string(),
+                )
+                .await;
+            Ok(())
+        }
+        Message::Error { reason } => {
+            let mut auth_service = auth_service.lock().await;
+            auth_service
+                .process_error(payload.session, payload.sender, reason)
                 .await;
             Ok(())
         }
@@ -141,24 +340,24 @@ async fn handle_payload(
     }
 }
 
-async fn connect_to_peer(addr: &str) -> tokio::io::Result<tokio::net::TcpStream> {
-    let stream = tokio::net::TcpStream::connect(addr).await?;
-    Ok(stream)
-}
-
+/// Connects to every signer. The AS only sends on these connections, signers answer on their
+/// own connection to the AS.
 async fn connect_to_peers(
+    keys: &NodeKeys,
+    signing_keys: &Arc<SigningKeys>,
     self_id: &u16,
     total_nodes: &u16,
-) -> HashMap<u16, Arc<Mutex<tokio::net::TcpStream>>> {
+) -> HashMap<u16, Arc<Mutex<EnvelopeWriter>>> {
     let mut peers = HashMap::new();
     for node_id in 1..*total_nodes {
         if node_id == *self_id {
             continue;
         }
         let addr = format!("node{}:{}", node_id, 8000 + node_id);
-        match connect_to_peer(&addr).await {
-            Ok(stream) => {
+        match keys.connect(&addr, node_id).await {
+            Ok((_, stream)) => {
                 println!("Connected to peer {}", addr);
+                let stream = EnvelopeWriter::new(stream, node_id, Arc::clone(signing_keys));
                 peers.insert(node_id, Arc::new(Mutex::new(stream)));
             }
             Err(e) => {
@@ -177,32 +376,103 @@ async fn main() -> tokio::io::Result<()> {
     let node_id = config.node_id;
     let total_nodes = config.total_nodes;
     let threshold_signers = config.threshold_signers;
+    let refresh_interval = config.refresh_interval;
+
+    let keys = match NodeKeys::load(
+        &config.node_keys_dir,
+        node_id,
+        total_nodes,
+        &config.deployment_id,
+    ) {
+        Ok(keys) => Arc::new(keys),
+        Err(e) => {
+            eprintln!(
+                "Failed to load node keys from {}: {}. Generate them with the node_keys binary.",
+                config.node_keys_dir, e
+            );
+            std::process::exit(1);
+        }
+    };
+
+    let signing_keys = match SigningKeys::load(
+        &config.node_keys_dir,
+        node_id,
+        total_nodes,
+        &config.deployment_id,
+    ) {
+        Ok(keys) => Arc::new(keys),
+        Err(e) => {
+            eprintln!(
+                "Failed to load signing keys from {}: {}. Generate them with the node_keys binary.",
+                config.node_keys_dir, e
+            );
+            std::process::exit(1);
+        }
+    };
 
     let port = 8000;
 
     let listener = TcpListener::bind(format!("0.0.0.0:{}", port)).await?;
     println!("Listening on {}", listener.local_addr()?);
 
-    // Sleep for a few seconds to allow all nodes to start
-    tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
-
-    let peers = Arc::new(Mutex::new(connect_to_peers(&node_id, &total_nodes).await));
+    let peers = Arc::new(Mutex::new(HashMap::new()));
 
     let auth_service = Arc::new(Mutex::new(AuthenticationService::init(
         config,
         threshold_signers,
         peers.clone(),
+        Arc::clone(&signing_keys),
     )));
 
     let auth_service_clone = Arc::clone(&auth_service);
 
-    let listener_fut = handle_listener(listener, auth_service_clone);
-
-    auth_service.lock().await.share_sk_shares().await;
+    // Accept before connecting out, a handshake only completes once the other end accepts
+    let listener_fut = tokio::spawn(handle_listener(
+        listener,
+        Arc::clone(&keys),
+        Arc::clone(&signing_keys),
+        auth_service_clone,
+    ));
 
+    // Sleep for a few seconds to allow all nodes to start
     tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
-    auth_service.lock().await.send_round1_request().await;
 
-    listener_fut.await?;
+    // Messages that arrive in the meantime wait for the connections on the lock
+    let mut peers_guard = peers.lock().await;
+    *peers_guard = connect_to_peers(&keys, &signing_keys, &node_id, &total_nodes).await;
+    drop(peers_guard);
+
+    // Signers that kept their key shares from an earlier run carry on with that key. Otherwise
+    // signing starts once the dealer handed out the shares, or once every signer has reported
+    // the same public key
+    auth_service.lock().await.send_key_status_request().await;
+
+    {
+        // Sessions whose signers stopped answering are given up and retried elsewhere
+        let auth_service = Arc::clone(&auth_service);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(1));
+            loop {
+                interval.tick().await;
+                auth_service.lock().await.check_deadlines().await;
+            }
+        });
+    }
+
+    if refresh_interval > 0 {
+        let auth_service = Arc::clone(&auth_service);
+        tokio::spawn(async move {
+            let mut interval =
+                tokio::time::interval(tokio::time::Duration::from_secs(refresh_interval));
+            // The first tick completes immediately, before the key even exists
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                auth_service.lock().await.send_refresh_request().await;
+            }
+        });
+    }
+
+    listener_fut.await??;
     Ok(())
 }
@@ -1,34 +1,32 @@
 use crate::config::Config;
-use crate::exp_utils::setup_messages;
+use crate::envelope::{Envelope, EnvelopeWriter, SigningKeys};
 use crate::exp_utils::*;
 use crate::helper::encoder::Encoder;
+use crate::rng::node_rng;
+use crate::token::TokenAttributes;
 
 use crate::constant::*;
-use crate::helper::message::{Message, Payload};
-use ark_bls12_381::{Bls12_381, Fr};
-use ark_std::rand::{rngs::StdRng, SeedableRng};
-use ark_std::Zero;
-use bbs_plus::threshold::multiplication_phase::Phase2;
-use bbs_plus::threshold::randomness_generation_phase::Phase1;
+use crate::helper::message::{Message, Payload, SessionId, NO_SESSION};
+use crate::presignature::BatchCommitments;
+use ark_bls12_381::{Bls12_381, Fr, G1Affine, G1Projective, G2Affine, G2Projective};
+use ark_ec::pairing::Pairing;
+use ark_ec::{AffineRepr, CurveGroup};
+use ark_ff::{Field, Zero};
+use bbs_plus::prelude::Signature23G1;
+use bbs_plus::setup::{PublicKeyG2, SecretKey, SignatureParams23G1};
 use bbs_plus::threshold::threshold_bbs::BBSSignatureShare;
-use bbs_plus::{
-    setup::{PublicKeyG2, SecretKey, SignatureParams23G1},
-    threshold::threshold_bbs::Phase1Output,
-};
 use blake2::Blake2b512;
-use oblivious_transfer_protocols::cointoss::Commitments;
-use oblivious_transfer_protocols::ot_based_multiplication::batch_mul_multi_party::Message1;
-use oblivious_transfer_protocols::ot_based_multiplication::{
-    dkls18_mul_2p::MultiplicationOTEParams, dkls19_batch_mul_2p::GadgetVector,
-};
 use oblivious_transfer_protocols::*;
 use rand::prelude::*;
 use rayon::vec;
+use secret_sharing_and_dkg::common::lagrange_basis_at_0;
 use secret_sharing_and_dkg::shamir_ss::deal_random_secret;
+use serde::Serialize;
 use serde_json::json;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::io::AsyncWriteExt;
 use tokio::sync::Mutex;
 
@@ -45,36 +43,200 @@ fn trusted_party_keygen<R: RngCore>(
     (public_key, secret, secret_shares)
 }
 
-async fn send_message(
-    stream: &mut tokio::net::TcpStream,
-    payload: &Payload,
-) -> tokio::io::Result<()> {
-    let mut serialized = serde_json::to_vec(payload)?;
-    serialized.push(b'\n');
-    stream.write_all(&serialized).await?;
-    Ok(())
+async fn send_message(stream: &mut EnvelopeWriter, payload: &Payload) -> tokio::io::Result<()> {
+    stream.send(payload).await
+}
+
+/// The connection of a client waiting for its login.
+pub type Client = Arc<Mutex<EnvelopeWriter>>;
+
+/// A login waiting for a presignature. The token's attributes and the deadline hold across
+/// retries.
+struct Login {
+    attempts: u32,
+    client: Option<Client>,
+    attributes: TokenAttributes,
+    deadline: Instant,
+}
+
+impl Login {
+    fn new(client: Option<Client>, attributes: TokenAttributes) -> Self {
+        Self {
+            attempts: 0,
+            client,
+            attributes,
+            deadline: Instant::now() + Duration::from_secs(LOGIN_TIMEOUT_SECS),
+        }
+    }
+}
+
+/// Time for the signers to finish the current round of a session.
+fn round_deadline() -> Instant {
+    Instant::now() + Duration::from_secs(ROUND_TIMEOUT_SECS)
+}
+
+/// The key a signer holds a share of, as it reported on startup of the AS.
+pub struct StoredKey {
+    pub public_key: PublicKeyG2<Bls12_381>,
+    pub public_key_share: PublicKeyG2<Bls12_381>,
+    pub epoch: u64,
+    pub threshold: u16,
+    pub committee: BTreeSet<ParticipantId>,
+}
+
+/// A presignature batch as tracked by the AS: who holds it and the next unused index.
+struct PresignatureBatch {
+    quorum: BTreeSet<ParticipantId>,
+    batch_size: u32,
+    next_index: u32,
+    /// Each signer's commitments to its `r` and `u` of every presignature.
+    commitments: BTreeMap<ParticipantId, BatchCommitments>,
+    /// The `e` of every presignature, which all signers of the quorum reported.
+    e: Vec<Fr>,
+}
+
+/// A presignature batch being computed, and the signers done with each phase.
+struct PresignSession {
+    quorum: BTreeSet<ParticipantId>,
+    round1_done: BTreeSet<ParticipantId>,
+    /// Signers done with Phase 2, with their commitments to the presignatures and their `e`.
+    round2_done: BTreeMap<ParticipantId, BatchCommitments>,
+    /// When the running phase times out.
+    deadline: Instant,
+}
+
+impl PresignSession {
+    fn new(quorum: BTreeSet<ParticipantId>) -> Self {
+        Self {
+            quorum,
+            round1_done: BTreeSet::new(),
+            round2_done: BTreeMap::new(),
+            deadline: round_deadline(),
+        }
+    }
+}
+
+/// The signature for one login and the signature shares received so far.
+struct SigningSession {
+    quorum: BTreeSet<ParticipantId>,
+    /// The presignature batch used, and the login being served.
+    batch: SessionId,
+    login: Login,
+    /// The messages derived from the token's attributes, their `B`, and each signer's
+    /// commitments `params.g2 * r` and `params.g1 * u` of the presignature used. A share's `R`
+    /// must be `B * r`, its `u` the committed one, and its `e` the presignature's `e`.
+    messages: Vec<Fr>,
+    base: G1Affine,
+    commitments: BTreeMap<ParticipantId, (G2Affine, G1Affine)>,
+    e: Fr,
+    shares: HashMap<ParticipantId, BBSSignatureShare<Bls12_381>>,
+    deadline: Instant,
+    token_issue_timer: Timer,
+    token_verify_timer: Timer,
+}
+
+impl SigningSession {
+    fn new(
+        quorum: BTreeSet<ParticipantId>,
+        batch: SessionId,
+        login: Login,
+        messages: Vec<Fr>,
+        base: G1Affine,
+        commitments: BTreeMap<ParticipantId, (G2Affine, G1Affine)>,
+        e: Fr,
+    ) -> Self {
+        Self {
+            quorum,
+            batch,
+            login,
+            messages,
+            base,
+            commitments,
+            e,
+            shares: HashMap::new(),
+            deadline: round_deadline(),
+            token_issue_timer: Timer::with_label("token_issue"),
+            token_verify_timer: Timer::with_label("token_verify"),
+        }
+    }
+}
+
+/// A failed session and the signer held responsible, if the failure can be tied to one.
+#[derive(Serialize, Debug, Clone)]
+pub struct BlameReport {
+    pub session: SessionId,
+    pub culprit: Option<ParticipantId>,
+    /// The signer that caught the culprit, or the AS itself.
+    pub reporter: ParticipantId,
+    pub reason: String,
+    /// The AS checked the misbehaviour itself, or the signer's report came with evidence that
+    /// proves it. Only then is the culprit kept out on a single report.
+    pub verified: bool,
+}
+
+/// A signer's refusal to sign a token, with the envelope it signed the refusal in.
+#[derive(Serialize, Debug, Clone)]
+pub struct Refusal {
+    pub session: SessionId,
+    pub signer: ParticipantId,
+    pub attributes: TokenAttributes,
+    pub reason: String,
+    pub envelope: String,
 }
 
 pub struct AuthenticationService {
     config: Config,
-    peers: Arc<Mutex<HashMap<u16, Arc<Mutex<tokio::net::TcpStream>>>>>,
+    peers: Arc<Mutex<HashMap<u16, Arc<Mutex<EnvelopeWriter>>>>>,
+    /// The registered keys of every node, to check the evidence in blame reports.
+    signing_keys: Arc<SigningKeys>,
     params: SignatureParams23G1<Bls12_381>,
+    /// Only filled when the AS acts as trusted dealer.
     sk_shares: Vec<Fr>,
-    messages: Vec<Fr>,
-    public_key: PublicKeyG2<Bls12_381>,
+    /// Draws the token IDs.
+    rng: StdRng,
+    public_key: Option<PublicKeyG2<Bls12_381>>,
+    /// Keys the signers reported holding when the AS started, while it waits for the others.
+    key_status: Option<BTreeMap<ParticipantId, Option<StoredKey>>>,
+    /// Public keys reported by the signers at the end of DKG.
+    keygen_done: BTreeMap<ParticipantId, PublicKeyG2<Bls12_381>>,
+    /// Epoch of the signers' key shares, bumped by every finished refresh.
+    epoch: u64,
+    /// Signers done with the running refresh or resharing.
+    refresh_done: BTreeSet<ParticipantId>,
+    /// Public key shares of the committee, and those reported for the running keygen, refresh
+    /// or resharing. Checked against the public key once all are in.
+    public_key_shares: BTreeMap<ParticipantId, G2Affine>,
+    new_public_key_shares: BTreeMap<ParticipantId, G2Affine>,
+    /// Current signer committee and, while resharing, the committee and threshold to move to.
+    committee: BTreeSet<ParticipantId>,
+    resharing: Option<(BTreeSet<ParticipantId>, u16)>,
+    refreshing: bool,
+    /// When each signer last sent a heartbeat.
+    last_seen: HashMap<ParticipantId, Instant>,
+    /// Last session ID handed out, and the session of the running keygen, refresh or resharing.
+    last_session: SessionId,
+    key_session: SessionId,
+    /// When the running keygen, refresh or resharing times out.
+    key_deadline: Option<Instant>,
+    /// Presignature batches held by the signers, by the session that computed them.
+    presignatures: BTreeMap<SessionId, PresignatureBatch>,
+    presign_sessions: BTreeMap<SessionId, PresignSession>,
+    signing_sessions: HashMap<SessionId, SigningSession>,
+    /// Logins waiting for a presignature.
+    pending_logins: VecDeque<Login>,
+    /// Every blame report so far, and the signers kept out of new sessions because of them.
+    blame_reports: Vec<BlameReport>,
+    /// Every token a signer refused to sign so far.
+    refusals: Vec<Refusal>,
+    excluded: BTreeSet<ParticipantId>,
+    /// Signers that accused each signer without evidence that proves it.
+    accusations: BTreeMap<ParticipantId, BTreeSet<ParticipantId>>,
+    /// Failed signatures each signer took part in when the culprit was unknown.
+    suspicion: HashMap<ParticipantId, u32>,
 
-    round1s: HashMap<ParticipantId, Phase1<Fr, 256>>,
-    commitments: HashMap<ParticipantId, Commitments>,
-    commitments_zero_share: HashMap<ParticipantId, BTreeMap<ParticipantId, Commitments>>,
-    round1outs: HashMap<ParticipantId, Phase1Output<Fr>>,
     threshold_signers: u16,
-    expected_sk: Fr,
-    round2s: HashMap<ParticipantId, Phase2<Fr, 256, 80>>,
-    all_msg_1s: HashMap<ParticipantId, BTreeMap<ParticipantId, Message1<Fr>>>,
     fn1_timer: Timer,
     fn2_timer: Timer,
-    token_issue_timer: Timer,
-    token_verify_timer: Timer,
     current_run: u32,
 }
 
@@ -82,57 +244,76 @@ impl AuthenticationService {
     pub fn init(
         config: Config,
         threshold_signers: u16,
-        peers: Arc<Mutex<HashMap<u16, Arc<Mutex<tokio::net::TcpStream>>>>>,
+        peers: Arc<Mutex<HashMap<u16, Arc<Mutex<EnvelopeWriter>>>>>,
+        signing_keys: Arc<SigningKeys>,
     ) -> Self {
         let total_signers = config.total_nodes;
 
-        let mut rng = StdRng::seed_from_u64(0u64);
-
-        let params =
-            SignatureParams23G1::<Bls12_381>::generate_using_rng(&mut rng, config.message_count);
-
-        let messages = setup_messages(&mut rng, config.message_count);
-
-        // Add sk_shares logic
-        let (public_key, _sk, sk_shares) =
-            trusted_party_keygen(&mut rng, threshold_signers, total_signers, params.clone());
+        let mut rng = node_rng(config.rng_seed);
 
-        let round1s = HashMap::new();
-        let commitments = HashMap::new();
-        let commitments_zero_share = HashMap::new();
-        let round1outs = HashMap::new();
+        let params = SignatureParams23G1::<Bls12_381>::new::<Blake2b512>(
+            SIG_PARAMS_LABEL,
+            config.message_count,
+        );
 
-        let expected_sk = Fr::zero();
-
-        let round2s = HashMap::new();
-        let all_msg_1s = HashMap::new();
+        // With DKG the signers generate the key among themselves and report the public key
+        let committee = (1..=config.signers).collect::<BTreeSet<_>>();
+        let (public_key, sk_shares) = if config.trusted_dealer {
+            let (public_key, _sk, sk_shares) =
+                trusted_party_keygen(&mut rng, threshold_signers, total_signers, params.clone());
+            (Some(public_key), sk_shares)
+        } else {
+            (None, vec![])
+        };
 
         let fn1_timer = Timer::with_label("fn1");
         let fn2_timer = Timer::with_label("fn2");
-        let token_issue_timer = Timer::with_label("token_issue");
-        let token_verify_timer = Timer::with_label("token_verify");
 
         let current_run = config.current_run;
+        // The dealer knows every key share, so it derives the public key shares itself
+        let public_key_shares = committee
+            .iter()
+            .filter_map(|id| sk_shares.get((*id - 1) as usize).map(|share| (*id, share)))
+            .map(|(id, share)| (id, (params.g2 * share).into_affine()))
+            .collect();
 
         Self {
             config,
             peers,
+            signing_keys,
             params,
             sk_shares,
-            messages,
+            rng,
             public_key,
-            round1s,
-            commitments,
-            commitments_zero_share,
-            round1outs,
+            key_status: None,
+            keygen_done: BTreeMap::new(),
+            epoch: 0,
+            refresh_done: BTreeSet::new(),
+            public_key_shares,
+            new_public_key_shares: BTreeMap::new(),
+            committee,
+            resharing: None,
+            refreshing: false,
+            last_seen: HashMap::new(),
+            // Carries on from the time, so a restarted AS never reuses the session IDs of an
+            // earlier run, which the signers' audit logs refer to
+            last_session: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(NO_SESSION, |now| now.as_micros() as SessionId),
+            key_session: NO_SESSION,
+            key_deadline: None,
+            presignatures: BTreeMap::new(),
+            presign_sessions: BTreeMap::new(),
+            signing_sessions: HashMap::new(),
+            pending_logins: VecDeque::new(),
+            blame_reports: Vec::new(),
+            refusals: Vec::new(),
+            excluded: BTreeSet::new(),
+            accusations: BTreeMap::new(),
+            suspicion: HashMap::new(),
             threshold_signers,
-            expected_sk,
-            round2s,
-            all_msg_1s,
             fn1_timer,
             fn2_timer,
-            token_issue_timer,
-            token_verify_timer,
             current_run,
         }
     }
@@ -141,11 +322,16 @@ impl AuthenticationService {
         self.current_run += 1;
     }
 
-    async fn on_complete(&mut self) {
-        let mut file = tokio::fs::File::create(format!(
-            "./op/tbbs_msg_{}_threshold_{}_current_run_{}.json",
+    /// Where the output file `name` goes.
+    fn output_path(&self, name: String) -> PathBuf {
+        Path::new(&self.config.output_dir).join(name)
+    }
+
+    async fn on_complete(&mut self, token_issue_timer: &Timer, token_verify_timer: &Timer) {
+        let mut file = tokio::fs::File::create(self.output_path(format!(
+            "tbbs_msg_{}_threshold_{}_current_run_{}.json",
             self.config.message_count, self.threshold_signers, self.current_run
-        ))
+        )))
         .await
         .unwrap();
 
@@ -153,8 +339,8 @@ impl AuthenticationService {
             "msg_count": self.config.message_count,
             "fn1": self.fn1_timer.get_duration(),
             "fn2": self.fn2_timer.get_duration(),
-            "token_issue": self.token_issue_timer.get_duration(),
-            "token_verify": self.token_verify_timer.get_duration(),
+            "token_issue": token_issue_timer.get_duration(),
+            "token_verify": token_verify_timer.get_duration(),
         });
         let json_str = serde_json::to_string_pretty(&timings).unwrap();
         file.write_all(json_str.as_bytes()).await.unwrap();
@@ -164,188 +350,475 @@ impl AuthenticationService {
     }
 
     pub async fn share_sk_shares(&mut self) {
+        let public_key = match Encoder::encode_public_key(self.public_key.as_ref().unwrap()) {
+            Ok(public_key) => public_key,
+            Err(e) => {
+                eprintln!("Failed to encode public key: {}", e);
+                return;
+            }
+        };
         let guard = self.peers.lock().await;
         for (node_id, peer) in guard.iter() {
             let node_id = *node_id;
 
-            if !(1..=self.threshold_signers).contains(&node_id) {
+            // Every committee member gets a share, so all of them can take part in share refresh
+            if !self.committee.contains(&node_id) {
                 continue;
             }
 
-            let peer = peer.clone();
-
             let sk_share = self.sk_shares.get((node_id - 1) as usize).unwrap();
             let payload = Payload {
                 sender: self.config.node_id,
+                session: NO_SESSION,
                 msg: Message::SkShares {
                     shares: Encoder::encode_sk_share(sk_share),
+                    public_key: public_key.clone(),
                 },
             };
 
-            tokio::spawn(async move {
-                let mut stream = peer.lock().await;
-                if let Err(e) = send_message(&mut *stream, &payload).await {
-                    eprintln!(
-                        "Failed to send message to {}: {}",
-                        stream.local_addr().unwrap(),
-                        e
-                    );
-                }
-            });
+            // Not spawned, so the shares reach the signers before any session that uses them
+            let mut stream = peer.lock().await;
+            if let Err(e) = send_message(&mut stream, &payload).await {
+                eprintln!("Failed to send message to {}: {}", node_id, e);
+            }
         }
     }
 
-    pub async fn send_round1_request(&mut self) {
-        println!("Sending round 1 request...");
-        self.fn1_timer.start();
-        let guard = self.peers.lock().await;
-        for (_node_id, peer) in guard.iter() {
-            if !(1..=self.threshold_signers).contains(&_node_id) {
-                continue;
-            }
+    /// Asks every signer which key it holds a share of, before any new key is made. A signer
+    /// that restarted keeps its share in its keystore, and a new key would strand it.
+    pub async fn send_key_status_request(&mut self) {
+        println!("Asking the signers for their key shares...");
+        let committee = self.committee.clone();
+        self.key_status = Some(BTreeMap::new());
+        self.key_session = self.new_session();
+        self.key_deadline = Some(round_deadline());
+        self.send_to_signers(&committee, self.key_session, Message::KeyStatusRequest)
+            .await;
+    }
 
-            let peer = peer.clone();
-            let payload = Payload {
-                sender: self.config.node_id,
-                msg: Message::Round1Request,
-            };
-            tokio::spawn(async move {
-                let mut stream = peer.lock().await;
-                if let Err(e) = send_message(&mut *stream, &payload).await {
-                    eprintln!(
-                        "Failed to send message to {}: {}",
-                        stream.local_addr().unwrap(),
-                        e
-                    );
-                }
-            });
+    pub async fn process_key_status(
+        &mut self,
+        session: SessionId,
+        sender: ParticipantId,
+        stored: Option<StoredKey>,
+    ) {
+        if session != self.key_session || !self.committee.contains(&sender) {
+            return;
+        }
+        let Some(key_status) = self.key_status.as_mut() else {
+            return;
+        };
+        key_status.insert(sender, stored);
+        if key_status.len() == self.committee.len() {
+            self.resume_key().await;
         }
     }
 
-    async fn complete_round1(&mut self) {
-        for i in 1..=self.threshold_signers {
-            let round1s = self.round1s.get_mut(&i).unwrap();
-            for j in 1..=self.threshold_signers {
-                if i != j {
-                    round1s
-                        .receive_commitment(
-                            j,
-                            self.commitments.get(&j).unwrap().clone(),
-                            self.commitments_zero_share
-                                .get(&j)
-                                .unwrap()
-                                .get(&i)
-                                .unwrap()
-                                .clone(),
-                        )
-                        .unwrap();
+    /// Carries on with the key most signers hold, if at least a threshold of them agree on it
+    /// and its epoch. Signers without a share of it are kept out of sessions until a resharing
+    /// brings them back. A new key is only made when every signer answered and none holds one.
+    async fn resume_key(&mut self) {
+        let Some(reports) = self.key_status.take() else {
+            return;
+        };
+        let session = self.key_session;
+        self.key_session = NO_SESSION;
+        self.key_deadline = None;
+
+        let mut keys: Vec<(&StoredKey, BTreeSet<ParticipantId>)> = vec![];
+        for (id, stored) in reports.iter() {
+            let Some(stored) = stored else {
+                continue;
+            };
+            match keys
+                .iter_mut()
+                .find(|(key, _)| key.public_key == stored.public_key && key.epoch == stored.epoch)
+            {
+                Some((_, holders)) => {
+                    holders.insert(*id);
                 }
+                None => keys.push((stored, BTreeSet::from([*id]))),
             }
         }
-
-        for i in 1..=self.threshold_signers {
-            for j in 1..=self.threshold_signers {
-                if i != j {
-                    let share = self.round1s.get(&j).unwrap().get_comm_shares_and_salts();
-                    let zero_share = self
-                        .round1s
-                        .get(&j)
-                        .unwrap()
-                        .get_comm_shares_and_salts_for_zero_sharing_protocol_with_other(&i);
-                    self.round1s
-                        .get_mut(&i)
-                        .unwrap()
-                        .receive_shares(j, share, zero_share)
-                        .unwrap();
-                }
+        let Some((key, holders)) = keys.into_iter().max_by_key(|(_, holders)| holders.len()) else {
+            if reports.len() < self.committee.len() {
+                eprintln!(
+                    "Only {:?} answered and hold no key share, not making a new key without the others",
+                    reports.keys()
+                );
+            } else if self.config.trusted_dealer {
+                self.share_sk_shares().await;
+                self.queue_first_login().await;
+            } else {
+                // Signing starts once every signer has reported the same public key
+                self.send_keygen_request().await;
             }
+            return;
+        };
+        if holders.len() < key.threshold as usize {
+            eprintln!(
+                "Only {:?} hold shares of the same key and epoch, fewer than the threshold of {}",
+                holders, key.threshold
+            );
+            return;
         }
 
-        for (i, round1) in self.round1s.drain().collect::<Vec<_>>() {
-            // Acquire the peer Arc<Mutex<TcpStream>> by locking the peers map briefly and cloning the entry,
-            // then lock the stream's mutex inside the spawned task to avoid holding both locks across await.
-            let peer = {
-                let guard = self.peers.lock().await;
-                guard.get(&i).unwrap().clone()
+        println!(
+            "Resuming with the stored key of epoch {}, held by {:?}",
+            key.epoch, holders
+        );
+        self.public_key = Some(key.public_key.clone());
+        self.epoch = key.epoch;
+        self.threshold_signers = key.threshold;
+        self.committee = key.committee.clone();
+        // The dealer's own key is not the one the signers hold
+        self.sk_shares.clear();
+        self.new_public_key_shares = reports
+            .iter()
+            .filter(|(id, _)| holders.contains(id))
+            .filter_map(|(id, stored)| {
+                stored
+                    .as_ref()
+                    .map(|stored| (*id, stored.public_key_share.0))
+            })
+            .collect();
+        for id in self.committee.clone().difference(&holders) {
+            let reason = if reports.contains_key(id) {
+                format!("Holds no share of the key of epoch {}", key.epoch)
+            } else {
+                "Did not report its key share".to_string()
             };
+            self.blame(BlameReport {
+                session,
+                culprit: Some(*id),
+                reporter: self.config.node_id,
+                reason,
+                verified: true,
+            })
+            .await;
+        }
+        self.install_public_key_shares(session).await;
+        self.queue_first_login().await;
+    }
 
-            // if i == 1 {
-            //     println!("round1: {:?}", round1);
-            // }
+    /// Asks every signer to run DKG. The AS never sees any key share.
+    pub async fn send_keygen_request(&mut self) {
+        println!("Sending keygen request...");
+        let committee = self.committee.clone();
+        self.key_session = self.new_session();
+        self.key_deadline = Some(round_deadline());
+        self.send_to_signers(&committee, self.key_session, Message::KeygenRequest)
+            .await;
+    }
 
-            let payload = Payload {
-                sender: self.config.node_id,
-                msg: Message::Round1FinalRequest {
-                    phase1: Encoder::encode_phase1(&round1),
-                },
-            };
+    pub async fn process_keygen_response(
+        &mut self,
+        session: SessionId,
+        sender: ParticipantId,
+        public_key: PublicKeyG2<Bls12_381>,
+        public_key_share: PublicKeyG2<Bls12_381>,
+    ) {
+        if session != self.key_session || !self.committee.contains(&sender) {
+            return;
+        }
+        self.keygen_done.insert(sender, public_key);
+        self.new_public_key_shares
+            .insert(sender, public_key_share.0);
 
-            tokio::spawn(async move {
-                let mut stream = peer.lock().await;
-                if let Err(e) = send_message(&mut *stream, &payload).await {
-                    eprintln!(
-                        "Failed to send message to {}: {}",
-                        stream.local_addr().unwrap(),
-                        e
-                    );
+        if self.keygen_done.len() == self.committee.len() {
+            self.key_deadline = None;
+            let keys = std::mem::take(&mut self.keygen_done);
+            let public_key = match agreed_public_key(&keys) {
+                Ok(public_key) => public_key,
+                Err(culprits) => {
+                    eprintln!("Signers derived different public keys, aborting");
+                    if culprits.is_empty() {
+                        self.suspect(&self.committee.clone());
+                    }
+                    for culprit in culprits {
+                        self.blame(BlameReport {
+                            session,
+                            culprit: Some(culprit),
+                            reporter: self.config.node_id,
+                            reason: "Public key differs from that of most signers".into(),
+                            verified: true,
+                        })
+                        .await;
+                    }
+                    self.abort_key_session().await;
+                    return;
                 }
-            });
+            };
+
+            println!("Key generation finished");
+            self.public_key = Some(public_key);
+            self.install_public_key_shares(session).await;
+            self.queue_first_login().await;
+        }
+    }
+
+    /// Asks every signer to refresh its key share into the next epoch. Refresh and signing never
+    /// overlap, since a session mixing shares of two epochs would produce an invalid signature.
+    pub async fn send_refresh_request(&mut self) {
+        if self.public_key.is_none() || self.busy() {
+            println!("Signers busy, skipping share refresh");
+            return;
         }
+        self.refreshing = true;
+
+        println!("Sending refresh request for epoch {}...", self.epoch + 1);
+        let committee = self.committee.clone();
+        let msg = Message::RefreshRequest {
+            epoch: self.epoch + 1,
+        };
+        self.key_session = self.new_session();
+        self.key_deadline = Some(round_deadline());
+        self.send_to_signers(&committee, self.key_session, msg)
+            .await;
     }
 
-    pub async fn process_round1_response(
+    pub async fn process_refresh_response(
         &mut self,
+        session: SessionId,
         sender: ParticipantId,
-        phase1: Phase1<Fr, 256>,
-        comm: Commitments,
-        comm_zero: BTreeMap<ParticipantId, Commitments>,
+        epoch: u64,
+        public_key_share: PublicKeyG2<Bls12_381>,
     ) {
-        /*!
-         * Process the round 1 response from other signers.
-         * Store the round 1 data and commitments for later use.
-         */
-        self.round1s.insert(sender, phase1);
-        self.commitments.insert(sender, comm);
-        self.commitments_zero_share.insert(sender, comm_zero);
+        if !self.refreshing
+            || session != self.key_session
+            || epoch != self.epoch + 1
+            || !self.committee.contains(&sender)
+        {
+            return;
+        }
+        self.refresh_done.insert(sender);
+        self.new_public_key_shares
+            .insert(sender, public_key_share.0);
 
-        self.fn1_timer.stop_and_print_ms();
+        if self.refresh_done.len() == self.committee.len() {
+            self.refresh_done.clear();
+            self.refreshing = false;
+            self.key_deadline = None;
+            self.epoch = epoch;
+            println!("Share refresh finished, epoch {}", self.epoch);
+            self.install_public_key_shares(session).await;
 
-        if self.round1s.len() as u16 == self.threshold_signers {
-            self.complete_round1().await;
+            // The signers dropped their presignatures along with the old shares
+            self.presignatures.clear();
+            self.serve_logins().await;
+            self.refill_presignatures().await;
         }
     }
 
-    pub async fn initiate_round2(&mut self) {
-        // for i in 2..=self.threshold_signers + 1 {}
-        for i in 1..=self.threshold_signers {
-            // Acquire the peer Arc<Mutex<TcpStream>> by locking the peers map briefly and cloning the entry,
-            // then lock the stream's mutex inside the spawned task to avoid holding both locks across await.
-            let peer = {
-                let guard = self.peers.lock().await;
-                guard.get(&i).unwrap().clone()
-            };
+    /// Asks the old and new signers to reshare the key to `committee` with `threshold`. The
+    /// public key stays the same, so relying parties are unaffected.
+    pub async fn send_reshare_request(
+        &mut self,
+        committee: BTreeSet<ParticipantId>,
+        threshold: u16,
+    ) {
+        if self.public_key.is_none() || self.busy() {
+            println!("Signers busy, skipping resharing");
+            return;
+        }
+        let connected = self
+            .peers
+            .lock()
+            .await
+            .keys()
+            .copied()
+            .collect::<BTreeSet<_>>();
+        if threshold == 0
+            || threshold as usize > committee.len()
+            || !committee.is_subset(&connected)
+        {
+            eprintln!(
+                "Invalid resharing to {:?} with threshold {}",
+                committee, threshold
+            );
+            return;
+        }
 
-            let masked_rs = self.round1outs.get(&i).unwrap().masked_rs.clone();
-            let masked_signing_key_share = self
-                .round1outs
-                .get(&i)
-                .unwrap()
-                .masked_signing_key_shares
-                .clone();
+        let public_key = match Encoder::encode_public_key(self.public_key.as_ref().unwrap()) {
+            Ok(public_key) => public_key,
+            Err(e) => {
+                eprintln!("Failed to encode public key: {}", e);
+                return;
+            }
+        };
+        let Some(dealers) = self.pick_quorum() else {
+            eprintln!(
+                "Fewer than {} signers are live, skipping resharing",
+                self.threshold_signers
+            );
+            return;
+        };
+        let msg = Message::ReshareRequest {
+            epoch: self.epoch + 1,
+            dealers: dealers.into_iter().collect(),
+            committee: committee.iter().copied().collect(),
+            threshold,
+            public_key,
+        };
 
-            self.fn2_timer.start();
+        println!(
+            "Sending reshare request to {:?} with threshold {} for epoch {}...",
+            committee,
+            threshold,
+            self.epoch + 1
+        );
+        let receivers = self.committee.union(&committee).copied().collect();
+        self.resharing = Some((committee, threshold));
+        self.key_session = self.new_session();
+        self.key_deadline = Some(round_deadline());
+        self.send_to_signers(&receivers, self.key_session, msg)
+            .await;
+    }
+
+    pub async fn process_reshare_response(
+        &mut self,
+        session: SessionId,
+        sender: ParticipantId,
+        epoch: u64,
+        public_key_share: Option<PublicKeyG2<Bls12_381>>,
+    ) {
+        let Some((committee, threshold)) = self.resharing.clone() else {
+            return;
+        };
+        // Old and new members answer, anyone else would finish the count early
+        if session != self.key_session
+            || epoch != self.epoch + 1
+            || !(self.committee.contains(&sender) || committee.contains(&sender))
+        {
+            return;
+        }
+        self.refresh_done.insert(sender);
+        if let Some(public_key_share) = public_key_share {
+            self.new_public_key_shares
+                .insert(sender, public_key_share.0);
+        }
+
+        if self.refresh_done.len() == self.committee.union(&committee).count() {
+            self.refresh_done.clear();
+            self.resharing = None;
+            self.key_deadline = None;
+            self.epoch = epoch;
+            self.committee = committee;
+            self.threshold_signers = threshold;
+            println!(
+                "Resharing finished, epoch {}, committee {:?}, threshold {}",
+                self.epoch, self.committee, self.threshold_signers
+            );
+
+            // The operator reshared, possibly to get rid of a blamed signer
+            self.excluded.clear();
+            self.accusations.clear();
+            self.suspicion.clear();
+            self.presignatures.clear();
+            self.install_public_key_shares(session).await;
+            self.serve_logins().await;
+            self.refill_presignatures().await;
+        }
+    }
+
+    /// Replaces the public key shares with those reported for the finished keygen, refresh or
+    /// resharing. A signer whose share does not match the public key holds a bad key share and
+    /// is kept out of new sessions, before it spoils any signature.
+    async fn install_public_key_shares(&mut self, session: SessionId) {
+        let shares = std::mem::take(&mut self.new_public_key_shares);
+        let Some(public_key) = self.public_key.as_ref() else {
+            return;
+        };
+        // Signers already kept out need not report a share
+        let missing = self
+            .committee
+            .iter()
+            .find(|id| !shares.contains_key(id) && !self.excluded.contains(id))
+            .copied();
+        let checked = match missing {
+            Some(id) => Err((Some(id), "No public key share".to_string())),
+            None => check_public_key_shares(&shares, &public_key.0, self.threshold_signers),
+        };
+        self.public_key_shares = shares;
+        if let Err((culprit, reason)) = checked {
+            self.blame(BlameReport {
+                session,
+                culprit,
+                reporter: self.config.node_id,
+                reason,
+                verified: true,
+            })
+            .await;
+        }
+    }
+
+    /// A refresh, a resharing, a signature or a presignature batch is running. Key shares
+    /// must not change while any of them runs.
+    fn busy(&self) -> bool {
+        self.refreshing
+            || self.resharing.is_some()
+            || !self.signing_sessions.is_empty()
+            || !self.presign_sessions.is_empty()
+    }
+
+    pub fn mark_alive(&mut self, sender: ParticipantId) {
+        self.last_seen.insert(sender, Instant::now());
+    }
+
+    /// Committee members that sent a heartbeat recently and were not caught misbehaving.
+    fn live_signers(&self) -> BTreeSet<ParticipantId> {
+        let timeout = Duration::from_secs(HEARTBEAT_TIMEOUT_SECS);
+        self.committee
+            .iter()
+            .filter(|id| !self.excluded.contains(id))
+            .filter(|id| {
+                self.last_seen
+                    .get(id)
+                    .is_some_and(|seen| seen.elapsed() < timeout)
+            })
+            .copied()
+            .collect()
+    }
+
+    /// Picks the signers of a session: the `threshold_signers` committee members that sent a
+    /// heartbeat recently and took part in the fewest failed signatures. `None` if too few of
+    /// them are live.
+    fn pick_quorum(&self) -> Option<BTreeSet<ParticipantId>> {
+        let mut live = self.live_signers().into_iter().collect::<Vec<_>>();
+        live.sort_by_key(|id| (self.suspicion.get(id).copied().unwrap_or_default(), *id));
+        let quorum = live
+            .into_iter()
+            .take(self.threshold_signers as usize)
+            .collect::<BTreeSet<_>>();
+        (quorum.len() == self.threshold_signers as usize).then_some(quorum)
+    }
+
+    /// Hands out a fresh session ID. IDs are never reused.
+    fn new_session(&mut self) -> SessionId {
+        self.last_session += 1;
+        self.last_session
+    }
 
+    async fn send_to_signers(
+        &self,
+        receivers: &BTreeSet<ParticipantId>,
+        session: SessionId,
+        msg: Message,
+    ) {
+        let guard = self.peers.lock().await;
+        for node_id in receivers {
+            let Some(peer) = guard.get(node_id).cloned() else {
+                eprintln!("No connection to signer {}", node_id);
+                continue;
+            };
             let payload = Payload {
                 sender: self.config.node_id,
-                msg: Message::Round2Request {
-                    masked_rs: Encoder::encode_vec_fr(&masked_rs),
-                    masked_signing_key_share: Encoder::encode_vec_fr(&masked_signing_key_share),
-                },
+                session,
+                msg: msg.clone(),
             };
-
             tokio::spawn(async move {
                 let mut stream = peer.lock().await;
-                if let Err(e) = send_message(&mut *stream, &payload).await {
+                if let Err(e) = send_message(&mut stream, &payload).await {
                     eprintln!(
                         "Failed to send message to {}: {}",
                         stream.local_addr().unwrap(),
@@ -356,119 +829,1542 @@ impl AuthenticationService {
         }
     }
 
+    /// Queues a login and serves it from the presignature pool. `client` gets the signed
+    /// token or the reason the login failed.
+    pub async fn queue_login(
+        &mut self,
+        client: Option<Client>,
+        subject: String,
+        audience: String,
+        credential_proof: Option<String>,
+    ) {
+        let attributes = TokenAttributes::new(
+            subject,
+            audience,
+            self.rng.next_u64(),
+            credential_proof,
+            self.config.token_lifetime,
+        );
+        self.pending_logins
+            .push_back(Login::new(client, attributes));
+        self.serve_logins().await;
+    }
+
+    /// Queues a first token for the AS itself, which nobody waits for.
+    pub async fn queue_first_login(&mut self) {
+        self.queue_login(None, "as".into(), "verisso".into(), None)
+            .await;
+    }
+
+    /// Starts a signing session for every queued login, each with the oldest unused
+    /// presignature whose quorum is live. Only `BBSSignatureShare::new` runs on the signers,
+    /// Phase 1 and Phase 2 ran ahead of time.
+    pub async fn serve_logins(&mut self) {
+        if self.refreshing || self.resharing.is_some() {
+            return;
+        }
+
+        let live = self.live_signers();
+        while !self.pending_logins.is_empty() {
+            let Some((&batch, presignatures)) = self
+                .presignatures
+                .iter_mut()
+                .find(|(_, batch)| batch.quorum.is_subset(&live))
+            else {
+                // Nothing usable in the pool, the logins wait for the next batch
+                break;
+            };
+            let index = presignatures.next_index;
+            let quorum = presignatures.quorum.clone();
+            let commitments = presignatures
+                .commitments
+                .iter()
+                .map(|(id, commitments)| {
+                    let index = index as usize;
+                    (*id, (commitments.r[index], commitments.u[index]))
+                })
+                .collect();
+            let e = presignatures.e[index as usize];
+            // Each presignature is handed out once, the signers refuse to use one twice anyway
+            presignatures.next_index += 1;
+            if presignatures.next_index == presignatures.batch_size {
+                self.presignatures.remove(&batch);
+            }
+            let login = self.pending_logins.pop_front().unwrap();
+
+            let messages = match login.attributes.to_messages(self.config.message_count) {
+                Ok(messages) => messages,
+                Err(e) => {
+                    self.reply_error(&login, e).await;
+                    continue;
+                }
+            };
+            let base = match self.params.b(messages.iter().enumerate()) {
+                Ok(base) => base.into_affine(),
+                Err(e) => {
+                    self.reply_error(&login, format!("Invalid messages: {:?}", e))
+                        .await;
+                    continue;
+                }
+            };
+            let attributes = match serde_json::to_string(&login.attributes) {
+                Ok(attributes) => attributes,
+                Err(e) => {
+                    self.reply_error(&login, format!("Failed to encode attributes: {}", e))
+                        .await;
+                    continue;
+                }
+            };
+
+            let session = self.new_session();
+            println!(
+                "Session {}: signing with presignature {} of batch {} by {:?}...",
+                session, index, batch, quorum
+            );
+            let msg = Message::SignRequest {
+                batch,
+                index,
+                quorum: quorum.iter().copied().collect(),
+                attributes,
+            };
+            self.send_to_signers(&quorum, session, msg).await;
+            self.signing_sessions.insert(
+                session,
+                SigningSession::new(quorum, batch, login, messages, base, commitments, e),
+            );
+        }
+
+        self.refill_presignatures().await;
+    }
+
+    /// Starts computing a new presignature batch with a live quorum once the pool runs low.
+    /// One batch is computed at a time.
+    async fn refill_presignatures(&mut self) {
+        if !self.presign_sessions.is_empty()
+            || self.public_key.is_none()
+            || self.refreshing
+            || self.resharing.is_some()
+        {
+            return;
+        }
+        let live = self.live_signers();
+        let available = self
+            .presignatures
+            .values()
+            .filter(|batch| batch.quorum.is_subset(&live))
+            .map(|batch| batch.batch_size - batch.next_index)
+            .sum::<u32>();
+        if available >= PRESIGNATURE_LOW_WATER + self.pending_logins.len() as u32 {
+            return;
+        }
+
+        let Some(quorum) = self.pick_quorum() else {
+            eprintln!(
+                "Fewer than {} signers are live, not computing presignatures",
+                self.threshold_signers
+            );
+            return;
+        };
+        let session = self.new_session();
+
+        println!(
+            "Session {}: sending round 1 request to {:?}...",
+            session, quorum
+        );
+        self.fn1_timer.start();
+        let msg = Message::Round1Request {
+            batch_size: SIG_BATCH_SIZE,
+            quorum: quorum.iter().copied().collect(),
+        };
+        self.send_to_signers(&quorum, session, msg).await;
+        self.presign_sessions
+            .insert(session, PresignSession::new(quorum));
+    }
+
     pub async fn process_round1_final_response(
         &mut self,
+        session: SessionId,
         sender: ParticipantId,
-        round1: Phase1Output<Fr>,
     ) {
-        self.expected_sk += round1.masked_signing_key_shares.iter().sum::<Fr>();
+        let Some(state) = self.presign_sessions.get_mut(&session) else {
+            return;
+        };
+        if !state.quorum.contains(&sender) {
+            return;
+        }
+        state.round1_done.insert(sender);
 
-        self.round1outs.insert(sender, round1);
+        // Phase 2 follows on the signers without a request from the AS
+        if state.round1_done == state.quorum {
+            state.deadline = round_deadline();
+            self.fn1_timer.stop_and_print_ms();
+            self.fn2_timer.start();
+        }
+    }
 
-        if self.round1outs.len() as u16 == self.threshold_signers {
-            self.initiate_round2().await;
+    pub async fn process_round2_final_response(
+        &mut self,
+        session: SessionId,
+        sender: ParticipantId,
+        commitments: BatchCommitments,
+    ) {
+        let Some(state) = self.presign_sessions.get_mut(&session) else {
+            return;
+        };
+        if !state.quorum.contains(&sender) {
+            return;
+        }
+        let batch_size = SIG_BATCH_SIZE as usize;
+        if [
+            commitments.r.len(),
+            commitments.r_g1.len(),
+            commitments.u.len(),
+            commitments.e.len(),
+        ]
+        .iter()
+        .any(|len| *len != batch_size)
+        {
+            let report = BlameReport {
+                session,
+                culprit: Some(sender),
+                reporter: self.config.node_id,
+                reason: format!(
+                    "{} presignature commitments and {} e for a batch of {}",
+                    commitments.r.len(),
+                    commitments.e.len(),
+                    SIG_BATCH_SIZE
+                ),
+                verified: true,
+            };
+            self.abort_presign_session(session, report).await;
+            return;
+        }
+        state.round2_done.insert(sender, commitments);
+
+        if state.round2_done.len() == state.quorum.len() {
+            self.fn2_timer.stop_and_print_ms();
+            let state = self.presign_sessions.remove(&session).unwrap();
+            let checked = agreed_e(&state.round2_done).and_then(|e| {
+                let public_key = self
+                    .public_key
+                    .as_ref()
+                    .ok_or((None, "No public key to check the batch with".to_string()))?;
+                check_batch(&state.round2_done, &e, &public_key.0, &self.params)?;
+                Ok(e)
+            });
+            let e = match checked {
+                Ok(e) => e,
+                Err((culprit, reason)) => {
+                    if culprit.is_none() {
+                        self.suspect(&state.quorum);
+                    }
+                    let report = BlameReport {
+                        session,
+                        culprit,
+                        reporter: self.config.node_id,
+                        reason,
+                        verified: true,
+                    };
+                    self.presign_sessions.insert(session, state);
+                    self.abort_presign_session(session, report).await;
+                    return;
+                }
+            };
+
+            // The batch is named after the session that computed it
+            println!("Session {}: presignature batch ready", session);
+            self.presignatures.insert(
+                session,
+                PresignatureBatch {
+                    quorum: state.quorum,
+                    batch_size: SIG_BATCH_SIZE,
+                    next_index: 0,
+                    commitments: state.round2_done,
+                    e,
+                },
+            );
+            // The signers keep the same number of batches and also drop the oldest
+            while self.presignatures.len() > PRESIGNATURE_MAX_BATCHES {
+                self.presignatures.pop_first();
+            }
+
+            self.serve_logins().await;
         }
     }
 
-    async fn complete_round2(&mut self) {
-        let ote_params = MultiplicationOTEParams::<KAPPA, STATISTICAL_SECURITY_PARAMETER> {};
-        let gadget_vector = GadgetVector::<Fr, KAPPA, STATISTICAL_SECURITY_PARAMETER>::new::<
-            Blake2b512,
-        >(ote_params, b"test-gadget-vector");
+    /// Aggregates the signature shares and checks the signature. A failed login is retried
+    /// with another presignature, without the culprit when one is known.
+    async fn complete_signing(&mut self, session: SessionId, state: SigningSession) {
+        let sig = match aggregate_shares(&state.shares) {
+            Ok(sig) => sig,
+            Err((culprit, reason)) => {
+                self.fail_signing(session, state, culprit, reason).await;
+                return;
+            }
+        };
+        state.token_issue_timer.stop_and_print_ms();
 
-        let mut all_msg_2s = vec![];
+        let Some(public_key) = self.public_key.clone() else {
+            self.reply_error(
+                &state.login,
+                "No public key to verify the signature with".into(),
+            )
+            .await;
+            return;
+        };
 
-        for sender_id in 1..=self.threshold_signers {
-            // consume to avoid reprocessing
-            let msg_1s = self.all_msg_1s.get(&sender_id).unwrap();
-            for (receiver_id, m) in msg_1s {
-                let m2 = self
-                    .round2s
-                    .get_mut(&receiver_id)
-                    .unwrap()
-                    .receive_message1::<Blake2b512>(sender_id, m.clone(), &gadget_vector)
-                    .unwrap();
+        state.token_verify_timer.start();
+        if let Err(err) = sig.verify(&state.messages, public_key, self.params.clone()) {
+            // Any share of the quorum could be the bad one
+            let reason = format!("Signature verification failed: {:?}", err);
+            self.fail_signing(session, state, None, reason).await;
+        } else {
+            if let Err(e) = decode_token(&state.messages, &state.login.attributes) {
+                self.reply_error(&state.login, format!("Token does not decode: {}", e))
+                    .await;
+                return;
+            }
+            state.token_verify_timer.stop_and_print_ms();
+            self.on_complete(&state.token_issue_timer, &state.token_verify_timer)
+                .await;
+            println!("Signature verified successfully");
+            let encoded = Encoder::encode_signature(&sig)
+                .map_err(|e| e.to_string())
+                .and_then(|signature| {
+                    serde_json::to_string(&state.login.attributes)
+                        .map(|attributes| (attributes, signature))
+                        .map_err(|e| e.to_string())
+                });
+            match encoded {
+                Ok((attributes, signature)) => {
+                    self.reply(
+                        &state.login,
+                        Message::LoginResponse {
+                            attributes,
+                            signature,
+                        },
+                    )
+                    .await
+                }
+                Err(e) => {
+                    self.reply_error(&state.login, format!("Failed to encode token: {}", e))
+                        .await
+                }
+            }
+        }
+    }
 
-                // Store as (target_id = sender_id, from_id = receiver_id, m2)
-                all_msg_2s.push((receiver_id, sender_id, m2));
+    /// Sends `msg` to the client of `login`, if it is still connected.
+    async fn reply(&self, login: &Login, msg: Message) {
+        let Some(client) = &login.client else {
+            return;
+        };
+        let payload = Payload {
+            sender: self.config.node_id,
+            session: NO_SESSION,
+            msg,
+        };
+        let client = client.clone();
+        tokio::spawn(async move {
+            if let Err(e) = send_message(&mut *client.lock().await, &payload).await {
+                eprintln!("Failed to reply to client: {}", e);
             }
+        });
+    }
+
+    async fn reply_error(&self, login: &Login, reason: String) {
+        eprintln!("Login failed: {}", reason);
+        self.reply(login, Message::LoginError { reason }).await;
+    }
+
+    /// Retries a failed login, or gives up on it after `MAX_LOGIN_ATTEMPTS` or past its
+    /// deadline.
+    async fn retry_login(&mut self, session: SessionId, mut login: Login) {
+        login.attempts += 1;
+        if login.attempts >= MAX_LOGIN_ATTEMPTS {
+            let reason = format!(
+                "Session {}: giving up on the login after {} attempts",
+                session, MAX_LOGIN_ATTEMPTS
+            );
+            self.reply_error(&login, reason).await;
+        } else if login.deadline <= Instant::now() {
+            self.reply_error(&login, "Login timed out".to_string())
+                .await;
+        } else {
+            self.pending_logins.push_back(login);
         }
+    }
 
-        for (sender_id, receiver_id, m2) in all_msg_2s {
-            self.round2s
-                .get_mut(&receiver_id)
-                .unwrap()
-                .receive_message2::<Blake2b512>(*sender_id, m2, &gadget_vector)
-                .unwrap();
+    async fn fail_signing(
+        &mut self,
+        session: SessionId,
+        state: SigningSession,
+        culprit: Option<ParticipantId>,
+        reason: String,
+    ) {
+        self.blame(BlameReport {
+            session,
+            culprit,
+            reporter: self.config.node_id,
+            reason,
+            verified: true,
+        })
+        .await;
+        // Without a culprit every signer of the quorum is suspect. The retry prefers other
+        // signers, and the other batches of this quorum are dropped too.
+        if culprit.is_none() {
+            self.suspect(&state.quorum);
+            self.presignatures
+                .retain(|_, batch| batch.quorum != state.quorum);
         }
+        self.presignatures.remove(&state.batch);
 
-        let round2_outputs: BTreeMap<ParticipantId, _> = self
-            .round2s
-            .drain()
-            .map(|(id, p)| (id, p.finish()))
-            .collect();
+        // A named culprit is excluded, so the login goes first to a batch or refill whose
+        // quorum has a spare signer in its place. That is not an attempt of the login.
+        match culprit {
+            Some(_) if state.login.deadline > Instant::now() => {
+                self.pending_logins.push_front(state.login)
+            }
+            _ => self.retry_login(session, state.login).await,
+        }
+        self.serve_logins().await;
+    }
 
-        self.token_issue_timer.start();
-        let mut shares = vec![];
-        for i in 1..=self.threshold_signers {
-            let share = BBSSignatureShare::new(
-                &self.messages,
-                0,
-                self.round1outs.get(&i).unwrap(),
-                round2_outputs.get(&i).unwrap(),
-                &self.params,
-            )
-            .unwrap();
-            shares.push(share);
-            if i == self.threshold_signers {
-                self.token_issue_timer.stop_and_print_ms();
+    pub async fn process_sign_response(
+        &mut self,
+        session: SessionId,
+        sender: ParticipantId,
+        share: BBSSignatureShare<Bls12_381>,
+    ) {
+        let Some(state) = self.signing_sessions.get_mut(&session) else {
+            return;
+        };
+        if !state.quorum.contains(&sender) {
+            return;
+        }
+        // Token issuance spans from the first signature share to the aggregated signature
+        if state.shares.is_empty() {
+            state.token_issue_timer.start();
+        }
+        if let Err(reason) = check_share(state, sender, &share, &self.params) {
+            // The login goes on right away with a presignature of signers without the culprit
+            if let Some(state) = self.signing_sessions.remove(&session) {
+                self.fail_signing(session, state, Some(sender), reason)
+                    .await;
             }
+            return;
         }
+        state.shares.insert(sender, share);
 
-        let sig = BBSSignatureShare::aggregate(shares).unwrap();
+        if state.shares.len() == state.quorum.len() {
+            // The session is over, whether or not the signature verifies
+            if let Some(state) = self.signing_sessions.remove(&session) {
+                self.complete_signing(session, state).await;
+            }
+        }
+    }
 
-        self.token_verify_timer.start();
-        if let Err(err) = sig.verify(&self.messages, self.public_key.clone(), self.params.clone()) {
-            eprintln!("Signature verification failed: {:?}", err);
-        } else {
-            self.token_verify_timer.stop_and_print_ms();
-            self.on_complete().await;
-            println!("Signature verified successfully");
+    /// A signature share from `sender` that did not even decode.
+    pub async fn process_invalid_sign_response(
+        &mut self,
+        session: SessionId,
+        sender: ParticipantId,
+        reason: String,
+    ) {
+        if !self
+            .signing_sessions
+            .get(&session)
+            .is_some_and(|state| state.quorum.contains(&sender))
+        {
+            return;
+        }
+        if let Some(state) = self.signing_sessions.remove(&session) {
+            self.fail_signing(session, state, Some(sender), reason)
+                .await;
         }
+    }
 
-        // let now = SystemTime::now();
-        // let timestamp = now.duration_since(UNIX_EPOCH).unwrap().as_secs();
-        // let mut file = tokio::fs::File::create(format!(
-        //     "./op/timings_{}_{}.json",
-        //     self.config.message_count, timestamp
-        // ))
-        // .await
-        // .unwrap();
+    /// Handles a signer's report that `culprit` misbehaved in `session`. The session is
+    /// aborted on all its signers and, for a presignature batch, computed again without the
+    /// culprit. Only reports between two signers of the session are taken, and the culprit is
+    /// only kept out if `evidence` proves the report or enough signers made the same one.
+    pub async fn process_blame(
+        &mut self,
+        session: SessionId,
+        reporter: ParticipantId,
+        culprit: ParticipantId,
+        reason: String,
+        evidence: Option<String>,
+    ) {
+        if reporter == culprit {
+            return;
+        }
+        let verified = match evidence.as_deref().map(|evidence| {
+            check_evidence(
+                &self.signing_keys,
+                evidence,
+                session,
+                reporter,
+                culprit,
+                &self.params.g2,
+            )
+        }) {
+            Some(Ok(broken)) => {
+                eprintln!(
+                    "Session {}: evidence against signer {} holds: {}",
+                    session, culprit, broken
+                );
+                true
+            }
+            Some(Err(e)) => {
+                eprintln!(
+                    "Session {}: evidence against signer {} proves nothing: {}",
+                    session, culprit, e
+                );
+                false
+            }
+            None => false,
+        };
+        let report = BlameReport {
+            session,
+            culprit: Some(culprit),
+            reporter,
+            reason,
+            verified,
+        };
 
-        // let timings = json!({
-        //     "fn1": self.fn1_timer.get_duration(),
-        //     "fn2": self.fn2_timer.get_duration(),
-        //     "token_issue": self.token_issue_timer.get_duration(),
-        //     "token_verify": self.token_verify_timer.get_duration(),
-        // });
-        // file.write_all(timings.to_string().as_bytes())
-        //     .await
-        //     .unwrap();
+        if let Some(state) = self.presign_sessions.get(&session) {
+            if !state.quorum.contains(&reporter) || !state.quorum.contains(&culprit) {
+                return;
+            }
+            let state = self.presign_sessions.remove(&session).unwrap();
+            self.send_to_signers(&state.quorum, session, Message::Abort)
+                .await;
+            self.accuse(report).await;
+            self.serve_logins().await;
+        } else if session == self.key_session && session != NO_SESSION {
+            let mut signers = self.committee.clone();
+            if let Some((committee, _)) = &self.resharing {
+                signers.extend(committee);
+            }
+            if !signers.contains(&reporter) || !signers.contains(&culprit) {
+                return;
+            }
+            // Keygen and refresh need every committee member, so they are not retried. The
+            // report tells the operator whom to reshare away from.
+            self.abort_key_session().await;
+            self.accuse(report).await;
+            self.serve_logins().await;
+        } else if session == NO_SESSION {
+            // Base OT runs outside any session
+            if self.committee.contains(&reporter) && self.committee.contains(&culprit) {
+                self.accuse(report).await;
+            }
+        }
     }
 
-    pub async fn process_round2_response(
+    /// Handles a signer's report that it could not go on with `session`. Like a timeout, but
+    /// without the wait: the session is aborted on its other signers and the sender counts as
+    /// suspect, since the failure is on its side but need not be malicious.
+    pub async fn process_error(
         &mut self,
+        session: SessionId,
         sender: ParticipantId,
-        phase2: Phase2<Fr, 256, 80>,
-        map: BTreeMap<ParticipantId, Message1<Fr>>,
+        reason: String,
     ) {
-        self.round2s.insert(sender, phase2);
-        self.all_msg_1s.insert(sender, map);
+        eprintln!("Session {}: signer {} failed: {}", session, sender, reason);
+        let failed = BTreeSet::from([sender]);
+
+        if self
+            .presign_sessions
+            .get(&session)
+            .is_some_and(|state| state.quorum.contains(&sender))
+        {
+            let state = self.presign_sessions.remove(&session).unwrap();
+            self.send_to_signers(&state.quorum, session, Message::Abort)
+                .await;
+            self.suspect(&failed);
+        } else if self
+            .signing_sessions
+            .get(&session)
+            .is_some_and(|state| state.quorum.contains(&sender))
+        {
+            let state = self.signing_sessions.remove(&session).unwrap();
+            self.send_to_signers(&state.quorum, session, Message::Abort)
+                .await;
+            self.suspect(&failed);
+            self.presignatures
+                .retain(|_, batch| !batch.quorum.contains(&sender));
+            self.retry_login(session, state.login).await;
+        } else if session == self.key_session && session != NO_SESSION {
+            self.abort_key_session().await;
+        }
+        self.serve_logins().await;
+    }
+
+    /// Handles a signer's refusal to sign the token of a signing session. The token is against
+    /// the signer's issuance policy, so the login fails instead of being retried, and the
+    /// refusal is kept along with the envelope the signer signed it in. A refusal is no fault,
+    /// the signer is not suspected.
+    pub async fn process_refusal(
+        &mut self,
+        session: SessionId,
+        signer: ParticipantId,
+        reason: String,
+        envelope: String,
+    ) {
+        if !self
+            .signing_sessions
+            .get(&session)
+            .is_some_and(|state| state.quorum.contains(&signer))
+        {
+            return;
+        }
+        let state = self.signing_sessions.remove(&session).unwrap();
+        self.send_to_signers(&state.quorum, session, Message::Abort)
+            .await;
+
+        let refusal = Refusal {
+            session,
+            signer,
+            attributes: state.login.attributes.clone(),
+            reason: reason.clone(),
+            envelope,
+        };
+        self.refusals.push(refusal);
+        let path = self.output_path(format!("refusals_current_run_{}.json", self.current_run));
+        let written = match serde_json::to_string_pretty(&self.refusals) {
+            Ok(json) => tokio::fs::write(&path, json)
+                .await
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        if let Err(e) = written {
+            eprintln!("Failed to write {}: {}", path.display(), e);
+        }
+
+        let reason = format!("Signer {} refused the token: {}", signer, reason);
+        self.reply_error(&state.login, reason).await;
+        self.serve_logins().await;
+    }
+
+    /// Aborts a presignature session on all its signers because of `report`. Another quorum
+    /// computes the batch instead.
+    async fn abort_presign_session(&mut self, session: SessionId, report: BlameReport) {
+        if let Some(state) = self.presign_sessions.remove(&session) {
+            self.send_to_signers(&state.quorum, session, Message::Abort)
+                .await;
+        }
+        self.blame(report).await;
+        self.serve_logins().await;
+    }
+
+    /// Aborts the running keygen, refresh or resharing on all its signers.
+    async fn abort_key_session(&mut self) {
+        let session = self.key_session;
+        let mut signers = self.committee.clone();
+        if let Some((committee, _)) = &self.resharing {
+            signers.extend(committee);
+        }
+        self.send_to_signers(&signers, session, Message::Abort)
+            .await;
+        self.keygen_done.clear();
+        self.refresh_done.clear();
+        self.new_public_key_shares.clear();
+        self.refreshing = false;
+        self.resharing = None;
+        self.key_session = NO_SESSION;
+        self.key_deadline = None;
+        eprintln!("Session {}: aborted", session);
+    }
+
+    /// Gives up on sessions whose signers stopped answering. A presignature batch is dropped
+    /// and computed again by another quorum, a login is retried with a presignature of signers
+    /// that did answer. Silent signers count as suspect, so the next quorum avoids them.
+    pub async fn check_deadlines(&mut self) {
+        let now = Instant::now();
+
+        let expired = self
+            .presign_sessions
+            .iter()
+            .filter(|(_, state)| state.deadline <= now)
+            .map(|(session, _)| *session)
+            .collect::<Vec<_>>();
+        for session in expired {
+            let state = self.presign_sessions.remove(&session).unwrap();
+            let (round, done) = if state.round1_done == state.quorum {
+                (2, state.round2_done.keys().copied().collect())
+            } else {
+                (1, state.round1_done.clone())
+            };
+            let silent = self.unresponsive(state.quorum.difference(&done).copied().collect());
+            eprintln!(
+                "Session {}: round {} timed out waiting for {:?}",
+                session, round, silent
+            );
+            self.send_to_signers(&state.quorum, session, Message::Abort)
+                .await;
+            self.suspect(&silent);
+        }
+
+        let expired = self
+            .signing_sessions
+            .iter()
+            .filter(|(_, state)| state.deadline <= now)
+            .map(|(session, _)| *session)
+            .collect::<Vec<_>>();
+        for session in expired {
+            let state = self.signing_sessions.remove(&session).unwrap();
+            let silent = self.unresponsive(
+                state
+                    .quorum
+                    .iter()
+                    .filter(|id| !state.shares.contains_key(id))
+                    .copied()
+                    .collect(),
+            );
+            eprintln!(
+                "Session {}: signing timed out waiting for {:?}",
+                session, silent
+            );
+            self.suspect(&silent);
+            self.presignatures
+                .retain(|_, batch| batch.quorum.is_disjoint(&silent));
+            self.retry_login(session, state.login).await;
+        }
+
+        let (expired, pending): (VecDeque<_>, VecDeque<_>) =
+            std::mem::take(&mut self.pending_logins)
+                .into_iter()
+                .partition(|login| login.deadline <= now);
+        self.pending_logins = pending;
+        for login in expired {
+            self.reply_error(&login, "No quorum of signers answered in time".into())
+                .await;
+        }
+
+        if self.key_deadline.is_some_and(|deadline| deadline <= now) && self.key_status.is_some() {
+            // Signers that did not answer might hold the key, so only a stored one is taken
+            self.resume_key().await;
+        } else if self.key_deadline.is_some_and(|deadline| deadline <= now) {
+            eprintln!(
+                "Session {}: timed out waiting for the signers",
+                self.key_session
+            );
+            self.abort_key_session().await;
+        }
+
+        self.serve_logins().await;
+    }
+
+    /// The signers out of `waiting` that stalled a session. Honest signers of a round wait on
+    /// the silent one too, so when some of them stopped sending heartbeats only those count.
+    fn unresponsive(&self, waiting: BTreeSet<ParticipantId>) -> BTreeSet<ParticipantId> {
+        let live = self.live_signers();
+        let stale = waiting.difference(&live).copied().collect::<BTreeSet<_>>();
+        if stale.is_empty() {
+            waiting
+        } else {
+            stale
+        }
+    }
+
+    /// Makes the next quorums prefer other signers than `signers`.
+    fn suspect(&mut self, signers: &BTreeSet<ParticipantId>) {
+        for id in signers {
+            *self.suspicion.entry(*id).or_default() += 1;
+        }
+    }
+
+    /// Takes a signer's blame report. A faulty signer could accuse honest ones until too few
+    /// are left to sign, so an unproven report only makes both sides suspect. The culprit is
+    /// kept out once the report is verified, or once more signers accused it than the
+    /// `threshold_signers - 1` that may be faulty.
+    async fn accuse(&mut self, report: BlameReport) {
+        let Some(culprit) = report.culprit else {
+            return;
+        };
+        let accusers = self.accusations.entry(culprit).or_default();
+        accusers.insert(report.reporter);
+        if report.verified || accusers.len() >= self.threshold_signers as usize {
+            self.blame(report).await;
+        } else {
+            self.suspect(&BTreeSet::from([culprit, report.reporter]));
+            self.record_blame(report).await;
+        }
+    }
+
+    /// Records a verified blame report and keeps the culprit out of new sessions and
+    /// presignatures.
+    async fn blame(&mut self, report: BlameReport) {
+        if let Some(culprit) = report.culprit {
+            self.excluded.insert(culprit);
+            self.presignatures
+                .retain(|_, batch| !batch.quorum.contains(&culprit));
+        }
+        self.record_blame(report).await;
+    }
+
+    /// Keeps a blame report for the operator, along with the earlier ones.
+    async fn record_blame(&mut self, report: BlameReport) {
+        match serde_json::to_string(&report) {
+            Ok(json) => eprintln!("Blame report: {}", json),
+            Err(e) => eprintln!("Failed to serialize blame report: {}", e),
+        }
+        self.blame_reports.push(report);
+
+        let path = self.output_path(format!("blame_current_run_{}.json", self.current_run));
+        let written = match serde_json::to_string_pretty(&self.blame_reports) {
+            Ok(json) => tokio::fs::write(&path, json)
+                .await
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        if let Err(e) = written {
+            eprintln!("Failed to write {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Checks the evidence of a blame report: the envelope `culprit` signed for `reporter` in
+/// `session`, whose message must break a rule of its round that the AS can check on its own.
+/// Those are that the message decodes and that a dealt Feldman share verifies against its
+/// commitments, for the receiver and, in a refresh, as a sharing of zero. Returns the rule
+/// broken, or why the evidence proves nothing.
+fn check_evidence(
+    keys: &SigningKeys,
+    evidence: &str,
+    session: SessionId,
+    reporter: ParticipantId,
+    culprit: ParticipantId,
+    g2: &G2Affine,
+) -> Result<String, String> {
+    let envelope: Envelope =
+        serde_json::from_str(evidence).map_err(|e| format!("Invalid envelope: {}", e))?;
+    if envelope.sender != culprit || envelope.receiver != reporter || envelope.session != session {
+        return Err(format!(
+            "Envelope from {} to {} in session {}",
+            envelope.sender, envelope.receiver, envelope.session
+        ));
+    }
+    let payload = keys.verify(envelope).map_err(|e| e.to_string())?;
+
+    fn undecodable<T, E: std::fmt::Display>(decoded: Result<T, E>) -> Option<String> {
+        decoded.err().map(|e| format!("Does not decode: {}", e))
+    }
+    let broken = match &payload.msg {
+        Message::KeygenShare { share, commitments }
+        | Message::RefreshShare {
+            share, commitments, ..
+        }
+        | Message::ReshareShare {
+            share, commitments, ..
+        } => {
+            match (
+                Encoder::decode_keygen_share(share),
+                Encoder::decode_coeff_commitments(commitments),
+            ) {
+                (Err(e), _) | (_, Err(e)) => Some(format!("Does not decode: {}", e)),
+                (Ok(share), _) if share.id != reporter => {
+                    Some(format!("Share {} dealt to signer {}", share.id, reporter))
+                }
+                (Ok(share), Ok(comms)) => {
+                    if share.verify(&comms, g2).is_err() {
+                        Some("Share does not match the commitments".to_string())
+                    } else if matches!(payload.msg, Message::RefreshShare { .. })
+                        && !comms.commitment_to_secret().is_zero()
+                    {
+                        Some("Refresh shares a secret other than zero".to_string())
+                    } else {
+                        None
+                    }
+                }
+            }
+        }
+        Message::Round1Commitment {
+            commitments,
+            commitment_zero_share,
+        } => undecodable(Encoder::decode_commitments(commitments))
+            .or_else(|| undecodable(Encoder::decode_commitments(commitment_zero_share))),
+        Message::Round1Shares {
+            shares,
+            zero_shares,
+        } => undecodable(Encoder::decode_shares_and_salts(shares))
+            .or_else(|| undecodable(Encoder::decode_shares_and_salts(zero_shares))),
+        Message::BaseOTSenderPubKey { pubkey } => {
+            undecodable(Encoder::decode_base_ot_sender_pubkey(pubkey))
+        }
+        Message::BaseOTReceiverPubKey { pubkey } => {
+            undecodable(Encoder::decode_base_ot_receiver_pubkey(pubkey))
+        }
+        Message::BaseOTChallenges { challenges } => {
+            undecodable(Encoder::decode_base_ot_challenges(challenges))
+        }
+        Message::BaseOTResponses { responses } => {
+            undecodable(Encoder::decode_base_ot_responses(responses))
+        }
+        Message::BaseOTHashedKeys { hashed_keys } => {
+            undecodable(Encoder::decode_base_ot_hashed_keys(hashed_keys))
+        }
+        Message::Round2Message1 { message1 } => undecodable(Encoder::decode_msg1(message1)),
+        Message::Round2Message2 { message2 } => undecodable(Encoder::decode_msg2(message2)),
+        _ => None,
+    };
+    broken.ok_or_else(|| format!("{} passes every check the AS can make", payload.msg.round()))
+}
+
+/// Checks a signature share from `sender` on arrival against what the sender committed to
+/// before signing. Its `e` must be the presignature's `e`, its `R` must be `B * r` for the `r`
+/// the sender committed to, which a pairing checks without knowing `r`, and its `u` must be the
+/// committed one. `check_batch` tied those commitments to the public key shares, so a share
+/// that passes is the sender's part of a valid signature, and one that fails names the sender.
+fn check_share(
+    state: &SigningSession,
+    sender: ParticipantId,
+    share: &BBSSignatureShare<Bls12_381>,
+    params: &SignatureParams23G1<Bls12_381>,
+) -> Result<(), String> {
+    if share.id != sender {
+        return Err(format!(
+            "Share of signer {} claims to be from {}",
+            sender, share.id
+        ));
+    }
+    if share.e != state.e {
+        return Err("e of the signature share does not match the presignature".to_string());
+    }
+    let (r, u) = state.commitments.get(&sender).ok_or(format!(
+        "No commitment to the presignature of signer {}",
+        sender
+    ))?;
+    if Bls12_381::pairing(share.R, params.g2) != Bls12_381::pairing(state.base, *r) {
+        return Err("R of the signature share does not match the presignature".to_string());
+    }
+    if (params.g1 * share.u).into_affine() != *u {
+        return Err("u of the signature share does not match the presignature".to_string());
+    }
+    Ok(())
+}
+
+/// Checks that the public key shares lie on one polynomial of degree `threshold - 1` whose
+/// value at 0 is the public key. The lowest `threshold` shares must interpolate to the public
+/// key, and every other share must too together with the lowest `threshold - 1`.
+fn check_public_key_shares(
+    shares: &BTreeMap<ParticipantId, G2Affine>,
+    public_key: &G2Affine,
+    threshold: u16,
+) -> Result<(), (Option<ParticipantId>, String)> {
+    let interpolate = |ids: &[ParticipantId]| -> Result<G2Affine, String> {
+        let mut sum = G2Projective::zero();
+        for id in ids {
+            let lagrange = lagrange_basis_at_0::<Fr>(ids, *id).map_err(|e| format!("{:?}", e))?;
+            sum += shares[id] * lagrange;
+        }
+        Ok(sum.into_affine())
+    };
+
+    let ids = shares.keys().copied().collect::<Vec<_>>();
+    let threshold = threshold as usize;
+    if ids.len() < threshold {
+        return Err((None, format!("Only {} public key shares", ids.len())));
+    }
+    if interpolate(&ids[..threshold]).map_err(|e| (None, e))? != *public_key {
+        return Err((
+            None,
+            format!(
+                "Public key shares of {:?} do not match the public key",
+                &ids[..threshold]
+            ),
+        ));
+    }
+    for id in &ids[threshold..] {
+        let mut subset = ids[..threshold - 1].to_vec();
+        subset.push(*id);
+        if interpolate(&subset).map_err(|e| (None, e))? != *public_key {
+            return Err((
+                Some(*id),
+                "Public key share does not match the public key".to_string(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// The public key most signers of a keygen derived. If the keys differ, the signers whose
+/// key is not that of a majority are named, or none when no key has a majority.
+fn agreed_public_key(
+    keys: &BTreeMap<ParticipantId, PublicKeyG2<Bls12_381>>,
+) -> Result<PublicKeyG2<Bls12_381>, BTreeSet<ParticipantId>> {
+    let majority = keys
+        .values()
+        .find(|key| keys.values().filter(|other| other == key).count() * 2 > keys.len());
+    match majority {
+        Some(key) if keys.values().all(|other| other == key) => Ok(key.clone()),
+        Some(key) => Err(keys
+            .iter()
+            .filter(|(_, other)| *other != key)
+            .map(|(id, _)| *id)
+            .collect()),
+        None => Err(BTreeSet::new()),
+    }
+}
+
+/// The `e` of every presignature of a batch, which comes from a coin toss among the quorum and
+/// so is the same for all its signers. A single signer that disagrees with all others is named.
+fn agreed_e(
+    reported: &BTreeMap<ParticipantId, BatchCommitments>,
+) -> Result<Vec<Fr>, (Option<ParticipantId>, String)> {
+    let mut votes = HashMap::<&[Fr], Vec<ParticipantId>>::new();
+    for (id, commitments) in reported {
+        votes.entry(commitments.e.as_slice()).or_default().push(*id);
+    }
+    match votes.len() {
+        0 => Err((None, "No presignature e".to_string())),
+        1 => Ok(votes.into_keys().next().unwrap().to_vec()),
+        2 => {
+            let mut sides = votes.into_values().collect::<Vec<_>>();
+            sides.sort_by_key(|ids| ids.len());
+            let culprit = (sides[0].len() == 1 && sides[1].len() > 1).then(|| sides[0][0]);
+            Err((
+                culprit,
+                "Signers disagree on the presignature e".to_string(),
+            ))
+        }
+        _ => Err((None, "Signers disagree on the presignature e".to_string())),
+    }
+}
+
+/// Checks the quorum's commitments to a batch before any of it is used. A signer's
+/// commitments to `r` in G1 and G2 must be to the same `r`, which names the signer if not.
+/// Then for every presignature the `u` of the quorum must add up to `r * (e + sk)` for the
+/// committed `r`, which a pairing with the public key checks. The public key is what the
+/// public key shares of keygen interpolate to, so a `u` commitment that does not fit the key
+/// share of its signer fails this. It cannot say which signer's it is though.
+fn check_batch(
+    reported: &BTreeMap<ParticipantId, BatchCommitments>,
+    e: &[Fr],
+    public_key: &G2Affine,
+    params: &SignatureParams23G1<Bls12_381>,
+) -> Result<(), (Option<ParticipantId>, String)> {
+    for (id, commitments) in reported {
+        let same_r = commitments
+            .r
+            .iter()
+            .zip(&commitments.r_g1)
+            .all(|(r, r_g1)| {
+                Bls12_381::pairing(r_g1, params.g2) == Bls12_381::pairing(params.g1, r)
+            });
+        if !same_r {
+            return Err((
+                Some(*id),
+                "Commitments to r in G1 and G2 do not match".to_string(),
+            ));
+        }
+    }
+    for (index, e) in e.iter().enumerate() {
+        let (r, u) = reported.values().fold(
+            (G1Projective::zero(), G1Projective::zero()),
+            |(r, u), commitments| (r + commitments.r_g1[index], u + commitments.u[index]),
+        );
+        let key = params.g2 * e + public_key;
+        if Bls12_381::pairing(u, params.g2) != Bls12_381::pairing(r, key) {
+            return Err((
+                None,
+                format!(
+                    "Commitments to u of presignature {} do not match the public key",
+                    index
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Decodes the signed messages of a token like a verifier of it would, and checks that they
+/// hold exactly the claims of `attributes`.
+fn decode_token(messages: &[Fr], attributes: &TokenAttributes) -> Result<(), String> {
+    let revealed = messages.iter().copied().enumerate().collect();
+    let claims = attributes.claims();
+    let decoded = TokenAttributes::decode(&revealed, &claims).map_err(|e| e.to_string())?;
+    match decoded
+        .iter()
+        .find(|(name, value)| value.as_ref() != claims.get(*name))
+    {
+        Some((name, _)) => Err(format!("Attribute {} is not the one signed", name)),
+        None => Ok(()),
+    }
+}
+
+/// Adds up the signature shares like `BBSSignatureShare::aggregate`, but never panics and
+/// names the signer whose share does not fit. All shares of a presignature agree on `e`, so
+/// a share that disagrees with the others is the bad one.
+fn aggregate_shares(
+    shares: &HashMap<ParticipantId, BBSSignatureShare<Bls12_381>>,
+) -> Result<Signature23G1<Bls12_381>, (Option<ParticipantId>, String)> {
+    let mut votes = HashMap::<Fr, usize>::new();
+    for share in shares.values() {
+        *votes.entry(share.e).or_default() += 1;
+    }
+    let (e, count) = votes
+        .iter()
+        .max_by_key(|(_, count)| **count)
+        .map(|(e, count)| (*e, *count))
+        .ok_or((None, "No signature shares".to_string()))?;
+    if count * 2 <= shares.len() && votes.len() > 1 {
+        return Err((None, "Signature shares disagree on e".to_string()));
+    }
+    if let Some((sender, _)) = shares.iter().find(|(_, share)| share.e != e) {
+        return Err((
+            Some(*sender),
+            "Signature share with a different e".to_string(),
+        ));
+    }
+
+    let sum_u = shares.values().map(|share| share.u).sum::<Fr>();
+    let sum_r = shares.values().map(|share| share.R).sum::<G1Projective>();
+    let inverse = sum_u
+        .inverse()
+        .ok_or((None, "Signature shares add up to u = 0".to_string()))?;
+    Ok(Signature23G1 {
+        A: (sum_r * inverse).into_affine(),
+        e,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helper::message::ParticipantId;
+    use ark_std::UniformRand;
+
+    /// Writes the blame reports to `output_dir`.
+    fn config(output_dir: &Path) -> Config {
+        Config {
+            node_id: 0,
+            total_nodes: 4,
+            message_count: 2,
+            threshold_signers: 2,
+            signers: 3,
+            current_run: 0,
+            trusted_dealer: false,
+            refresh_interval: 0,
+            deployment_id: "test".into(),
+            rng_seed: None,
+            keystore_path: String::new(),
+            keystore_passphrase: None,
+            audit_log_path: String::new(),
+            output_dir: output_dir.to_string_lossy().into_owned(),
+            node_keys_dir: String::new(),
+            token_lifetime: 60,
+            policy_max_lifetime: 60,
+            policy_audiences: vec![],
+            policy_require_credential: false,
+            policy_quota: 0,
+            policy_quota_window: 0,
+        }
+    }
 
-        self.fn2_timer.stop_and_print_ms();
+    /// Signing keys of nodes 0 to 3, written to a fresh directory for `test`, which also takes
+    /// the output of the AS.
+    fn key_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "verisso-auth-service-{}-{}",
+            test,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        SigningKeys::generate(&mut StdRng::seed_from_u64(0), &dir, 4).unwrap();
+        dir
+    }
+
+    fn node_keys(dir: &PathBuf, id: ParticipantId) -> SigningKeys {
+        SigningKeys::load(dir, id, 4, "test").unwrap()
+    }
+
+    /// An AS with signers 1 to 3, of which 2 must sign.
+    fn service(dir: &PathBuf) -> AuthenticationService {
+        AuthenticationService::init(
+            config(dir),
+            2,
+            Arc::new(Mutex::new(HashMap::new())),
+            Arc::new(node_keys(dir, 0)),
+        )
+    }
+
+    /// The envelope `sender` signed for `receiver` outside any session, as `receiver` would
+    /// hand it in.
+    fn evidence(
+        dir: &PathBuf,
+        sender: ParticipantId,
+        receiver: ParticipantId,
+        msg: Message,
+    ) -> String {
+        let payload = Payload {
+            sender,
+            session: NO_SESSION,
+            msg,
+        };
+        let envelope = node_keys(dir, sender).seal(receiver, 1, &payload).unwrap();
+        serde_json::to_string(&envelope).unwrap()
+    }
+
+    fn clean_up(dir: PathBuf) {
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn undecodable_pubkey() -> Message {
+        Message::BaseOTSenderPubKey {
+            pubkey: "not a key".into(),
+        }
+    }
+
+    #[tokio::test]
+    async fn does_not_exclude_on_a_false_accusation() {
+        let dir = key_dir("false");
+        let mut service = service(&dir);
+
+        // No evidence at all
+        service
+            .process_blame(NO_SESSION, 1, 2, "Bad message".into(), None)
+            .await;
+        // Evidence signed by the accuser itself, claiming to come from the culprit
+        let mut forged: Envelope =
+            serde_json::from_str(&evidence(&dir, 1, 1, undecodable_pubkey())).unwrap();
+        forged.sender = 2;
+        service
+            .process_blame(
+                NO_SESSION,
+                1,
+                2,
+                "Bad message".into(),
+                Some(serde_json::to_string(&forged).unwrap()),
+            )
+            .await;
+        // A message of the culprit that is fine
+        let fine = evidence(&dir, 2, 1, Message::BaseOTRestart);
+        service
+            .process_blame(NO_SESSION, 1, 2, "Bad message".into(), Some(fine))
+            .await;
+
+        assert!(service.excluded.is_empty());
+        assert!(service.blame_reports.iter().all(|report| !report.verified));
+        // Both sides are suspect, so the next quorum avoids them
+        assert_eq!(service.suspicion[&1], 3);
+        assert_eq!(service.suspicion[&2], 3);
+        clean_up(dir);
+    }
+
+    #[tokio::test]
+    async fn excludes_on_verified_evidence() {
+        let dir = key_dir("verified");
+        let mut service = service(&dir);
+
+        // Evidence sent to someone else proves nothing about the reporter's session
+        let misdirected = evidence(&dir, 2, 3, undecodable_pubkey());
+        service
+            .process_blame(NO_SESSION, 1, 2, "Bad key".into(), Some(misdirected))
+            .await;
+        assert!(service.excluded.is_empty());
+
+        let bad = evidence(&dir, 2, 1, undecodable_pubkey());
+        service
+            .process_blame(NO_SESSION, 1, 2, "Bad key".into(), Some(bad))
+            .await;
+        assert_eq!(service.excluded, BTreeSet::from([2]));
+        assert!(service.blame_reports.last().unwrap().verified);
+        assert!(dir.join("blame_current_run_0.json").exists());
+        clean_up(dir);
+    }
+
+    #[tokio::test]
+    async fn excludes_once_enough_signers_accuse() {
+        let dir = key_dir("corroborated");
+        let mut service = service(&dir);
+
+        for _ in 0..2 {
+            service
+                .process_blame(NO_SESSION, 1, 2, "Bad message".into(), None)
+                .await;
+        }
+        assert!(service.excluded.is_empty());
+        // A second accuser is more than the one signer that may be faulty
+        service
+            .process_blame(NO_SESSION, 3, 2, "Bad message".into(), None)
+            .await;
+        assert_eq!(service.excluded, BTreeSet::from([2]));
+        clean_up(dir);
+    }
+
+    /// Commitments of signers 1 and 2 to one presignature with `e` under the key 5, whose
+    /// masked shares are 2 and 3, and each signer's `r` and `u`.
+    fn presignature(
+        params: &SignatureParams23G1<Bls12_381>,
+        e: Fr,
+    ) -> (BTreeMap<ParticipantId, BatchCommitments>, Vec<(Fr, Fr)>) {
+        let mut rng = StdRng::seed_from_u64(0);
+        let r = [Fr::rand(&mut rng), Fr::rand(&mut rng)];
+        let cross = Fr::rand(&mut rng);
+        let u1 = r[0] * (e + Fr::from(2u64)) + cross;
+        let u2 = (r[0] + r[1]) * (e + Fr::from(5u64)) - u1;
+        let secrets = vec![(r[0], u1), (r[1], u2)];
+        let commitments = secrets
+            .iter()
+            .zip(1..)
+            .map(|((r, u), id)| {
+                let commitments = BatchCommitments {
+                    r: vec![(params.g2 * r).into_affine()],
+                    r_g1: vec![(params.g1 * r).into_affine()],
+                    u: vec![(params.g1 * u).into_affine()],
+                    e: vec![e],
+                };
+                (id, commitments)
+            })
+            .collect();
+        (commitments, secrets)
+    }
+
+    #[test]
+    fn checks_batch_commitments_against_the_public_key() {
+        let params = SignatureParams23G1::<Bls12_381>::new::<Blake2b512>(b"test", 2);
+        let public_key = (params.g2 * Fr::from(5u64)).into_affine();
+        let e = Fr::from(7u64);
+        let (commitments, _) = presignature(&params, e);
+        assert!(check_batch(&commitments, &[e], &public_key, &params).is_ok());
+
+        let other_key = (params.g2 * Fr::from(6u64)).into_affine();
+        let (culprit, _) = check_batch(&commitments, &[e], &other_key, &params).unwrap_err();
+        assert_eq!(culprit, None);
+
+        let mut bad_u = commitments.clone();
+        let u = bad_u.get_mut(&2).unwrap();
+        u.u[0] = (u.u[0] + params.g1).into_affine();
+        let (culprit, _) = check_batch(&bad_u, &[e], &public_key, &params).unwrap_err();
+        assert_eq!(culprit, None);
+
+        let mut bad_r = commitments;
+        let r = bad_r.get_mut(&1).unwrap();
+        r.r_g1[0] = (r.r_g1[0] + params.g1).into_affine();
+        let (culprit, _) = check_batch(&bad_r, &[e], &public_key, &params).unwrap_err();
+        assert_eq!(culprit, Some(1));
+    }
+
+    #[test]
+    fn checks_r_and_u_of_signature_shares() {
+        let params = SignatureParams23G1::<Bls12_381>::new::<Blake2b512>(b"test", 2);
+        let e = Fr::from(7u64);
+        let (commitments, secrets) = presignature(&params, e);
+        let messages = vec![Fr::from(1u64), Fr::from(2u64)];
+        let base = params.b(messages.iter().enumerate()).unwrap().into_affine();
+        let attributes = TokenAttributes::new("alice".into(), "app".into(), 1, None, 60);
+        let state = SigningSession::new(
+            BTreeSet::from([1, 2]),
+            1,
+            Login::new(None, attributes),
+            messages,
+            base,
+            commitments
+                .iter()
+                .map(|(id, commitments)| (*id, (commitments.r[0], commitments.u[0])))
+                .collect(),
+            e,
+        );
+        let (r, u) = secrets[0];
+        let share = BBSSignatureShare {
+            id: 1,
+            e,
+            u,
+            R: (base * r).into_affine(),
+        };
+        assert!(check_share(&state, 1, &share, &params).is_ok());
+        // Only signer 1 committed to this share
+        assert!(check_share(&state, 2, &share, &params).is_err());
+
+        let bad_u = BBSSignatureShare {
+            u: u + Fr::from(1u64),
+            ..share.clone()
+        };
+        assert!(check_share(&state, 1, &bad_u, &params).is_err());
+        let bad_r = BBSSignatureShare {
+            R: (base * (r + Fr::from(1u64))).into_affine(),
+            ..share.clone()
+        };
+        assert!(check_share(&state, 1, &bad_r, &params).is_err());
+        let bad_e = BBSSignatureShare {
+            e: e + Fr::from(1u64),
+            ..share
+        };
+        assert!(check_share(&state, 1, &bad_e, &params).is_err());
+    }
+
+    #[test]
+    fn decodes_tokens_back_into_their_claims() {
+        let attributes =
+            TokenAttributes::new("alice".into(), "app".into(), 1, Some("proof".into()), 60);
+        let messages = attributes.to_messages(8).unwrap();
+        assert!(decode_token(&messages, &attributes).is_ok());
+
+        let other = TokenAttributes {
+            expires_at: attributes.expires_at + 1,
+            ..attributes.clone()
+        };
+        assert!(decode_token(&messages, &other).is_err());
+        let without_proof = TokenAttributes {
+            credential_proof: None,
+            ..attributes.clone()
+        };
+        assert!(decode_token(&messages, &without_proof).is_err());
+
+        let mut past_the_schema = messages;
+        past_the_schema[7] = Fr::from(1u64);
+        assert!(decode_token(&past_the_schema, &attributes).is_err());
+    }
+
+    fn key(secret: u64) -> PublicKeyG2<Bls12_381> {
+        let params = SignatureParams23G1::<Bls12_381>::new::<Blake2b512>(b"test", 2);
+        PublicKeyG2((params.g2 * Fr::from(secret)).into_affine())
+    }
+
+    #[tokio::test]
+    async fn finishes_keygen_with_the_committee_only() {
+        let dir = key_dir("keygen");
+        let mut service = service(&dir);
+        service.send_keygen_request().await;
+        let session = service.key_session;
+
+        // Node 4 is no member of the committee 1 to 3
+        for sender in [1, 2, 4] {
+            service
+                .process_keygen_response(session, sender, key(5), key(sender as u64))
+                .await;
+        }
+        assert!(service.public_key.is_none());
+        service
+            .process_keygen_response(session, 3, key(5), key(3))
+            .await;
+        assert_eq!(service.public_key, Some(key(5)));
+        clean_up(dir);
+    }
+
+    #[tokio::test]
+    async fn aborts_keygen_on_different_public_keys() {
+        let dir = key_dir("keygen-mismatch");
+        let mut service = service(&dir);
+        service.send_keygen_request().await;
+        let session = service.key_session;
+        for (sender, secret) in [(1, 5), (2, 5), (3, 6)] {
+            service
+                .process_keygen_response(session, sender, key(secret), key(sender as u64))
+                .await;
+        }
+        assert!(service.public_key.is_none());
+        assert_eq!(service.key_session, NO_SESSION);
+        assert!(service.key_deadline.is_none());
+        assert_eq!(service.excluded, BTreeSet::from([3]));
+
+        // Without a majority nobody can be blamed
+        let mut service = self::service(&dir);
+        service.send_keygen_request().await;
+        let session = service.key_session;
+        for (sender, secret) in [(1, 5), (2, 6), (3, 7)] {
+            service
+                .process_keygen_response(session, sender, key(secret), key(sender as u64))
+                .await;
+        }
+        assert_eq!(service.key_session, NO_SESSION);
+        assert!(service.excluded.is_empty());
+        clean_up(dir);
+    }
+
+    /// An AS that has the key 5.
+    fn service_with_key(dir: &PathBuf) -> AuthenticationService {
+        let mut service = service(dir);
+        service.public_key = Some(key(5));
+        service
+    }
+
+    /// A batch held by `quorum` with `left` presignatures not handed out yet.
+    fn batch(quorum: BTreeSet<ParticipantId>, left: u32) -> PresignatureBatch {
+        PresignatureBatch {
+            quorum,
+            batch_size: SIG_BATCH_SIZE,
+            next_index: SIG_BATCH_SIZE - left,
+            commitments: BTreeMap::new(),
+            e: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn refills_presignatures_at_the_low_water_mark() {
+        let dir = key_dir("refill");
+        let mut service = service_with_key(&dir);
+        for id in [1, 2] {
+            service.last_seen.insert(id, Instant::now());
+        }
+        service
+            .presignatures
+            .insert(1, batch(BTreeSet::from([1, 2]), 1));
+        service
+            .presignatures
+            .insert(2, batch(BTreeSet::from([1, 2]), PRESIGNATURE_LOW_WATER - 1));
+        service.refill_presignatures().await;
+        assert!(service.presign_sessions.is_empty());
+
+        // Signer 3 is not live, so its batch does not count
+        service.presignatures.get_mut(&1).unwrap().next_index += 1;
+        service
+            .presignatures
+            .insert(3, batch(BTreeSet::from([1, 3]), 4));
+        service.refill_presignatures().await;
+        assert_eq!(service.presign_sessions.len(), 1);
+        let session = service.presign_sessions.values().next().unwrap();
+        assert_eq!(session.quorum, BTreeSet::from([1, 2]));
+
+        // One batch is computed at a time
+        service.refill_presignatures().await;
+        assert_eq!(service.presign_sessions.len(), 1);
+        clean_up(dir);
+    }
+
+    #[tokio::test]
+    async fn finishes_refresh_with_the_committee_only() {
+        let dir = key_dir("refresh");
+        let mut service = service_with_key(&dir);
+        service.send_refresh_request().await;
+        let session = service.key_session;
+        for sender in [1, 2, 4] {
+            service
+                .process_refresh_response(session, sender, 1, key(sender as u64))
+                .await;
+        }
+        assert!(service.refreshing);
+        assert_eq!(service.epoch, 0);
+        service
+            .process_refresh_response(session, 3, 1, key(3))
+            .await;
+        assert!(!service.refreshing);
+        assert_eq!(service.epoch, 1);
+        clean_up(dir);
+    }
 
-        if self.round2s.len() as u16 == self.threshold_signers {
-            self.complete_round2().await;
+    #[tokio::test]
+    async fn finishes_resharing_with_the_old_and_new_committee_only() {
+        let dir = key_dir("reshare");
+        let mut service = service_with_key(&dir);
+        // From signers 1 to 3 to signers 2 to 4
+        service.resharing = Some((BTreeSet::from([2, 3, 4]), 2));
+        service.key_session = service.new_session();
+        let session = service.key_session;
+        for sender in [1, 2, 3, 5] {
+            service
+                .process_reshare_response(session, sender, 1, Some(key(sender as u64)))
+                .await;
         }
+        assert!(service.resharing.is_some());
+        service
+            .process_reshare_response(session, 4, 1, Some(key(4)))
+            .await;
+        assert!(service.resharing.is_none());
+        assert_eq!(service.committee, BTreeSet::from([2, 3, 4]));
+        clean_up(dir);
     }
 }
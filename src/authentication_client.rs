@@ -1,23 +1,132 @@
-use tokio::io::AsyncWriteExt;
-use tokio::net::TcpStream;
+use std::sync::Arc;
 
 mod helper {
     pub mod message;
 }
-use helper::message::{Message, Payload};
+mod envelope;
+mod transport;
+use envelope::{Envelope, EnvelopeWriter, SigningKeys};
+use helper::message::{Message, Payload, CLIENT, NO_SESSION};
+use transport::NodeKeys;
 
 #[tokio::main]
 async fn main() -> tokio::io::Result<()> {
     let addr = std::env::var("SERVER_ADDR").unwrap_or_else(|_| "0.0.0.0:8000".to_string());
-    let mut stream = TcpStream::connect(&addr).await?;
+
+    // REFRESH=true asks the AS to refresh the signers' key shares instead of signing.
+    // RESHARE=1,2,4 with RESHARE_THRESHOLD=2 asks it to reshare the key to that committee.
+    // Otherwise LOGINS=10 logs in that many users at once, each over its own connection, as
+    // user0 to user9 or all as SUBJECT, for AUDIENCE, with CREDENTIAL_PROOF if set.
+    let refresh = std::env::var("REFRESH").is_ok_and(|s| s == "true");
+    let reshare = std::env::var("RESHARE").ok();
+    let msg = if let Some(committee) = reshare {
+        let committee = committee
+            .split(',')
+            .map(|id| id.trim().parse::<u16>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        let threshold = std::env::var("RESHARE_THRESHOLD")
+            .ok()
+            .and_then(|s| s.parse::<u16>().ok())
+            .unwrap_or(committee.len() as u16);
+        Message::Reshare {
+            committee,
+            threshold,
+        }
+    } else if refresh {
+        Message::RefreshRequest { epoch: 0 }
+    } else {
+        Message::Start {
+            subject: String::new(),
+            audience: std::env::var("AUDIENCE").unwrap_or_else(|_| "verisso".to_string()),
+            credential_proof: std::env::var("CREDENTIAL_PROOF").ok(),
+        }
+    };
+    let subject = std::env::var("SUBJECT").ok();
+
+    let logins = match msg {
+        Message::Start { .. } => std::env::var("LOGINS")
+            .ok()
+            .and_then(|s| s.parse::<usize>().ok())
+            .unwrap_or(1),
+        _ => 1,
+    };
+
+    let wait_for_reply = matches!(msg, Message::Start { .. });
+
+    // The AS is pinned by its keys in NODE_KEYS_DIR. Refresh and reshare are only taken from
+    // the operator, who proves it with the keys of the AS, so it runs as node 0.
+    let keys_dir = std::env::var("NODE_KEYS_DIR").unwrap_or_else(|_| "./node_keys".to_string());
+    let deployment_id = std::env::var("DEPLOYMENT_ID").unwrap_or_else(|_| "local".to_string());
+    let (sender, keys, signing_keys) = if wait_for_reply {
+        (
+            CLIENT,
+            NodeKeys::client(&keys_dir, &deployment_id)?,
+            SigningKeys::client(&keys_dir, &deployment_id)?,
+        )
+    } else {
+        (
+            0,
+            NodeKeys::load(&keys_dir, 0, 1, &deployment_id)?,
+            SigningKeys::load(&keys_dir, 0, 1, &deployment_id)?,
+        )
+    };
+    let (keys, signing_keys) = (Arc::new(keys), Arc::new(signing_keys));
 
     let payload = Payload {
-        sender: 0,
-        msg: Message::Start,
+        sender,
+        session: NO_SESSION,
+        msg,
     };
 
-    let serialized = serde_json::to_vec(&payload)?;
-    stream.write_all(&serialized).await?;
+    let mut clients = vec![];
+    for login in 0..logins {
+        let addr = addr.clone();
+        let mut payload = payload.clone();
+        if let Message::Start {
+            subject: ref mut login_subject,
+            ..
+        } = payload.msg
+        {
+            *login_subject = subject.clone().unwrap_or_else(|| format!("user{}", login));
+        }
+        let keys = Arc::clone(&keys);
+        let signing_keys = Arc::clone(&signing_keys);
+        clients.push(tokio::spawn(async move {
+            let (mut reader, writer) = keys.connect(&addr, 0).await?;
+            EnvelopeWriter::new(writer, 0, Arc::clone(&signing_keys))
+                .send(&payload)
+                .await?;
+            if !wait_for_reply {
+                return Ok(());
+            }
+
+            // The AS answers a login with the signature or the reason it failed
+            let mut line = String::new();
+            reader.read_line(&mut line).await?;
+            let reply = serde_json::from_str::<Envelope>(line.trim_end())
+                .map_err(|e| e.to_string())
+                .and_then(|envelope| signing_keys.open(envelope).map_err(|e| e.to_string()));
+            match reply {
+                Ok(Payload {
+                    sender: 0,
+                    msg: Message::LoginResponse { .. },
+                    ..
+                }) => println!("Login succeeded"),
+                Ok(Payload {
+                    sender: 0,
+                    msg: Message::LoginError { reason },
+                    ..
+                }) => println!("Login failed: {}", reason),
+                Ok(payload) => eprintln!("Unexpected reply: {:?}", payload.msg),
+                Err(e) => eprintln!("Failed to read reply: {}", e),
+            }
+            Ok::<(), std::io::Error>(())
+        }));
+    }
+    for client in clients {
+        client.await??;
+    }
 
     Ok(())
 }
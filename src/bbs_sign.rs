@@ -1,10 +1,18 @@
 // Ref: https://github.com/docknetwork/crypto/tree/main/bbs_plus
 
+mod attributes;
+mod blind_issue;
 mod exp_utils;
+mod rng;
+mod schema;
 
+use crate::attributes::{AttributeError, AttributeValue};
+use crate::blind_issue::LinkSecret;
 use crate::exp_utils::*;
+use crate::rng::{seeded_rng, BENCH_SEED};
+use crate::schema::{CredentialSchema, SchemaError, SchemaRegistry};
 use ark_bls12_381::{Bls12_381, Fr};
-use ark_std::rand::{rngs::StdRng, SeedableRng};
+use ark_std::UniformRand;
 use bbs_plus::prelude::{PublicKeyG2, Signature23G1};
 use bbs_plus::proof_23::PoKOfSignature23G1Protocol;
 use bbs_plus::setup::{KeypairG2, SecretKey, SignatureParams23G1};
@@ -17,6 +25,46 @@ use std::fs::File;
 use std::io::Write;
 use std::time::{Duration, Instant};
 
+/// Why a verifier rejects presentations.
+#[derive(Debug)]
+pub enum PresentationError {
+    /// Public keys, parameters or claims that are not one per presentation.
+    Count(usize, usize),
+    Schema(SchemaError),
+    Attribute(AttributeError),
+    InvalidProof(String),
+    /// Presented credentials that do not share the holder's link secret.
+    LinkSecretMismatch,
+}
+
+impl std::fmt::Display for PresentationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Count(count, presentations) => {
+                write!(f, "{} inputs for {} presentations", count, presentations)
+            }
+            Self::Schema(e) => write!(f, "{}", e),
+            Self::Attribute(e) => write!(f, "{}", e),
+            Self::InvalidProof(e) => write!(f, "Invalid presentation: {}", e),
+            Self::LinkSecretMismatch => {
+                write!(f, "Presented credentials do not share a link secret")
+            }
+        }
+    }
+}
+
+impl From<SchemaError> for PresentationError {
+    fn from(e: SchemaError) -> Self {
+        Self::Schema(e)
+    }
+}
+
+impl From<AttributeError> for PresentationError {
+    fn from(e: AttributeError) -> Self {
+        Self::Attribute(e)
+    }
+}
+
 pub fn setup_keys<R: rand::RngCore>(
     rng: &mut R,
     params: &SignatureParams23G1<Bls12_381>,
@@ -24,12 +72,31 @@ pub fn setup_keys<R: rand::RngCore>(
     return KeypairG2::<Bls12_381>::generate_using_rng_and_bbs23_params(rng, &params);
 }
 
+/// A credential the holder presents, with the indices of the messages it reveals.
+pub struct Credential<'a> {
+    pub messages: Vec<Fr>,
+    pub revealed_indices: BTreeSet<usize>,
+    pub signature: Signature23G1<Bls12_381>,
+    pub schema: &'a CredentialSchema,
+    pub params: SignatureParams23G1<Bls12_381>,
+}
+
+/// A proof of knowledge of a credential, with the ID of the schema it was issued under and the
+/// messages it reveals.
+pub struct Presentation {
+    pub schema_id: String,
+    pub revealed_msgs: BTreeMap<usize, Fr>,
+    pub proof: bbs_plus::proof_23::PoKOfSignature23G1Proof<Bls12_381>,
+}
+
 pub fn sign<R: rand::RngCore>(
     messages: Vec<Fr>,
     secret_key: SecretKey<Fr>,
+    schema: &CredentialSchema,
     params: SignatureParams23G1<Bls12_381>,
     rng: &mut R,
 ) -> Signature23G1<Bls12_381> {
+    schema.check_params(&params).unwrap();
     return Signature23G1::<Bls12_381>::new(rng, &messages, &secret_key, &params).unwrap();
 }
 
@@ -44,63 +111,151 @@ pub fn verify_sign(
         .unwrap();
 }
 
-pub fn make_proof<R: rand::RngCore>(
-    messages: Vec<Fr>,
-    revealed_msgs: BTreeMap<usize, Fr>,
-    revealed_indices: BTreeSet<usize>,
-    signature: Signature23G1<Bls12_381>,
-    params: SignatureParams23G1<Bls12_381>,
-    rng: &mut R,
-) -> bbs_plus::proof_23::PoKOfSignature23G1Proof<Bls12_381> {
-    let pok = PoKOfSignature23G1Protocol::init(
-        rng,
-        None,
-        None,
-        &signature,
-        &params,
-        messages.iter().enumerate().map(|(idx, msg)| {
-            if revealed_indices.contains(&idx) {
-                MessageOrBlinding::RevealMessage(msg)
-            } else {
-                MessageOrBlinding::BlindMessageRandomly(msg)
-            }
-        }),
-    )
-    .unwrap();
-
+/// Proves knowledge of every credential under one challenge. The responses for link secrets
+/// prove `link_secret * r`, so the credentials share the signature randomizer `r` and the
+/// blinding of their link secrets. They also share the blinding of `r` itself, so that equal
+/// responses for `r` show the verifier that `r` is the same, and then equal responses for the
+/// link secrets that the link secret is.
+pub fn make_proof<R: rand::RngCore>(credentials: &[Credential], rng: &mut R) -> Vec<Presentation> {
+    let signature_randomizer = Fr::rand(rng);
+    let randomizer_blinding = Fr::rand(rng);
+    let link_secret_blinding = Fr::rand(rng);
+    let mut poks = vec![];
     let mut chal_bytes_prover = vec![];
-    pok.challenge_contribution(&revealed_msgs, &params, &mut chal_bytes_prover)
+    for credential in credentials {
+        let schema = credential.schema;
+        let revealed_indices = &credential.revealed_indices;
+        schema.check_params(&credential.params).unwrap();
+        schema.check_revealed(revealed_indices).unwrap();
+        let link_secret = schema.attributes.link_secret();
+        let revealed_msgs = revealed_indices
+            .iter()
+            .map(|idx| (*idx, credential.messages[*idx]))
+            .collect::<BTreeMap<_, _>>();
+
+        let pok = PoKOfSignature23G1Protocol::init(
+            rng,
+            Some(signature_randomizer),
+            Some(randomizer_blinding),
+            &credential.signature,
+            &credential.params,
+            credential.messages.iter().enumerate().map(|(idx, msg)| {
+                if revealed_indices.contains(&idx) {
+                    MessageOrBlinding::RevealMessage(msg)
+                } else if link_secret == Some(idx) {
+                    MessageOrBlinding::BlindMessageWithConcreteBlinding {
+                        message: msg,
+                        blinding: link_secret_blinding,
+                    }
+                } else {
+                    MessageOrBlinding::BlindMessageRandomly(msg)
+                }
+            }),
+        )
         .unwrap();
+        pok.challenge_contribution(&revealed_msgs, &credential.params, &mut chal_bytes_prover)
+            .unwrap();
+        poks.push((schema.id(), revealed_msgs, pok));
+    }
+
     let challenge_prover = compute_random_oracle_challenge::<Fr, Blake2b512>(&chal_bytes_prover);
-    let res: bbs_plus::proof_23::PoKOfSignature23G1Proof<Bls12_381> =
-        pok.gen_proof(&challenge_prover).unwrap();
-    return res;
+    return poks
+        .into_iter()
+        .map(|(schema_id, revealed_msgs, pok)| Presentation {
+            schema_id,
+            revealed_msgs,
+            proof: pok.gen_proof(&challenge_prover).unwrap(),
+        })
+        .collect();
 }
 
+/// Verifies `presentations` under the schemas they name in `registry`, checks that the
+/// credentials with a link secret share it, and decodes the revealed messages of each. The
+/// holder sends the values of revealed strings and blobs along in `claimed`, one map per
+/// presentation.
 pub fn verify_proof(
-    proof: bbs_plus::proof_23::PoKOfSignature23G1Proof<Bls12_381>,
-    revealed_msgs: BTreeMap<usize, Fr>,
-    challenge_verifier: Fr,
-    public_key: PublicKeyG2<Bls12_381>,
-    params: SignatureParams23G1<Bls12_381>,
-) {
-    return proof
-        .verify(
-            &revealed_msgs,
-            &challenge_verifier,
-            public_key.clone(),
-            params.clone(),
-        )
-        .unwrap();
+    presentations: &[Presentation],
+    public_keys: &[PublicKeyG2<Bls12_381>],
+    params: &[SignatureParams23G1<Bls12_381>],
+    registry: &SchemaRegistry,
+    claimed: &[BTreeMap<String, AttributeValue>],
+) -> Result<Vec<BTreeMap<String, Option<AttributeValue>>>, PresentationError> {
+    for count in [public_keys.len(), params.len(), claimed.len()] {
+        if count != presentations.len() {
+            return Err(PresentationError::Count(count, presentations.len()));
+        }
+    }
+    let invalid = |e| PresentationError::InvalidProof(format!("{:?}", e));
+
+    let mut schemas = vec![];
+    let mut chal_bytes_verifier = vec![];
+    for (presentation, params) in presentations.iter().zip(params) {
+        let schema = registry.get(&presentation.schema_id)?;
+        schema.check_params(params)?;
+        schema.check_revealed(&presentation.revealed_msgs.keys().copied().collect())?;
+        presentation
+            .proof
+            .challenge_contribution(
+                &presentation.revealed_msgs,
+                params,
+                &mut chal_bytes_verifier,
+            )
+            .map_err(invalid)?;
+        schemas.push(schema);
+    }
+    let challenge_verifier =
+        compute_random_oracle_challenge::<Fr, Blake2b512>(&chal_bytes_verifier);
+
+    // Responses for `r` and for the link secret, which must be the same in every presentation
+    let mut link_secret_responses: Option<(Fr, Fr)> = None;
+    let mut revealed_claims = vec![];
+    for (i, presentation) in presentations.iter().enumerate() {
+        let revealed_msgs = &presentation.revealed_msgs;
+        presentation
+            .proof
+            .verify(
+                revealed_msgs,
+                &challenge_verifier,
+                public_keys[i].clone(),
+                params[i].clone(),
+            )
+            .map_err(invalid)?;
+        if let Some(idx) = schemas[i].attributes.link_secret() {
+            let responses = (
+                *presentation.proof.get_resp_for_known_messages_commitment(),
+                *presentation
+                    .proof
+                    .get_resp_for_message(idx, &revealed_msgs.keys().copied().collect())
+                    .map_err(invalid)?,
+            );
+            if *link_secret_responses.get_or_insert(responses) != responses {
+                return Err(PresentationError::LinkSecretMismatch);
+            }
+        }
+        revealed_claims.push(schemas[i].attributes.decode(revealed_msgs, &claimed[i])?);
+    }
+    Ok(revealed_claims)
 }
 
 pub fn test_credential(message_count: u32, revealed_indices_count: u32) -> (f64, f64) {
     // let message_count = 15;
-    let mut rng = StdRng::seed_from_u64(0u64);
-    let params = SignatureParams23G1::<Bls12_381>::generate_using_rng(&mut rng, message_count);
+    let mut rng = seeded_rng(BENCH_SEED);
+    let schema = credential_schema(message_count);
+    let registry_path =
+        std::env::var("SCHEMA_REGISTRY").unwrap_or_else(|_| "./schemas.json".to_string());
+    let mut registry = SchemaRegistry::load(&registry_path).unwrap();
+    let schema_id = registry.register(schema.clone()).unwrap();
+    registry.save(&registry_path).unwrap();
+    let params = schema.params();
 
     let keypair = setup_keys(&mut rng, &params);
-    let messages = setup_messages(&mut rng, message_count);
+    let claims = setup_claims(&mut rng, message_count);
+    let mut messages = schema.attributes.encode(&claims, message_count).unwrap();
+    let link_secret = LinkSecret::generate(&mut rng);
+    let link_secret_idx = link_secret
+        .insert(&schema.attributes, &mut messages)
+        .unwrap();
+    schema.check_params(&params).unwrap();
     // let (messages, params, keypair) = sig_setup(&mut rng, message_count);
 
     // For experimental purposes
@@ -112,15 +267,42 @@ pub fn test_credential(message_count: u32, revealed_indices_count: u32) -> (f64,
     let mut proof_verify_time: f64;
     //
 
+    // The holder keeps its name and link secret from the issuer, which vouches for the other
+    // claims
+    let hidden = BTreeSet::from([schema.attributes.index_of("name").unwrap(), link_secret_idx]);
+    let known = (0..messages.len())
+        .filter(|idx| !hidden.contains(idx))
+        .map(|idx| (idx, messages[idx]))
+        .collect::<BTreeMap<_, _>>();
+
     mTimer.start();
-    let sig =
-        Signature23G1::<Bls12_381>::new(&mut rng, &messages, &keypair.secret_key, &params).unwrap();
+    let (request, blinding) = blind_issue::request(
+        &mut rng,
+        &registry,
+        &schema_id,
+        &params,
+        messages.clone(),
+        hidden,
+    )
+    .unwrap();
+    elapsed = mTimer.stop();
+    let vc_request_time = get_as_millis(elapsed.unwrap());
+
+    mTimer.start();
+    let blind_sig = blind_issue::issue(
+        &mut rng,
+        &registry,
+        &request,
+        &known,
+        &keypair.secret_key,
+        &params,
+    )
+    .unwrap();
     elapsed = mTimer.stop();
     vc_issue_time = get_as_millis(elapsed.unwrap());
 
     mTimer.start();
-    sig.verify(&messages, keypair.public_key.clone(), params.clone())
-        .unwrap();
+    let sig = blind_issue::unblind(&blind_sig, &blinding, &keypair.public_key, &params).unwrap();
     elapsed = mTimer.stop();
     vc_verify_time = get_as_millis(elapsed.unwrap());
 
@@ -141,6 +323,7 @@ pub fn test_credential(message_count: u32, revealed_indices_count: u32) -> (f64,
     for i in revealed_indices.iter() {
         revealed_msgs.insert(*i, messages[*i]);
     }
+    schema.check_revealed(&revealed_indices).unwrap();
 
     mTimer.start();
     let pok = PoKOfSignature23G1Protocol::init(
@@ -167,6 +350,10 @@ pub fn test_credential(message_count: u32, revealed_indices_count: u32) -> (f64,
     elapsed = mTimer.stop();
     proof_gen_time = get_as_millis(elapsed.unwrap());
 
+    // The verifier only knows the schema by the ID the holder presents
+    let schema = registry.get(&schema_id).unwrap();
+    schema.check_params(&params).unwrap();
+    schema.check_revealed(&revealed_indices).unwrap();
     let public_key: &PublicKeyG2<Bls12_381> = &keypair.public_key;
     let mut chal_bytes_verifier = vec![];
     proof
@@ -187,16 +374,30 @@ pub fn test_credential(message_count: u32, revealed_indices_count: u32) -> (f64,
     elapsed = mTimer.stop();
     proof_verify_time = get_as_millis(elapsed.unwrap());
 
+    // The holder sends the revealed claims along, strings are only checked against their hash
+    let claimed = schema
+        .attributes
+        .attributes()
+        .iter()
+        .enumerate()
+        .filter(|(idx, _)| revealed_indices.contains(idx))
+        .map(|(_, attribute)| (attribute.name.clone(), claims[&attribute.name].clone()))
+        .collect();
+    let revealed_claims = schema.attributes.decode(&revealed_msgs, &claimed).unwrap();
+
     println!();
     println!(
         "Verifiable Credential (VC)  of {:?} total attributes, \n\
-         and indexes {:?} revealed.\n\
+         and indexes {:?} revealed: {:?}\n\
+        VC Request phase: {:.2}ms \n\
         VC Issuance phase: {:.2}ms \n\
         VC Verification phase: {:.2}ms \n\
         VP Creation phase: {:.2}ms \n\
         VP Verification phase: {:.2}ms",
         message_count,
         revealed_indices,
+        revealed_claims,
+        vc_request_time,
         vc_issue_time,
         vc_verify_time,
         proof_gen_time,
@@ -206,6 +407,113 @@ pub fn test_credential(message_count: u32, revealed_indices_count: u32) -> (f64,
     (proof_gen_time, proof_verify_time)
 }
 
+/// Blind-issues a credential of random claims into `link_secret`, and returns its messages, its
+/// signature and the claims at `revealed_indices` the holder sends along when presenting it.
+fn issue_with_link_secret<R: rand::RngCore>(
+    rng: &mut R,
+    registry: &SchemaRegistry,
+    schema: &CredentialSchema,
+    keypair: &KeypairG2<Bls12_381>,
+    link_secret: &LinkSecret,
+    revealed_indices: &BTreeSet<usize>,
+) -> (
+    Vec<Fr>,
+    Signature23G1<Bls12_381>,
+    BTreeMap<String, AttributeValue>,
+) {
+    let message_count = schema.message_count();
+    let params = schema.params();
+    let claims = setup_claims(rng, message_count);
+    let mut messages = schema.attributes.encode(&claims, message_count).unwrap();
+    let link_secret_idx = link_secret
+        .insert(&schema.attributes, &mut messages)
+        .unwrap();
+    let hidden = BTreeSet::from([link_secret_idx]);
+    let known = (0..messages.len())
+        .filter(|idx| !hidden.contains(idx))
+        .map(|idx| (idx, messages[idx]))
+        .collect::<BTreeMap<_, _>>();
+    let (request, blinding) = blind_issue::request(
+        rng,
+        registry,
+        &schema.id(),
+        &params,
+        messages.clone(),
+        hidden,
+    )
+    .unwrap();
+    let blind_sig = blind_issue::issue(
+        rng,
+        registry,
+        &request,
+        &known,
+        &keypair.secret_key,
+        &params,
+    )
+    .unwrap();
+    let signature =
+        blind_issue::unblind(&blind_sig, &blinding, &keypair.public_key, &params).unwrap();
+
+    let claimed = schema
+        .attributes
+        .attributes()
+        .iter()
+        .enumerate()
+        .filter(|(idx, _)| revealed_indices.contains(idx))
+        .map(|(_, attribute)| (attribute.name.clone(), claims[&attribute.name].clone()))
+        .collect();
+    (messages, signature, claimed)
+}
+
+/// Blind-issues two credentials into the same link secret and presents them together.
+pub fn test_link_secret(message_count: u32) {
+    let mut rng = seeded_rng(BENCH_SEED);
+    let schema = credential_schema(message_count);
+    let mut registry = SchemaRegistry::default();
+    registry.register(schema.clone()).unwrap();
+    let params = schema.params();
+    let keypair = setup_keys(&mut rng, &params);
+    let link_secret = LinkSecret::generate(&mut rng);
+
+    let revealed_indices = BTreeSet::from([0, 2]);
+    let mut credentials = vec![];
+    let mut claimed = vec![];
+    for _ in 0..2 {
+        let (messages, signature, revealed_claims) = issue_with_link_secret(
+            &mut rng,
+            &registry,
+            &schema,
+            &keypair,
+            &link_secret,
+            &revealed_indices,
+        );
+        claimed.push(revealed_claims);
+        credentials.push(Credential {
+            messages,
+            revealed_indices: revealed_indices.clone(),
+            signature,
+            schema: &schema,
+            params: params.clone(),
+        });
+    }
+
+    let presentations = make_proof(&credentials, &mut rng);
+    let revealed_claims = verify_proof(
+        &presentations,
+        &[keypair.public_key.clone(), keypair.public_key.clone()],
+        &[params.clone(), params.clone()],
+        &registry,
+        &claimed,
+    )
+    .unwrap();
+    println!();
+    println!(
+        "{} credentials presented with a shared link secret: {:?}",
+        presentations.len(),
+        revealed_claims
+    );
+}
+
 pub fn run_exp() {
     // let message_counts = vec![20, 40, 60];
     // let revealed_indices_percentages = vec![20, 40, 60, 80, 100];
@@ -310,4 +618,138 @@ pub fn run_exp() {
 
 pub fn main() {
     run_exp();
+    test_link_secret(15);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    /// Presents two credentials, blind-issued into `first` and `second`, together.
+    fn present(
+        first: &LinkSecret,
+        second: &LinkSecret,
+        rng: &mut StdRng,
+    ) -> Result<Vec<BTreeMap<String, Option<AttributeValue>>>, PresentationError> {
+        let schema = credential_schema(6);
+        let mut registry = SchemaRegistry::default();
+        registry.register(schema.clone()).unwrap();
+        let params = schema.params();
+        let keypair = setup_keys(rng, &params);
+        let revealed_indices = BTreeSet::from([0, 2]);
+
+        let mut credentials = vec![];
+        let mut claimed = vec![];
+        for link_secret in [first, second] {
+            let (messages, signature, revealed_claims) = issue_with_link_secret(
+                rng,
+                &registry,
+                &schema,
+                &keypair,
+                link_secret,
+                &revealed_indices,
+            );
+            claimed.push(revealed_claims);
+            credentials.push(Credential {
+                messages,
+                revealed_indices: revealed_indices.clone(),
+                signature,
+                schema: &schema,
+                params: params.clone(),
+            });
+        }
+        let presentations = make_proof(&credentials, rng);
+        verify_proof(
+            &presentations,
+            &[keypair.public_key.clone(), keypair.public_key.clone()],
+            &[params.clone(), params],
+            &registry,
+            &claimed,
+        )
+    }
+
+    #[test]
+    fn accepts_credentials_of_one_link_secret() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let link_secret = LinkSecret::generate(&mut rng);
+        let claims = present(&link_secret, &link_secret, &mut rng).unwrap();
+        assert_eq!(claims.len(), 2);
+        assert!(claims
+            .iter()
+            .all(|claims| claims["email"].is_some() && claims["birthdate"].is_some()));
+    }
+
+    #[test]
+    fn rejects_credentials_of_different_link_secrets() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let first = LinkSecret::generate(&mut rng);
+        let second = LinkSecret::generate(&mut rng);
+        assert!(matches!(
+            present(&first, &second, &mut rng),
+            Err(PresentationError::LinkSecretMismatch)
+        ));
+    }
+
+    #[test]
+    fn rejects_presentations_without_their_inputs() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let schema = credential_schema(6);
+        let mut registry = SchemaRegistry::default();
+        registry.register(schema.clone()).unwrap();
+        let params = schema.params();
+        let keypair = setup_keys(&mut rng, &params);
+        let revealed_indices = BTreeSet::from([0, 2]);
+        let link_secret = LinkSecret::generate(&mut rng);
+        let (messages, signature, claimed) = issue_with_link_secret(
+            &mut rng,
+            &registry,
+            &schema,
+            &keypair,
+            &link_secret,
+            &revealed_indices,
+        );
+        let credential = Credential {
+            messages,
+            revealed_indices,
+            signature,
+            schema: &schema,
+            params: params.clone(),
+        };
+        let presentations = make_proof(&[credential], &mut rng);
+        let public_keys = [keypair.public_key.clone()];
+        let params = [params];
+        let claimed = [claimed];
+
+        assert!(matches!(
+            verify_proof(&presentations, &[], &params, &registry, &claimed),
+            Err(PresentationError::Count(0, 1))
+        ));
+        let other_keys = [setup_keys(&mut rng, &params[0]).public_key.clone()];
+        assert!(matches!(
+            verify_proof(&presentations, &other_keys, &params, &registry, &claimed),
+            Err(PresentationError::InvalidProof(_))
+        ));
+        assert!(matches!(
+            verify_proof(
+                &presentations,
+                &public_keys,
+                &params,
+                &SchemaRegistry::default(),
+                &claimed
+            ),
+            Err(PresentationError::Schema(SchemaError::UnknownSchema(_)))
+        ));
+        assert!(matches!(
+            verify_proof(
+                &presentations,
+                &public_keys,
+                &params,
+                &registry,
+                &[BTreeMap::new()]
+            ),
+            Err(PresentationError::Attribute(AttributeError::Unclaimed(_)))
+        ));
+    }
 }
@@ -0,0 +1,553 @@
+// Blind issuance of credentials, as modelled in proverif/vc_issue.pv. The holder sends
+// `blind(pair(m, us), d)`: the messages the issuer knows and the ones hidden from it, scaled by
+// a blinding `d`. The issuer signs that and attaches its `e` (gamma in the model), and the
+// holder unblinds the signature with `d` and verifies it before accepting it.
+
+use ark_bls12_381::{Bls12_381, Fr, G1Affine};
+use ark_ec::{AffineRepr, CurveGroup};
+use ark_ff::{Field, PrimeField, Zero};
+use ark_serialize::CanonicalSerialize;
+use ark_std::UniformRand;
+use bbs_plus::prelude::{PublicKeyG2, Signature23G1};
+use bbs_plus::setup::{SecretKey, SignatureParams23G1};
+use blake2::Blake2b512;
+use schnorr_pok::{compute_random_oracle_challenge, SchnorrCommitment, SchnorrResponse};
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::attributes::AttributeSchema;
+use crate::schema::{SchemaError, SchemaRegistry};
+
+const REQUEST_TAG: &[u8] = b"verisso-blind-request";
+
+#[derive(Debug)]
+pub enum IssueError {
+    Schema(SchemaError),
+    /// Messages that are not one per attribute of the schema.
+    MessageCount(usize, u32),
+    /// An index that is both known to the issuer and hidden from it, or neither.
+    Index(usize),
+    /// The holder does not know the opening of its request.
+    InvalidProof,
+    /// The issuer's signature does not verify on the holder's messages.
+    InvalidSignature,
+    /// A request that shows the issuer the holder's link secret at that index.
+    LinkSecretKnown(usize),
+    Bbs(String),
+}
+
+impl std::fmt::Display for IssueError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Schema(e) => write!(f, "{}", e),
+            Self::MessageCount(count, expected) => {
+                write!(f, "{} messages for a schema of {}", count, expected)
+            }
+            Self::Index(index) => write!(
+                f,
+                "Message {} must be either known to the issuer or hidden from it",
+                index
+            ),
+            Self::InvalidProof => write!(f, "Invalid proof of the blinded messages"),
+            Self::InvalidSignature => write!(f, "Issued signature does not verify"),
+            Self::LinkSecretKnown(index) => {
+                write!(f, "Link secret at message {} is not hidden", index)
+            }
+            Self::Bbs(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<SchemaError> for IssueError {
+    fn from(e: SchemaError) -> Self {
+        Self::Schema(e)
+    }
+}
+
+/// The holder's link secret, blind-issued into every credential it holds so that presentations
+/// can show that credentials belong to the same holder without revealing it.
+#[derive(Clone)]
+pub struct LinkSecret(Fr);
+
+impl LinkSecret {
+    pub fn generate<R: rand::RngCore>(rng: &mut R) -> Self {
+        Self(Fr::rand(rng))
+    }
+
+    /// Puts the secret in the `messages` of a credential of `schema` and returns its index.
+    pub fn insert(&self, schema: &AttributeSchema, messages: &mut [Fr]) -> Option<usize> {
+        let index = schema.link_secret()?;
+        *messages.get_mut(index)? = self.0;
+        Some(index)
+    }
+}
+
+/// What the holder sends the issuer: `d * (g1 + sum of h_i * m_i)` over every message of the
+/// credential, and a proof that it opens to the messages the issuer knows, whatever the ones
+/// at `hidden` are.
+#[derive(Clone, Debug)]
+pub struct BlindRequest {
+    pub schema_id: String,
+    pub hidden: BTreeSet<usize>,
+    pub blinded: G1Affine,
+    pub t: G1Affine,
+    pub responses: SchnorrResponse<G1Affine>,
+}
+
+/// What the holder keeps until the issuer answers.
+pub struct Blinding {
+    d: Fr,
+    messages: Vec<Fr>,
+}
+
+/// The issuer's signature on a request, which only verifies once unblinded.
+#[derive(Clone, Debug)]
+pub struct BlindSignature {
+    pub blinded: G1Affine,
+    pub e: Fr,
+}
+
+/// `g1 + sum of h_i * m_i` over the messages the issuer knows.
+fn known_base(
+    params: &SignatureParams23G1<Bls12_381>,
+    known: &BTreeMap<usize, Fr>,
+) -> Result<G1Affine, IssueError> {
+    params
+        .b(known.iter().map(|(index, message)| (*index, message)))
+        .map(|b| b.into_affine())
+        .map_err(|e| IssueError::Bbs(format!("{:?}", e)))
+}
+
+fn challenge(
+    schema_id: &str,
+    hidden: &BTreeSet<usize>,
+    known_base: &G1Affine,
+    blinded: &G1Affine,
+    t: &G1Affine,
+) -> Fr {
+    let mut bytes = REQUEST_TAG.to_vec();
+    bytes.extend_from_slice(&(schema_id.len() as u64).to_be_bytes());
+    bytes.extend_from_slice(schema_id.as_bytes());
+    for index in hidden {
+        bytes.extend_from_slice(&(*index as u64).to_be_bytes());
+    }
+    for point in [known_base, blinded, t] {
+        point.serialize_compressed(&mut bytes).unwrap();
+    }
+    compute_random_oracle_challenge::<Fr, Blake2b512>(&bytes)
+}
+
+/// Bases of the proof: the known part of the signature and the generators of the hidden messages.
+fn bases(
+    params: &SignatureParams23G1<Bls12_381>,
+    known_base: G1Affine,
+    hidden: &BTreeSet<usize>,
+) -> Vec<G1Affine> {
+    std::iter::once(known_base)
+        .chain(hidden.iter().map(|index| params.h[*index]))
+        .collect()
+}
+
+/// Checks that every index of a schema of `message_count` messages is either `known` to the
+/// issuer or `hidden` from it.
+fn check_indices(
+    message_count: u32,
+    known: &BTreeSet<usize>,
+    hidden: &BTreeSet<usize>,
+) -> Result<(), IssueError> {
+    for index in 0..message_count as usize {
+        if known.contains(&index) == hidden.contains(&index) {
+            return Err(IssueError::Index(index));
+        }
+    }
+    match known
+        .union(hidden)
+        .find(|index| **index >= message_count as usize)
+    {
+        Some(index) => Err(IssueError::Index(*index)),
+        None => Ok(()),
+    }
+}
+
+/// Checks that the link secret of `schema`, if it has one, is among the `hidden` messages.
+fn check_link_secret(schema: &AttributeSchema, hidden: &BTreeSet<usize>) -> Result<(), IssueError> {
+    match schema.link_secret() {
+        Some(index) if !hidden.contains(&index) => Err(IssueError::LinkSecretKnown(index)),
+        _ => Ok(()),
+    }
+}
+
+/// Blinds `messages` of a credential of `schema_id` for the issuer, which will only learn the
+/// ones that are not `hidden`.
+pub fn request<R: rand::RngCore>(
+    rng: &mut R,
+    registry: &SchemaRegistry,
+    schema_id: &str,
+    params: &SignatureParams23G1<Bls12_381>,
+    messages: Vec<Fr>,
+    hidden: BTreeSet<usize>,
+) -> Result<(BlindRequest, Blinding), IssueError> {
+    let schema = registry.get(schema_id)?;
+    schema.check_params(params)?;
+    if messages.len() != schema.message_count() as usize {
+        return Err(IssueError::MessageCount(
+            messages.len(),
+            schema.message_count(),
+        ));
+    }
+    let known = (0..messages.len())
+        .filter(|index| !hidden.contains(index))
+        .map(|index| (index, messages[index]))
+        .collect::<BTreeMap<_, _>>();
+    check_indices(
+        schema.message_count(),
+        &known.keys().copied().collect(),
+        &hidden,
+    )?;
+    check_link_secret(&schema.attributes, &hidden)?;
+
+    let mut d = Fr::rand(rng);
+    while d.is_zero() {
+        d = Fr::rand(rng);
+    }
+    let all = (0..messages.len())
+        .map(|index| (index, messages[index]))
+        .collect();
+    let blinded = (known_base(params, &all)?.into_group() * d).into_affine();
+    let known_base = known_base(params, &known)?;
+
+    // blinded = d * known_base + sum of (d * m_j) * h_j over the hidden messages
+    let witnesses = std::iter::once(d)
+        .chain(hidden.iter().map(|index| d * messages[*index]))
+        .collect::<Vec<_>>();
+    let bases = bases(params, known_base, &hidden);
+    let blindings = (0..bases.len()).map(|_| Fr::rand(rng)).collect();
+    let commitment = SchnorrCommitment::new(&bases, blindings);
+    let challenge = challenge(schema_id, &hidden, &known_base, &blinded, &commitment.t);
+    let responses = commitment
+        .response(&witnesses, &challenge)
+        .map_err(|e| IssueError::Bbs(format!("{:?}", e)))?;
+
+    Ok((
+        BlindRequest {
+            schema_id: schema_id.to_string(),
+            hidden,
+            blinded,
+            t: commitment.t,
+            responses,
+        },
+        Blinding { d, messages },
+    ))
+}
+
+/// Signs `request` if it opens to the `known` messages, the ones the issuer vouches for.
+pub fn issue<R: rand::RngCore>(
+    rng: &mut R,
+    registry: &SchemaRegistry,
+    request: &BlindRequest,
+    known: &BTreeMap<usize, Fr>,
+    secret_key: &SecretKey<Fr>,
+    params: &SignatureParams23G1<Bls12_381>,
+) -> Result<BlindSignature, IssueError> {
+    let schema = registry.get(&request.schema_id)?;
+    schema.check_params(params)?;
+    check_indices(
+        schema.message_count(),
+        &known.keys().copied().collect(),
+        &request.hidden,
+    )?;
+    check_link_secret(&schema.attributes, &request.hidden)?;
+    // A zero point opens to any messages with d = 0
+    if request.blinded.is_zero() {
+        return Err(IssueError::InvalidProof);
+    }
+
+    let known_base = known_base(params, known)?;
+    let challenge = challenge(
+        &request.schema_id,
+        &request.hidden,
+        &known_base,
+        &request.blinded,
+        &request.t,
+    );
+    request
+        .responses
+        .is_valid(
+            &bases(params, known_base, &request.hidden),
+            &request.blinded,
+            &request.t,
+            &challenge,
+        )
+        .map_err(|_| IssueError::InvalidProof)?;
+
+    let mut e = Fr::rand(rng);
+    while (e + secret_key.0).is_zero() {
+        e = Fr::rand(rng);
+    }
+    let e_plus_x_inv = (e + secret_key.0).inverse().unwrap();
+    Ok(BlindSignature {
+        blinded: request
+            .blinded
+            .mul_bigint(e_plus_x_inv.into_bigint())
+            .into_affine(),
+        e,
+    })
+}
+
+/// Removes the blinding from the issuer's signature and checks that it signs the holder's
+/// messages.
+pub fn unblind(
+    signature: &BlindSignature,
+    blinding: &Blinding,
+    public_key: &PublicKeyG2<Bls12_381>,
+    params: &SignatureParams23G1<Bls12_381>,
+) -> Result<Signature23G1<Bls12_381>, IssueError> {
+    let d_inv = blinding.d.inverse().unwrap();
+    let signature = Signature23G1::<Bls12_381> {
+        A: signature
+            .blinded
+            .mul_bigint(d_inv.into_bigint())
+            .into_affine(),
+        e: signature.e,
+    };
+    signature
+        .verify(&blinding.messages, public_key.clone(), params.clone())
+        .map_err(|_| IssueError::InvalidSignature)?;
+    Ok(signature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attributes::{Attribute, AttributeType};
+    use crate::schema::CredentialSchema;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    /// A registry with a schema of a name and the holder's link secret, and that schema's ID
+    /// and parameters.
+    fn registry() -> (SchemaRegistry, String, SignatureParams23G1<Bls12_381>) {
+        let attributes = AttributeSchema::new(vec![
+            Attribute {
+                name: "name".into(),
+                kind: AttributeType::String,
+                revealable: true,
+            },
+            Attribute {
+                name: "link_secret".into(),
+                kind: AttributeType::LinkSecret,
+                revealable: false,
+            },
+        ])
+        .unwrap();
+        let schema = CredentialSchema::new("membership", 1, attributes);
+        let params = schema.params();
+        let mut registry = SchemaRegistry::default();
+        let id = registry.register(schema).unwrap();
+        (registry, id, params)
+    }
+
+    fn issuer_key(
+        rng: &mut StdRng,
+        params: &SignatureParams23G1<Bls12_381>,
+    ) -> (SecretKey<Fr>, PublicKeyG2<Bls12_381>) {
+        let secret_key = SecretKey(Fr::rand(rng));
+        let public_key =
+            PublicKeyG2::generate_using_secret_key_and_bbs23_params(&secret_key, params);
+        (secret_key, public_key)
+    }
+
+    /// The holder's messages with `link_secret`, and the ones the issuer knows of them.
+    fn holder_messages(link_secret: &LinkSecret) -> (Vec<Fr>, BTreeMap<usize, Fr>) {
+        let (registry, id, _) = registry();
+        let schema = &registry.get(&id).unwrap().attributes;
+        let mut messages = vec![Fr::from(7u64), Fr::zero()];
+        assert_eq!(link_secret.insert(schema, &mut messages), Some(1));
+        let known = BTreeMap::from([(0, messages[0])]);
+        (messages, known)
+    }
+
+    #[test]
+    fn issues_credentials_with_a_hidden_link_secret() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let (registry, id, params) = registry();
+        let (secret_key, public_key) = issuer_key(&mut rng, &params);
+        let link_secret = LinkSecret::generate(&mut rng);
+        let (messages, known) = holder_messages(&link_secret);
+
+        let (request, blinding) = request(
+            &mut rng,
+            &registry,
+            &id,
+            &params,
+            messages.clone(),
+            BTreeSet::from([1]),
+        )
+        .unwrap();
+        let signature = issue(&mut rng, &registry, &request, &known, &secret_key, &params).unwrap();
+        let signature = unblind(&signature, &blinding, &public_key, &params).unwrap();
+        assert!(signature.verify(&messages, public_key, params).is_ok());
+    }
+
+    #[test]
+    fn rejects_requests_for_other_known_messages() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let (registry, id, params) = registry();
+        let (secret_key, _) = issuer_key(&mut rng, &params);
+        let (messages, _) = holder_messages(&LinkSecret::generate(&mut rng));
+        let (request, _) = request(
+            &mut rng,
+            &registry,
+            &id,
+            &params,
+            messages,
+            BTreeSet::from([1]),
+        )
+        .unwrap();
+
+        let other = BTreeMap::from([(0, Fr::from(8u64))]);
+        assert!(matches!(
+            issue(&mut rng, &registry, &request, &other, &secret_key, &params),
+            Err(IssueError::InvalidProof)
+        ));
+        let zero = BlindRequest {
+            blinded: G1Affine::zero(),
+            ..request
+        };
+        let (_, known) = holder_messages(&LinkSecret::generate(&mut rng));
+        assert!(matches!(
+            issue(&mut rng, &registry, &zero, &known, &secret_key, &params),
+            Err(IssueError::InvalidProof)
+        ));
+    }
+
+    #[test]
+    fn keeps_the_link_secret_hidden() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let (registry, id, params) = registry();
+        let (secret_key, _) = issuer_key(&mut rng, &params);
+        let (messages, known) = holder_messages(&LinkSecret::generate(&mut rng));
+
+        assert!(matches!(
+            request(
+                &mut rng,
+                &registry,
+                &id,
+                &params,
+                messages.clone(),
+                BTreeSet::new()
+            ),
+            Err(IssueError::LinkSecretKnown(1))
+        ));
+        let (mut request, _) = request(
+            &mut rng,
+            &registry,
+            &id,
+            &params,
+            messages.clone(),
+            BTreeSet::from([1]),
+        )
+        .unwrap();
+        request.hidden.clear();
+        let all = messages.into_iter().enumerate().collect();
+        assert!(matches!(
+            issue(&mut rng, &registry, &request, &all, &secret_key, &params),
+            Err(IssueError::LinkSecretKnown(1))
+        ));
+    }
+
+    #[test]
+    fn rejects_bad_requests() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let (registry, id, params) = registry();
+        let (messages, _) = holder_messages(&LinkSecret::generate(&mut rng));
+
+        assert!(matches!(
+            request(
+                &mut rng,
+                &registry,
+                &id,
+                &params,
+                messages[..1].to_vec(),
+                BTreeSet::from([1])
+            ),
+            Err(IssueError::MessageCount(1, 2))
+        ));
+        assert!(matches!(
+            request(
+                &mut rng,
+                &registry,
+                &id,
+                &params,
+                messages.clone(),
+                BTreeSet::from([1, 2])
+            ),
+            Err(IssueError::Index(2))
+        ));
+        let other_params = SignatureParams23G1::<Bls12_381>::new::<Blake2b512>(b"other", 2);
+        assert!(matches!(
+            request(
+                &mut rng,
+                &registry,
+                &id,
+                &other_params,
+                messages.clone(),
+                BTreeSet::from([1])
+            ),
+            Err(IssueError::Schema(SchemaError::WrongParams(_)))
+        ));
+        assert!(matches!(
+            request(
+                &mut rng,
+                &registry,
+                "unknown",
+                &params,
+                messages,
+                BTreeSet::from([1])
+            ),
+            Err(IssueError::Schema(SchemaError::UnknownSchema(_)))
+        ));
+
+        let known = BTreeSet::from([0]);
+        assert!(check_indices(2, &known, &BTreeSet::from([1])).is_ok());
+        assert!(matches!(
+            check_indices(2, &known, &BTreeSet::from([0, 1])),
+            Err(IssueError::Index(0))
+        ));
+        assert!(matches!(
+            check_indices(2, &known, &BTreeSet::new()),
+            Err(IssueError::Index(1))
+        ));
+    }
+
+    #[test]
+    fn unblinds_only_signatures_on_the_holders_messages() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let (registry, id, params) = registry();
+        let (secret_key, public_key) = issuer_key(&mut rng, &params);
+        let (_, other_key) = issuer_key(&mut rng, &params);
+        let (messages, known) = holder_messages(&LinkSecret::generate(&mut rng));
+        let (request, blinding) = request(
+            &mut rng,
+            &registry,
+            &id,
+            &params,
+            messages,
+            BTreeSet::from([1]),
+        )
+        .unwrap();
+        let signature = issue(&mut rng, &registry, &request, &known, &secret_key, &params).unwrap();
+
+        assert!(matches!(
+            unblind(&signature, &blinding, &other_key, &params),
+            Err(IssueError::InvalidSignature)
+        ));
+        let tampered = BlindSignature {
+            e: signature.e + Fr::from(1u64),
+            ..signature
+        };
+        assert!(matches!(
+            unblind(&tampered, &blinding, &public_key, &params),
+            Err(IssueError::InvalidSignature)
+        ));
+    }
+}
@@ -1,10 +1,47 @@
+use zeroize::Zeroizing;
+
 #[derive(Clone)]
 pub struct Config {
     pub node_id: u16,
     pub total_nodes: u16,
     pub message_count: u32,
     pub threshold_signers: u16,
+    /// Size of the initial signer committee, nodes 1 to `signers`. The remaining nodes stand by
+    /// until resharing adds them.
+    pub signers: u16,
     pub current_run: u32,
+    /// Let the AS deal the key shares instead of running DKG. Only meant for benchmarks, since
+    /// the AS then learns the whole secret key.
+    pub trusted_dealer: bool,
+    /// Seconds between proactive refreshes of the key shares, 0 to refresh only on demand.
+    pub refresh_interval: u64,
+    /// Name of this deployment. Presignature sessions of different deployments never mix, even
+    /// with the same session IDs and signers.
+    pub deployment_id: String,
+    /// Seed of the node's RNG, for reproducible test runs. Only builds with the `insecure-rng`
+    /// feature accept one, otherwise randomness comes from OS entropy.
+    pub rng_seed: Option<u64>,
+    /// Where a signer keeps its encrypted key share, and the passphrase it is encrypted under.
+    /// Without a passphrase the share only lives in memory.
+    pub keystore_path: String,
+    pub keystore_passphrase: Option<Zeroizing<String>>,
+    /// Where a signer appends the record of every session it completed.
+    pub audit_log_path: String,
+    /// Where the AS writes the blame reports, refusals and timings of each run.
+    pub output_dir: String,
+    /// Directory with this node's static transport key and the pinned public keys of all nodes.
+    pub node_keys_dir: String,
+    /// Seconds the tokens the AS issues are valid for.
+    pub token_lifetime: u64,
+    /// Issuance policy of a signer: the longest token lifetime it signs, the audiences it
+    /// signs for (any if empty), whether a credential proof must be attached (its presence only,
+    /// the signer does not verify it), and how many tokens a subject gets per window (unlimited
+    /// if 0).
+    pub policy_max_lifetime: u64,
+    pub policy_audiences: Vec<String>,
+    pub policy_require_credential: bool,
+    pub policy_quota: u32,
+    pub policy_quota_window: u64,
 }
 
 impl Config {
@@ -45,6 +82,14 @@ impl Config {
             })
         });
 
+        // Node 0 is the AS, by default every other node is a signer
+        let signers: u16 = std::env::var("SIGNERS").map_or(total_nodes - 1, |s| {
+            s.parse::<u16>().unwrap_or_else(|_| {
+                eprintln!("SIGNERS must be a number, falling back to default TOTAL_NODES - 1.");
+                total_nodes - 1
+            })
+        });
+
         let current_run: u32 = std::env::var("CURRENT_RUN").map_or(0, |s| {
             s.parse::<u32>().unwrap_or_else(|_| {
                 eprintln!("CURRENT_RUN must be a number, falling back to default 0.");
@@ -52,9 +97,99 @@ impl Config {
             })
         });
 
+        let trusted_dealer: bool = std::env::var("TRUSTED_DEALER").is_ok_and(|s| {
+            s.parse::<bool>().unwrap_or_else(|_| {
+                eprintln!("TRUSTED_DEALER must be true or false, falling back to default false.");
+                false
+            })
+        });
+
+        let refresh_interval: u64 = std::env::var("REFRESH_INTERVAL").map_or(0, |s| {
+            s.parse::<u64>().unwrap_or_else(|_| {
+                eprintln!("REFRESH_INTERVAL must be a number, falling back to default 0.");
+                0
+            })
+        });
+
+        let deployment_id = std::env::var("DEPLOYMENT_ID").unwrap_or_else(|_| "local".to_string());
+
+        let rng_seed: Option<u64> = std::env::var("RNG_SEED").ok().map(|s| {
+            s.parse::<u64>().unwrap_or_else(|_| {
+                eprintln!("RNG_SEED must be a number.");
+                std::process::exit(1);
+            })
+        });
+        // A production build never runs with predictable randomness
+        if rng_seed.is_some() && !cfg!(feature = "insecure-rng") {
+            eprintln!("RNG_SEED is only allowed in builds with the insecure-rng feature.");
+            std::process::exit(1);
+        }
+
+        let keystore_path = std::env::var("KEYSTORE_PATH")
+            .unwrap_or_else(|_| format!("./keystore/signer_{}.json", node_id));
+        let keystore_passphrase = std::env::var("KEYSTORE_PASSPHRASE")
+            .ok()
+            .map(Zeroizing::new);
+        let audit_log_path = std::env::var("AUDIT_LOG_PATH")
+            .unwrap_or_else(|_| format!("./audit/signer_{}.log", node_id));
+
+        let output_dir = std::env::var("OUTPUT_DIR").unwrap_or_else(|_| "./op".to_string());
+
+        let node_keys_dir =
+            std::env::var("NODE_KEYS_DIR").unwrap_or_else(|_| "./node_keys".to_string());
+
+        let token_lifetime: u64 = std::env::var("TOKEN_LIFETIME_SECS").map_or(3600, |s| {
+            s.parse::<u64>().unwrap_or_else(|_| {
+                eprintln!("TOKEN_LIFETIME_SECS must be a number, falling back to default 3600.");
+                3600
+            })
+        });
+
+        let policy_max_lifetime: u64 =
+            std::env::var("POLICY_MAX_LIFETIME_SECS").map_or(3600, |s| {
+                s.parse::<u64>().unwrap_or_else(|_| {
+                    eprintln!(
+                        "POLICY_MAX_LIFETIME_SECS must be a number, falling back to default 3600."
+                    );
+                    3600
+                })
+            });
+
+        let policy_audiences: Vec<String> = std::env::var("POLICY_AUDIENCES").map_or(vec![], |s| {
+            s.split(',')
+                .map(|audience| audience.trim().to_string())
+                .filter(|audience| !audience.is_empty())
+                .collect()
+        });
+
+        let policy_require_credential: bool =
+            std::env::var("POLICY_REQUIRE_CREDENTIAL").is_ok_and(|s| {
+                s.parse::<bool>().unwrap_or_else(|_| {
+                    eprintln!("POLICY_REQUIRE_CREDENTIAL must be true or false, falling back to default false.");
+                    false
+                })
+            });
+
+        let policy_quota: u32 = std::env::var("POLICY_QUOTA").map_or(0, |s| {
+            s.parse::<u32>().unwrap_or_else(|_| {
+                eprintln!("POLICY_QUOTA must be a number, falling back to default 0.");
+                0
+            })
+        });
+
+        let policy_quota_window: u64 =
+            std::env::var("POLICY_QUOTA_WINDOW_SECS").map_or(3600, |s| {
+                s.parse::<u64>().unwrap_or_else(|_| {
+                    eprintln!(
+                        "POLICY_QUOTA_WINDOW_SECS must be a number, falling back to default 3600."
+                    );
+                    3600
+                })
+            });
+
         println!(
-            "Config - NODE_ID: {}, TOTAL_NODES: {}, MESSAGE_COUNT: {}, THRESHOLD_SIGNERS: {}, CURRENT_RUN: {}",
-            node_id, total_nodes, message_count, threshold_signers, current_run
+            "Config - NODE_ID: {}, TOTAL_NODES: {}, MESSAGE_COUNT: {}, THRESHOLD_SIGNERS: {}, SIGNERS: {}, CURRENT_RUN: {}, TRUSTED_DEALER: {}, REFRESH_INTERVAL: {}, DEPLOYMENT_ID: {}",
+            node_id, total_nodes, message_count, threshold_signers, signers, current_run, trusted_dealer, refresh_interval, deployment_id
         );
 
         Config {
@@ -62,7 +197,23 @@ impl Config {
             total_nodes,
             message_count,
             threshold_signers,
+            signers,
             current_run,
+            trusted_dealer,
+            refresh_interval,
+            deployment_id,
+            rng_seed,
+            keystore_path,
+            keystore_passphrase,
+            audit_log_path,
+            output_dir,
+            node_keys_dir,
+            token_lifetime,
+            policy_max_lifetime,
+            policy_audiences,
+            policy_require_credential,
+            policy_quota,
+            policy_quota_window,
         }
     }
 }
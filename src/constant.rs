@@ -1,8 +1,37 @@
 pub const BASE_OT_KEY_SIZE: u16 = 128;
 pub const KAPPA: u16 = 256;
 pub const STATISTICAL_SECURITY_PARAMETER: u16 = 80;
-pub const SIG_BATCH_SIZE: u32 = 1;
+// Signatures per presignature batch. The AS refills the pool once fewer than
+// PRESIGNATURE_LOW_WATER presignatures are left, and at most PRESIGNATURE_MAX_BATCHES are kept.
+pub const SIG_BATCH_SIZE: u32 = 8;
+pub const PRESIGNATURE_LOW_WATER: u32 = 4;
+pub const PRESIGNATURE_MAX_BATCHES: usize = 4;
 // pub const THRESHOLD_SIGNERS: u16 = 5;
 pub const TOTAL_SIGNERS: u16 = 8;
+// Label the token signature generators are derived from, so the AS and every signer agree on them
+pub const SIG_PARAMS_LABEL: &[u8] = b"verisso-token-params";
+// Tags of the protocol ID and the gadget vector label of a presignature session, which also
+// bind the deployment, the session and its signers
+pub const PROTOCOL_ID_TAG: &[u8] = b"verisso-tbbs-protocol-id";
+pub const GADGET_VECTOR_TAG: &[u8] = b"verisso-tbbs-gadget-vector";
+// Signers report to the AS this often, and count as down once they miss a few reports
+pub const HEARTBEAT_INTERVAL_SECS: u64 = 2;
+pub const HEARTBEAT_TIMEOUT_SECS: u64 = 6;
 // pub const MESSAGE_COUNT: u32 = 10;
-// 5, 10, 15, 20, 25, 30, 35, 40, 45, 50 
\ No newline at end of file
+// 5, 10, 15, 20, 25, 30, 35, 40, 45, 50
+// Signers drop the state of a presignature session that has not finished after this long
+pub const SESSION_TIMEOUT_SECS: u64 = 60;
+// Signers remember this many finished sessions to reject late or replayed messages for them
+pub const CLOSED_SESSIONS_KEPT: usize = 4096;
+// A login is retried with other presignatures when its signature fails, up to this many times
+pub const MAX_LOGIN_ATTEMPTS: u32 = 3;
+// The AS gives up on a round of a session after this long and retries with another quorum.
+// A login that has no signature after LOGIN_TIMEOUT_SECS fails with an error to the client.
+pub const ROUND_TIMEOUT_SECS: u64 = 10;
+pub const LOGIN_TIMEOUT_SECS: u64 = 30;
+// Signers refuse tokens issued further than this from their own clock
+pub const CLOCK_SKEW_SECS: u64 = 60;
+// A signer retries a connection to another signer this many times, waiting
+// PEER_RECONNECT_BACKOFF_MS after the first failure and twice as long after each next one
+pub const PEER_RECONNECT_ATTEMPTS: u32 = 6;
+pub const PEER_RECONNECT_BACKOFF_MS: u64 = 250;
@@ -0,0 +1,574 @@
+use ark_std::rand::{CryptoRng, RngCore};
+use base64::{engine::general_purpose, Engine as _};
+use ed25519_dalek::{Signature, Signer as _, SigningKey, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use zeroize::Zeroizing;
+
+use crate::helper::message::{Message, ParticipantId, Payload, SessionId, CLIENT};
+use crate::transport::{private_dir, public_dir, SecureWriter};
+
+/// Bound into every signature together with the deployment, so an envelope of one deployment
+/// never verifies in another.
+const ENVELOPE_TAG: &[u8] = b"verisso-envelope";
+const RECORD_TAG: &[u8] = b"verisso-signed-record";
+
+/// What goes on the wire: a payload signed by its sender for one receiver.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Envelope {
+    pub sender: ParticipantId,
+    pub receiver: ParticipantId,
+    pub session: SessionId,
+    /// Grows with every envelope on the link from sender to receiver, so a recorded envelope
+    /// is never taken twice.
+    pub seq: u64,
+    /// `Message::round` of the body.
+    pub round: String,
+    /// The message as JSON, signed exactly as sent.
+    pub body: String,
+    /// Ed25519 signature of the sender, only missing on a client's login.
+    pub signature: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum EnvelopeError {
+    Format(String),
+    /// Meant for another node, e.g. replayed from a link to someone else.
+    WrongReceiver(ParticipantId),
+    /// The round does not name the body's message.
+    WrongRound(String),
+    UnknownSender(ParticipantId),
+    Unsigned(ParticipantId),
+    BadSignature(ParticipantId),
+    /// Not newer than the last envelope from the sender: duplicated, reordered or replayed.
+    Replayed {
+        sender: ParticipantId,
+        seq: u64,
+        last: u64,
+    },
+}
+
+impl std::fmt::Display for EnvelopeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Format(e) => write!(f, "Invalid envelope: {}", e),
+            Self::WrongReceiver(id) => write!(f, "Envelope is meant for node {}", id),
+            Self::WrongRound(round) => {
+                write!(f, "Envelope of round {} holds another message", round)
+            }
+            Self::UnknownSender(id) => write!(f, "No signing key registered for node {}", id),
+            Self::Unsigned(id) => write!(f, "Unsigned envelope from node {}", id),
+            Self::BadSignature(id) => write!(f, "Signature of node {} does not verify", id),
+            Self::Replayed { sender, seq, last } => write!(
+                f,
+                "Envelope {} from node {} is not newer than {}, duplicated or replayed",
+                seq, sender, last
+            ),
+        }
+    }
+}
+
+/// The long-term Ed25519 key this node signs its envelopes with, and the registered verifying
+/// keys of the nodes it accepts envelopes from.
+pub struct SigningKeys {
+    node_id: ParticipantId,
+    signing: Option<SigningKey>,
+    nodes: BTreeMap<ParticipantId, VerifyingKey>,
+    deployment_id: String,
+    /// Sequence number of the last envelope taken from each node.
+    last_seq: Mutex<BTreeMap<ParticipantId, u64>>,
+}
+
+fn signing_key_path(dir: &Path, node_id: ParticipantId) -> PathBuf {
+    private_dir(dir, node_id).join(format!("node{}.signing.key", node_id))
+}
+
+fn verifying_key_path(dir: &Path, node_id: ParticipantId) -> PathBuf {
+    public_dir(dir).join(format!("node{}.signing.pub", node_id))
+}
+
+fn read_key(path: &Path) -> Result<Zeroizing<[u8; 32]>> {
+    let encoded = Zeroizing::new(std::fs::read_to_string(path)?);
+    let bytes = Zeroizing::new(
+        general_purpose::STANDARD
+            .decode(encoded.trim())
+            .map_err(|e| {
+                Error::new(ErrorKind::InvalidData, format!("{}: {}", path.display(), e))
+            })?,
+    );
+    let mut key = Zeroizing::new([0u8; 32]);
+    if bytes.len() != key.len() {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("{}: not a 32 byte key", path.display()),
+        ));
+    }
+    key.copy_from_slice(&bytes);
+    Ok(key)
+}
+
+fn read_verifying_key(path: &Path) -> Result<VerifyingKey> {
+    VerifyingKey::from_bytes(&*read_key(path)?)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))
+}
+
+impl SigningKeys {
+    /// Loads the signing key of node `node_id` and the verifying keys of nodes 0 to
+    /// `total_nodes - 1` from `dir`, as written by the `node_keys` binary.
+    pub fn load(
+        dir: impl AsRef<Path>,
+        node_id: ParticipantId,
+        total_nodes: u16,
+        deployment_id: &str,
+    ) -> Result<Self> {
+        let dir = dir.as_ref();
+        let signing = SigningKey::from_bytes(&*read_key(&signing_key_path(dir, node_id))?);
+        let mut nodes = BTreeMap::new();
+        for id in 0..total_nodes {
+            nodes.insert(id, read_verifying_key(&verifying_key_path(dir, id))?);
+        }
+        Ok(Self {
+            node_id,
+            signing: Some(signing),
+            nodes,
+            deployment_id: deployment_id.to_string(),
+            last_seq: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    /// Keys of a client, which signs nothing and only takes envelopes from the AS.
+    pub fn client(dir: impl AsRef<Path>, deployment_id: &str) -> Result<Self> {
+        Self::verifier(dir, [0], deployment_id)
+    }
+
+    /// Keys that sign nothing and only check what `nodes` signed, e.g. their audit logs.
+    pub fn verifier(
+        dir: impl AsRef<Path>,
+        nodes: impl IntoIterator<Item = ParticipantId>,
+        deployment_id: &str,
+    ) -> Result<Self> {
+        let nodes = nodes
+            .into_iter()
+            .map(|id| {
+                Ok((
+                    id,
+                    read_verifying_key(&verifying_key_path(dir.as_ref(), id))?,
+                ))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            node_id: CLIENT,
+            signing: None,
+            nodes,
+            deployment_id: deployment_id.to_string(),
+            last_seq: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    /// Writes a signing key for every node from 0 to `total_nodes - 1` that has none in `dir`
+    /// yet. Returns the nodes that got a new key.
+    pub fn generate<R: RngCore + CryptoRng>(
+        rng: &mut R,
+        dir: impl AsRef<Path>,
+        total_nodes: u16,
+    ) -> Result<Vec<ParticipantId>> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(public_dir(dir))?;
+        let mut generated = vec![];
+        for id in 0..total_nodes {
+            if signing_key_path(dir, id).exists() {
+                continue;
+            }
+            std::fs::create_dir_all(private_dir(dir, id))?;
+            let signing = SigningKey::generate(rng);
+            let encoded = Zeroizing::new(general_purpose::STANDARD.encode(signing.to_bytes()));
+            crate::transport::write_private(&signing_key_path(dir, id), encoded.as_bytes())?;
+            std::fs::write(
+                verifying_key_path(dir, id),
+                general_purpose::STANDARD.encode(signing.verifying_key().to_bytes()),
+            )?;
+            generated.push(id);
+        }
+        Ok(generated)
+    }
+
+    /// Signs `payload` for `receiver` as envelope `seq` of the link. Only a client's login goes
+    /// out unsigned.
+    pub fn seal(&self, receiver: ParticipantId, seq: u64, payload: &Payload) -> Result<Envelope> {
+        let body = serde_json::to_string(&payload.msg)?;
+        let round = payload.msg.round().to_string();
+        let signature = match &self.signing {
+            Some(signing) => {
+                let signed = self.signed_bytes(
+                    payload.sender,
+                    receiver,
+                    payload.session,
+                    seq,
+                    &round,
+                    &body,
+                );
+                Some(general_purpose::STANDARD.encode(signing.sign(&signed).to_bytes()))
+            }
+            None if matches!(payload.msg, Message::Start { .. }) => None,
+            None => {
+                return Err(Error::new(
+                    ErrorKind::PermissionDenied,
+                    "Clients have no key to sign with",
+                ))
+            }
+        };
+        Ok(Envelope {
+            sender: payload.sender,
+            receiver,
+            session: payload.session,
+            seq,
+            round,
+            body,
+            signature,
+        })
+    }
+
+    /// Checks that `envelope` is meant for this node and signed by the registered key of its
+    /// sender, and returns the payload it carries. The body is only parsed once it verified.
+    pub fn open(&self, envelope: Envelope) -> std::result::Result<Payload, EnvelopeError> {
+        if envelope.receiver != self.node_id {
+            return Err(EnvelopeError::WrongReceiver(envelope.receiver));
+        }
+
+        match &envelope.signature {
+            // Only a login, the round is checked against the body below
+            None if envelope.sender == CLIENT && envelope.round == "Start" => {}
+            None => return Err(EnvelopeError::Unsigned(envelope.sender)),
+            Some(signature) => {
+                self.check_signature(&envelope, signature)?;
+                self.check_seq(envelope.sender, envelope.seq)?;
+            }
+        }
+        Self::payload(envelope)
+    }
+
+    /// Checks an envelope kept after it arrived, e.g. in an audit log, whichever node it was
+    /// sent to. Its sequence number was checked when it was opened.
+    pub fn verify(&self, envelope: Envelope) -> std::result::Result<Payload, EnvelopeError> {
+        let signature = envelope
+            .signature
+            .as_ref()
+            .ok_or(EnvelopeError::Unsigned(envelope.sender))?;
+        self.check_signature(&envelope, signature)?;
+        Self::payload(envelope)
+    }
+
+    /// Signs `digest` of a record this node keeps, e.g. in its audit log. Such a signature
+    /// never passes for an envelope's, nor the other way round.
+    pub fn sign_record(&self, digest: &str) -> Result<String> {
+        let signing = self.signing.as_ref().ok_or_else(|| {
+            Error::new(
+                ErrorKind::PermissionDenied,
+                "Clients have no key to sign with",
+            )
+        })?;
+        let signed = self.record_bytes(self.node_id, digest);
+        Ok(general_purpose::STANDARD.encode(signing.sign(&signed).to_bytes()))
+    }
+
+    /// Checks that `signer` signed `digest` with `sign_record`.
+    pub fn verify_record(
+        &self,
+        signer: ParticipantId,
+        digest: &str,
+        signature: &str,
+    ) -> std::result::Result<(), EnvelopeError> {
+        let key = self
+            .nodes
+            .get(&signer)
+            .ok_or(EnvelopeError::UnknownSender(signer))?;
+        let signature = general_purpose::STANDARD
+            .decode(signature)
+            .ok()
+            .and_then(|bytes| Signature::from_slice(&bytes).ok())
+            .ok_or(EnvelopeError::BadSignature(signer))?;
+        key.verify_strict(&self.record_bytes(signer, digest), &signature)
+            .map_err(|_| EnvelopeError::BadSignature(signer))
+    }
+
+    fn check_signature(
+        &self,
+        envelope: &Envelope,
+        signature: &str,
+    ) -> std::result::Result<(), EnvelopeError> {
+        let key = self
+            .nodes
+            .get(&envelope.sender)
+            .ok_or(EnvelopeError::UnknownSender(envelope.sender))?;
+        let signature = general_purpose::STANDARD
+            .decode(signature)
+            .ok()
+            .and_then(|bytes| Signature::from_slice(&bytes).ok())
+            .ok_or(EnvelopeError::BadSignature(envelope.sender))?;
+        let signed = self.signed_bytes(
+            envelope.sender,
+            envelope.receiver,
+            envelope.session,
+            envelope.seq,
+            &envelope.round,
+            &envelope.body,
+        );
+        key.verify_strict(&signed, &signature)
+            .map_err(|_| EnvelopeError::BadSignature(envelope.sender))
+    }
+
+    fn payload(envelope: Envelope) -> std::result::Result<Payload, EnvelopeError> {
+        let msg: Message = serde_json::from_str(&envelope.body)
+            .map_err(|e| EnvelopeError::Format(e.to_string()))?;
+        if msg.round() != envelope.round {
+            return Err(EnvelopeError::WrongRound(envelope.round));
+        }
+        Ok(Payload {
+            sender: envelope.sender,
+            session: envelope.session,
+            msg,
+        })
+    }
+
+    /// Takes `seq` if it is newer than the last envelope from `sender`. A client opens a new
+    /// link for every login and takes a single reply on it, which the transport already keeps
+    /// from being replayed.
+    fn check_seq(&self, sender: ParticipantId, seq: u64) -> std::result::Result<(), EnvelopeError> {
+        if self.node_id == CLIENT {
+            return Ok(());
+        }
+        let mut last_seq = self.last_seq.lock().unwrap();
+        match last_seq.get(&sender) {
+            Some(&last) if seq <= last => Err(EnvelopeError::Replayed { sender, seq, last }),
+            _ => {
+                last_seq.insert(sender, seq);
+                Ok(())
+            }
+        }
+    }
+
+    fn signed_bytes(
+        &self,
+        sender: ParticipantId,
+        receiver: ParticipantId,
+        session: SessionId,
+        seq: u64,
+        round: &str,
+        body: &str,
+    ) -> Vec<u8> {
+        let mut bytes = ENVELOPE_TAG.to_vec();
+        for field in [
+            self.deployment_id.as_bytes(),
+            round.as_bytes(),
+            body.as_bytes(),
+        ] {
+            bytes.extend_from_slice(&(field.len() as u64).to_be_bytes());
+            bytes.extend_from_slice(field);
+        }
+        bytes.extend_from_slice(&sender.to_be_bytes());
+        bytes.extend_from_slice(&receiver.to_be_bytes());
+        bytes.extend_from_slice(&session.to_be_bytes());
+        bytes.extend_from_slice(&seq.to_be_bytes());
+        bytes
+    }
+
+    fn record_bytes(&self, signer: ParticipantId, digest: &str) -> Vec<u8> {
+        let mut bytes = RECORD_TAG.to_vec();
+        for field in [self.deployment_id.as_bytes(), digest.as_bytes()] {
+            bytes.extend_from_slice(&(field.len() as u64).to_be_bytes());
+            bytes.extend_from_slice(field);
+        }
+        bytes.extend_from_slice(&signer.to_be_bytes());
+        bytes
+    }
+}
+
+/// Sending half of a connection to `receiver`, which seals every payload for it.
+pub struct EnvelopeWriter {
+    writer: SecureWriter,
+    receiver: ParticipantId,
+    keys: Arc<SigningKeys>,
+    /// Sequence number of the next envelope. Starts at the time the link was opened, so a
+    /// restarted node carries on above the envelopes it sent before.
+    seq: u64,
+}
+
+impl EnvelopeWriter {
+    pub fn new(writer: SecureWriter, receiver: ParticipantId, keys: Arc<SigningKeys>) -> Self {
+        let seq = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_nanos() as u64);
+        Self {
+            writer,
+            receiver,
+            keys,
+            seq,
+        }
+    }
+
+    pub async fn send(&mut self, payload: &Payload) -> Result<()> {
+        let envelope = self.keys.seal(self.receiver, self.seq, payload)?;
+        self.seq += 1;
+        let mut serialized = serde_json::to_vec(&envelope)?;
+        serialized.push(b'\n');
+        self.writer.write_all(&serialized).await
+    }
+
+    pub fn local_addr(&self) -> Result<std::net::SocketAddr> {
+        self.writer.local_addr()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signing_key(node_id: ParticipantId) -> SigningKey {
+        SigningKey::from_bytes(&[node_id as u8 + 1; 32])
+    }
+
+    /// Keys of `node_id` in a deployment of nodes 0 to 2.
+    fn keys(node_id: ParticipantId, deployment_id: &str) -> SigningKeys {
+        SigningKeys {
+            node_id,
+            signing: Some(signing_key(node_id)),
+            nodes: (0..3)
+                .map(|id| (id, signing_key(id).verifying_key()))
+                .collect(),
+            deployment_id: deployment_id.to_string(),
+            last_seq: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn payload(sender: ParticipantId) -> Payload {
+        Payload {
+            sender,
+            session: 7,
+            msg: Message::KeygenRequest,
+        }
+    }
+
+    #[test]
+    fn opens_signed_envelope() {
+        let envelope = keys(1, "test").seal(0, 1, &payload(1)).unwrap();
+        let payload = keys(0, "test").open(envelope).unwrap();
+        assert_eq!(payload.sender, 1);
+        assert_eq!(payload.session, 7);
+        assert!(matches!(payload.msg, Message::KeygenRequest));
+    }
+
+    #[test]
+    fn rejects_forged_sender() {
+        // Node 2 signs with its own key but claims to be node 1
+        let envelope = keys(2, "test").seal(0, 1, &payload(1)).unwrap();
+        assert!(matches!(
+            keys(0, "test").open(envelope),
+            Err(EnvelopeError::BadSignature(1))
+        ));
+    }
+
+    #[test]
+    fn rejects_tampered_envelope() {
+        let receiver = keys(0, "test");
+        let sender = keys(1, "test");
+
+        let mut envelope = sender.seal(0, 1, &payload(1)).unwrap();
+        envelope.body = serde_json::to_string(&Message::Abort).unwrap();
+        envelope.round = Message::Abort.round().to_string();
+        assert!(matches!(
+            receiver.open(envelope),
+            Err(EnvelopeError::BadSignature(1))
+        ));
+
+        let mut envelope = sender.seal(0, 2, &payload(1)).unwrap();
+        envelope.session += 1;
+        assert!(matches!(
+            receiver.open(envelope),
+            Err(EnvelopeError::BadSignature(1))
+        ));
+    }
+
+    #[test]
+    fn rejects_envelope_of_other_deployment() {
+        let envelope = keys(1, "other").seal(0, 1, &payload(1)).unwrap();
+        assert!(matches!(
+            keys(0, "test").open(envelope),
+            Err(EnvelopeError::BadSignature(1))
+        ));
+    }
+
+    /// A copy of `envelope` as an attacker recording the link would replay it.
+    fn recorded(envelope: &Envelope) -> Envelope {
+        serde_json::from_str(&serde_json::to_string(envelope).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn rejects_replayed_envelope() {
+        let receiver = keys(0, "test");
+        let envelope = keys(1, "test").seal(0, 1, &payload(1)).unwrap();
+        let replayed = recorded(&envelope);
+        receiver.open(envelope).unwrap();
+        assert!(matches!(
+            receiver.open(replayed),
+            Err(EnvelopeError::Replayed {
+                sender: 1,
+                seq: 1,
+                last: 1
+            })
+        ));
+    }
+
+    #[test]
+    fn rejects_reordered_envelope() {
+        let receiver = keys(0, "test");
+        let sender = keys(1, "test");
+        let first = sender.seal(0, 4, &payload(1)).unwrap();
+        let second = sender.seal(0, 5, &payload(1)).unwrap();
+        receiver.open(second).unwrap();
+        assert!(matches!(
+            receiver.open(first),
+            Err(EnvelopeError::Replayed {
+                sender: 1,
+                seq: 4,
+                last: 5
+            })
+        ));
+    }
+
+    #[test]
+    fn takes_gaps_and_counts_each_sender_apart() {
+        let receiver = keys(0, "test");
+        receiver
+            .open(keys(1, "test").seal(0, 10, &payload(1)).unwrap())
+            .unwrap();
+        // A restarted sender carries on from a later time
+        receiver
+            .open(keys(1, "test").seal(0, 1_000, &payload(1)).unwrap())
+            .unwrap();
+        receiver
+            .open(keys(2, "test").seal(0, 1, &payload(2)).unwrap())
+            .unwrap();
+    }
+
+    #[test]
+    fn rejects_unsigned_and_misdirected_envelopes() {
+        let receiver = keys(0, "test");
+
+        let mut envelope = keys(1, "test").seal(0, 1, &payload(1)).unwrap();
+        envelope.signature = None;
+        assert!(matches!(
+            receiver.open(envelope),
+            Err(EnvelopeError::Unsigned(1))
+        ));
+
+        let envelope = keys(1, "test").seal(2, 2, &payload(1)).unwrap();
+        assert!(matches!(
+            receiver.open(envelope),
+            Err(EnvelopeError::WrongReceiver(2))
+        ));
+    }
+}
@@ -1,3 +1,5 @@
+use crate::attributes::{Attribute, AttributeSchema, AttributeType, AttributeValue, Date};
+use crate::schema::CredentialSchema;
 use ark_bls12_381::{Bls12_381, Fr};
 use ark_std::UniformRand;
 use bbs_plus::prelude::{KeypairG2, SignatureParams23G1};
@@ -96,9 +98,72 @@ where
     (result, elapsed)
 }
 
+/// Schema of the sample credential the experiments sign: an email, a name that is never
+/// revealed, a birthdate and whether the holder was verified, then integer claims and last the
+/// holder's link secret, `message_count` attributes in all.
+pub fn credential_schema(message_count: u32) -> CredentialSchema {
+    let mut attributes = vec![
+        ("email".to_string(), AttributeType::String, true),
+        ("name".to_string(), AttributeType::String, false),
+        ("birthdate".to_string(), AttributeType::Date, true),
+        ("verified".to_string(), AttributeType::Boolean, true),
+    ];
+    let claim_count = message_count.saturating_sub(1) as usize;
+    attributes.truncate(claim_count);
+    for i in attributes.len()..claim_count {
+        attributes.push((format!("claim{}", i), AttributeType::Integer, true));
+    }
+    attributes.push(("link_secret".to_string(), AttributeType::LinkSecret, false));
+    let attributes = AttributeSchema::new(
+        attributes
+            .into_iter()
+            .map(|(name, kind, revealable)| Attribute {
+                name,
+                kind,
+                revealable,
+            })
+            .collect(),
+    )
+    .unwrap();
+    CredentialSchema::new(
+        &format!("sample-credential-{}", message_count),
+        1,
+        attributes,
+    )
+}
+
+/// Claims of a fresh sample credential of `credential_schema(message_count)`.
+pub fn setup_claims<R: rand::RngCore>(
+    rng: &mut R,
+    message_count: u32,
+) -> BTreeMap<String, AttributeValue> {
+    let user = rng.next_u32();
+    credential_schema(message_count)
+        .attributes
+        .attributes()
+        .iter()
+        .filter(|attribute| attribute.kind != AttributeType::LinkSecret)
+        .map(|attribute| {
+            let value = match attribute.name.as_str() {
+                "email" => AttributeValue::String(format!("user{}@example.com", user)),
+                "name" => AttributeValue::String(format!("User {}", user)),
+                "birthdate" => AttributeValue::Date(
+                    Date::new(1950 + (user % 60) as u16, 1 + (user % 12) as u8, 1).unwrap(),
+                ),
+                "verified" => AttributeValue::Boolean(user.is_multiple_of(2)),
+                _ => AttributeValue::Integer(rng.next_u32() as i64),
+            };
+            (attribute.name.clone(), value)
+        })
+        .collect()
+}
+
 pub fn setup_messages<R: rand::RngCore>(rng: &mut R, message_count: u32) -> Vec<Fr> {
-    let messages: Vec<Fr> = (0..message_count).map(|_| Fr::rand(rng)).collect();
-    return messages;
+    let claims = setup_claims(rng, message_count);
+    credential_schema(message_count)
+        .attributes
+        .encode(&claims, message_count)
+        .unwrap()
 }
 
 pub fn reveal_messages(
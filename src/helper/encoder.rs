@@ -1,24 +1,33 @@
 use crate::constant::*;
+use crate::presignature::BatchCommitments;
 use std::{collections::BTreeMap, io::Cursor};
 
-use ark_bls12_381::Fr;
+use ark_bls12_381::{Bls12_381, Fr, G1Affine, G2Affine};
 use ark_ff::{BigInteger, PrimeField};
 use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
 use base64::{engine::general_purpose, Engine as _};
+use bbs_plus::prelude::Signature23G1;
+use bbs_plus::setup::PublicKeyG2;
 use bbs_plus::threshold::{
-    multiplication_phase::{Phase2, Phase2Output},
-    randomness_generation_phase::Phase1,
-    threshold_bbs::Phase1Output,
+    multiplication_phase::Phase2Output,
+    threshold_bbs::{BBSSignatureShare, Phase1Output},
     ParticipantId,
 };
 use oblivious_transfer_protocols::{
+    base_ot::simplest_ot::{Challenges, HashedKey, ReceiverPubKeys, Responses},
     cointoss::Commitments,
     ot_based_multiplication::{
+        base_ot_multi_party_pairwise::SenderPubKeyAndProof,
         batch_mul_multi_party::{Message1, Message2},
         dkls19_batch_mul_2p::GadgetVector,
     },
 };
 
+use secret_sharing_and_dkg::common::{CommitmentToCoefficients, Share};
+
+/// Phase 1 shares with their salts, as revealed from one signer to another.
+type SharesAndSalts = Vec<(Fr, [u8; 256])>;
+
 pub struct Encoder;
 
 impl Encoder {
@@ -39,18 +48,64 @@ impl Encoder {
         Ok(Fr::from_be_bytes_mod_order(&padded))
     }
 
-    pub fn encode_phase1(phase1: &Phase1<Fr, 256>) -> String {
+    pub fn encode_keygen_share(
+        share: &Share<Fr>,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
         let mut bytes = Vec::new();
-        phase1.serialize_compressed(&mut bytes).unwrap();
-        general_purpose::STANDARD.encode(bytes)
+        share.serialize_compressed(&mut bytes)?;
+        let b64 = general_purpose::STANDARD.encode(&bytes);
+        Ok(b64)
     }
 
-    pub fn decode_phase1(phase1_b64: &str) -> Result<Phase1<Fr, 256>, String> {
-        let bytes = general_purpose::STANDARD
-            .decode(phase1_b64)
-            .expect("Base64 decode error");
-        Phase1::<Fr, 256>::deserialize_compressed(&mut Cursor::new(bytes))
-            .map_err(|e| format!("Failed to deserialize Phase1: {}", e))
+    pub fn decode_keygen_share(
+        encoded: &str,
+    ) -> Result<Share<Fr>, Box<dyn std::error::Error + Send + Sync>> {
+        let bytes = general_purpose::STANDARD.decode(encoded)?;
+        let share = Share::<Fr>::deserialize_compressed(&mut Cursor::new(bytes))?;
+        Ok(share)
+    }
+
+    pub fn encode_coeff_commitments(
+        comms: &CommitmentToCoefficients<G2Affine>,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let mut bytes = Vec::new();
+        comms.serialize_compressed(&mut bytes)?;
+        let b64 = general_purpose::STANDARD.encode(&bytes);
+        Ok(b64)
+    }
+
+    pub fn decode_coeff_commitments(
+        encoded: &str,
+    ) -> Result<CommitmentToCoefficients<G2Affine>, Box<dyn std::error::Error + Send + Sync>> {
+        let bytes = general_purpose::STANDARD.decode(encoded)?;
+        let comms =
+            CommitmentToCoefficients::<G2Affine>::deserialize_compressed(&mut Cursor::new(bytes))?;
+        Ok(comms)
+    }
+
+    pub fn encode_public_key(
+        public_key: &PublicKeyG2<Bls12_381>,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let mut bytes = Vec::new();
+        public_key.serialize_compressed(&mut bytes)?;
+        let b64 = general_purpose::STANDARD.encode(&bytes);
+        Ok(b64)
+    }
+
+    pub fn decode_public_key(
+        encoded: &str,
+    ) -> Result<PublicKeyG2<Bls12_381>, Box<dyn std::error::Error + Send + Sync>> {
+        let bytes = general_purpose::STANDARD.decode(encoded)?;
+        let public_key = PublicKeyG2::<Bls12_381>::deserialize_compressed(&mut Cursor::new(bytes))?;
+        Ok(public_key)
+    }
+
+    pub fn encode_signature(
+        signature: &Signature23G1<Bls12_381>,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let mut bytes = Vec::new();
+        signature.serialize_compressed(&mut bytes)?;
+        Ok(general_purpose::STANDARD.encode(&bytes))
     }
 
     pub fn encode_phase1_output(phase1_output: &Phase1Output<Fr>) -> String {
@@ -81,63 +136,32 @@ impl Encoder {
             .map_err(|e| format!("Failed to deserialize Vec<Fr>: {}", e))
     }
 
-    pub fn encode_phase2(phase2: &Phase2<Fr, 256, 80>) -> String {
+    pub fn encode_vec_g2(vec: &Vec<G2Affine>) -> String {
         let mut bytes = Vec::new();
-        phase2.serialize_compressed(&mut bytes).unwrap();
+        vec.serialize_compressed(&mut bytes).unwrap();
         general_purpose::STANDARD.encode(bytes)
     }
 
-    pub fn decode_phase2(phase2_b64: &str) -> Result<Phase2<Fr, 256, 80>, String> {
+    pub fn decode_vec_g2(vec_b64: &str) -> Result<Vec<G2Affine>, String> {
         let bytes = general_purpose::STANDARD
-            .decode(phase2_b64)
-            .expect("Base64 decode error");
-        Phase2::<Fr, 256, 80>::deserialize_compressed(&mut Cursor::new(bytes))
-            .map_err(|e| format!("Failed to deserialize Phase2: {}", e))
+            .decode(vec_b64)
+            .map_err(|e| format!("Base64 decode error: {}", e))?;
+        Vec::<G2Affine>::deserialize_compressed(&mut Cursor::new(bytes))
+            .map_err(|e| format!("Failed to deserialize Vec<G2Affine>: {}", e))
     }
 
-    /// Encode a BTreeMap<ParticipantId, Message1<F>> -> JSON string
-    pub fn encode_map(
-        map: &BTreeMap<ParticipantId, Message1<Fr>>,
-    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>>
-    where
-        Message1<Fr>: CanonicalSerialize,
-    {
-        // intermediate map: ParticipantId -> base64 string
-        let mut tmp: BTreeMap<ParticipantId, String> = BTreeMap::new();
-
-        for (pid, msg) in map {
-            let mut bytes = Vec::new();
-            // serialize compressed into bytes
-            msg.serialize_compressed(&mut bytes)?;
-            // base64 encode
-            let b64 = general_purpose::STANDARD.encode(&bytes);
-            tmp.insert(*pid, b64);
-        }
-
-        // encode the intermediate map as JSON for transport
-        let json = serde_json::to_string(&tmp)?;
-        Ok(json)
+    pub fn encode_batch_commitments(commitments: &BatchCommitments) -> String {
+        let mut bytes = Vec::new();
+        commitments.serialize_compressed(&mut bytes).unwrap();
+        general_purpose::STANDARD.encode(bytes)
     }
 
-    /// Decode JSON string -> BTreeMap<ParticipantId, Message1<F>>
-    pub fn decode_map(
-        encoded: &str,
-    ) -> Result<BTreeMap<ParticipantId, Message1<Fr>>, Box<dyn std::error::Error + Send + Sync>>
-    where
-        Message1<Fr>: CanonicalDeserialize,
-    {
-        // parse JSON -> map of base64 strings
-        let tmp: BTreeMap<ParticipantId, String> = serde_json::from_str(encoded)?;
-
-        let mut map: BTreeMap<ParticipantId, Message1<Fr>> = BTreeMap::new();
-
-        for (pid, b64) in tmp {
-            let bytes = general_purpose::STANDARD.decode(&b64)?;
-            let msg = Message1::<Fr>::deserialize_compressed(&mut Cursor::new(bytes))?;
-            map.insert(pid, msg);
-        }
-
-        Ok(map)
+    pub fn decode_batch_commitments(encoded: &str) -> Result<BatchCommitments, String> {
+        let bytes = general_purpose::STANDARD
+            .decode(encoded)
+            .map_err(|e| format!("Base64 decode error: {}", e))?;
+        BatchCommitments::deserialize_compressed(&mut Cursor::new(bytes))
+            .map_err(|e| format!("Failed to deserialize BatchCommitments: {}", e))
     }
 
     pub fn encode_map_commitments(
@@ -190,6 +214,126 @@ impl Encoder {
         Ok(comm)
     }
 
+    pub fn encode_base_ot_sender_pubkey(
+        value: &SenderPubKeyAndProof<G1Affine>,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let mut bytes = Vec::new();
+        value.serialize_compressed(&mut bytes)?;
+        let b64 = general_purpose::STANDARD.encode(&bytes);
+        Ok(b64)
+    }
+
+    pub fn decode_base_ot_sender_pubkey(
+        encoded: &str,
+    ) -> Result<SenderPubKeyAndProof<G1Affine>, Box<dyn std::error::Error + Send + Sync>> {
+        let bytes = general_purpose::STANDARD.decode(encoded)?;
+        let value =
+            SenderPubKeyAndProof::<G1Affine>::deserialize_compressed(&mut Cursor::new(bytes))?;
+        Ok(value)
+    }
+
+    pub fn encode_base_ot_receiver_pubkey(
+        value: &ReceiverPubKeys<G1Affine>,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let mut bytes = Vec::new();
+        value.serialize_compressed(&mut bytes)?;
+        let b64 = general_purpose::STANDARD.encode(&bytes);
+        Ok(b64)
+    }
+
+    pub fn decode_base_ot_receiver_pubkey(
+        encoded: &str,
+    ) -> Result<ReceiverPubKeys<G1Affine>, Box<dyn std::error::Error + Send + Sync>> {
+        let bytes = general_purpose::STANDARD.decode(encoded)?;
+        let value = ReceiverPubKeys::<G1Affine>::deserialize_compressed(&mut Cursor::new(bytes))?;
+        Ok(value)
+    }
+
+    pub fn encode_base_ot_challenges(
+        value: &Challenges,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let mut bytes = Vec::new();
+        value.serialize_compressed(&mut bytes)?;
+        let b64 = general_purpose::STANDARD.encode(&bytes);
+        Ok(b64)
+    }
+
+    pub fn decode_base_ot_challenges(
+        encoded: &str,
+    ) -> Result<Challenges, Box<dyn std::error::Error + Send + Sync>> {
+        let bytes = general_purpose::STANDARD.decode(encoded)?;
+        let value = Challenges::deserialize_compressed(&mut Cursor::new(bytes))?;
+        Ok(value)
+    }
+
+    pub fn encode_base_ot_responses(
+        value: &Responses,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let mut bytes = Vec::new();
+        value.serialize_compressed(&mut bytes)?;
+        let b64 = general_purpose::STANDARD.encode(&bytes);
+        Ok(b64)
+    }
+
+    pub fn decode_base_ot_responses(
+        encoded: &str,
+    ) -> Result<Responses, Box<dyn std::error::Error + Send + Sync>> {
+        let bytes = general_purpose::STANDARD.decode(encoded)?;
+        let value = Responses::deserialize_compressed(&mut Cursor::new(bytes))?;
+        Ok(value)
+    }
+
+    pub fn encode_base_ot_hashed_keys(
+        value: &Vec<(HashedKey, HashedKey)>,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let mut bytes = Vec::new();
+        value.serialize_compressed(&mut bytes)?;
+        let b64 = general_purpose::STANDARD.encode(&bytes);
+        Ok(b64)
+    }
+
+    pub fn decode_base_ot_hashed_keys(
+        encoded: &str,
+    ) -> Result<Vec<(HashedKey, HashedKey)>, Box<dyn std::error::Error + Send + Sync>> {
+        let bytes = general_purpose::STANDARD.decode(encoded)?;
+        let value = Vec::<(HashedKey, HashedKey)>::deserialize_compressed(&mut Cursor::new(bytes))?;
+        Ok(value)
+    }
+
+    pub fn encode_shares_and_salts(
+        shares: &SharesAndSalts,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let mut bytes = Vec::new();
+        shares.serialize_compressed(&mut bytes)?;
+        let b64 = general_purpose::STANDARD.encode(&bytes);
+        Ok(b64)
+    }
+
+    pub fn decode_shares_and_salts(
+        encoded: &str,
+    ) -> Result<SharesAndSalts, Box<dyn std::error::Error + Send + Sync>> {
+        let bytes = general_purpose::STANDARD.decode(encoded)?;
+        let shares = SharesAndSalts::deserialize_compressed(&mut Cursor::new(bytes))?;
+        Ok(shares)
+    }
+
+    pub fn encode_msg1(
+        msg: &Message1<Fr>,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let mut bytes = Vec::new();
+        msg.serialize_compressed(&mut bytes)?;
+        let b64 = general_purpose::STANDARD.encode(&bytes);
+        Ok(b64)
+    }
+
+    pub fn decode_msg1(
+        encoded: &str,
+    ) -> Result<Message1<Fr>, Box<dyn std::error::Error + Send + Sync>> {
+        let bytes = general_purpose::STANDARD.decode(encoded)?;
+        let msg = Message1::<Fr>::deserialize_compressed(&mut Cursor::new(bytes))?;
+        Ok(msg)
+    }
+
     pub fn encode_msg2(
         msg: &Message2<Fr>,
     ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
@@ -224,6 +368,24 @@ impl Encoder {
         Ok(output)
     }
 
+    pub fn encode_signature_share(
+        share: &BBSSignatureShare<Bls12_381>,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let mut bytes = Vec::new();
+        share.serialize_compressed(&mut bytes)?;
+        let b64 = general_purpose::STANDARD.encode(&bytes);
+        Ok(b64)
+    }
+
+    pub fn decode_signature_share(
+        encoded: &str,
+    ) -> Result<BBSSignatureShare<Bls12_381>, Box<dyn std::error::Error + Send + Sync>> {
+        let bytes = general_purpose::STANDARD.decode(encoded)?;
+        let share =
+            BBSSignatureShare::<Bls12_381>::deserialize_compressed(&mut Cursor::new(bytes))?;
+        Ok(share)
+    }
+
     pub fn encode_gadget_vector(
         vector: &GadgetVector<Fr, KAPPA, STATISTICAL_SECURITY_PARAMETER>,
     ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
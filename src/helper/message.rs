@@ -2,38 +2,245 @@ use serde::{Deserialize, Serialize};
 
 pub type ParticipantId = u16;
 
+/// Identifies a protocol run. The AS assigns one to every keygen, refresh, resharing,
+/// presignature batch and signature, and every message of the run carries it.
+pub type SessionId = u64;
+
+/// Session of messages that belong to no protocol run, such as heartbeats and base OT.
+pub const NO_SESSION: SessionId = 0;
+
+/// Sender or receiver of a message from or to a client, which has no ID of its own.
+pub const CLIENT: ParticipantId = ParticipantId::MAX;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Message {
-    Start,
+    /// Client to AS: log in as `subject` for `audience`, optionally backed by a proof of a
+    /// verified credential.
+    Start {
+        subject: String,
+        audience: String,
+        credential_proof: Option<String>,
+    },
+    /// AS to signer: secret key share from the trusted dealer, and the public key it belongs
+    /// to. Only used for benchmarks.
     SkShares {
         shares: String,
+        public_key: String,
+    },
+    /// AS to signer: report the key share this signer holds, e.g. one stored by an earlier run.
+    KeyStatusRequest,
+    /// Signer to AS: the key this signer holds a share of, if any, with the share's epoch and
+    /// the committee and threshold it belongs to. The AS carries on with that key rather than
+    /// generating a new one.
+    KeyStatus {
+        public_key: Option<String>,
+        public_key_share: Option<String>,
+        epoch: u64,
+        threshold: u16,
+        committee: Vec<ParticipantId>,
+    },
+    /// AS to signer: run distributed key generation with the other signers.
+    KeygenRequest,
+    /// Signer to signer: the Feldman share dealt to the receiver and the commitments to the
+    /// sender's polynomial.
+    KeygenShare {
+        share: String,
+        commitments: String,
+    },
+    /// Signer to AS: DKG finished, with the public key the signer derived from the commitments
+    /// and the signer's public key share, which the AS checks signature shares against.
+    KeygenResponse {
+        public_key: String,
+        public_key_share: String,
+    },
+    /// AS to signer: refresh the key shares into `epoch`. Also accepted by the AS, from an
+    /// operator, to refresh on demand.
+    RefreshRequest {
+        epoch: u64,
+    },
+    /// Signer to signer: the receiver's share of the sender's sharing of zero for `epoch`.
+    RefreshShare {
+        epoch: u64,
+        share: String,
+        commitments: String,
+    },
+    /// Signer to AS: the key share was refreshed and the old one deleted.
+    RefreshResponse {
+        epoch: u64,
+        public_key_share: String,
+    },
+    /// Signer to signer: the OT receiver of the pair has no base OT with the sender, e.g. after
+    /// a restart, and asks it to start a new run.
+    BaseOTRestart,
+    /// Signer to signer: the steps of pairwise base OT, run once per pair and cached.
+    BaseOTSenderPubKey {
+        pubkey: String,
+    },
+    BaseOTReceiverPubKey {
+        pubkey: String,
+    },
+    BaseOTChallenges {
+        challenges: String,
+    },
+    BaseOTResponses {
+        responses: String,
+    },
+    BaseOTHashedKeys {
+        hashed_keys: String,
+    },
+    /// Operator to AS: reshare the key to `committee` with a new `threshold`.
+    Reshare {
+        committee: Vec<ParticipantId>,
+        threshold: u16,
+    },
+    /// AS to old and new signers: the `dealers` reshare their key shares to `committee`. The new
+    /// shares must still match `public_key`.
+    ReshareRequest {
+        epoch: u64,
+        dealers: Vec<ParticipantId>,
+        committee: Vec<ParticipantId>,
+        threshold: u16,
+        public_key: String,
+    },
+    /// Signer to signer: the receiver's share of the sender's key share.
+    ReshareShare {
+        epoch: u64,
+        share: String,
+        commitments: String,
+    },
+    /// Signer to AS: resharing finished. Signers that left the committee deleted their share.
+    ReshareResponse {
+        epoch: u64,
+        public_key_share: Option<String>,
+    },
+    /// Signer to AS: sent periodically so the AS only picks live signers for a session.
+    Heartbeat,
+    /// AS to signer: start computing a presignature batch of `batch_size` signatures with the
+    /// signers in `quorum`. The batch is named after the session. Phase 2 follows Phase 1
+    /// without another request.
+    Round1Request {
+        batch_size: u32,
+        quorum: Vec<ParticipantId>,
+    },
+    /// Signer to signer: commitments to the Phase 1 randomness and to the zero sharing with the
+    /// receiver.
+    Round1Commitment {
+        commitments: String,
+        commitment_zero_share: String,
+    },
+    /// Signer to signer: the committed shares and salts, sent once all commitments are in.
+    Round1Shares {
+        shares: String,
+        zero_shares: String,
+    },
+    /// Signer to AS: Phase 1 finished. Its output stays with the signer.
+    Round1FinalResponse,
+    /// Signer to signer: OT extension message from the multiplication receiver.
+    Round2Message1 {
+        message1: String,
     },
-    Round1Request,
-    Round1Response {
-        phase1: String,
+    /// Signer to signer: reply of the multiplication sender to `Round2Message1`.
+    Round2Message2 {
+        message2: String,
+    },
+    /// Signer to AS: Phase 2 finished and the presignature batch is in the signer's pool, with
+    /// the signer's `BatchCommitments` to the `r` and `u` of each presignature and the `e` of
+    /// each.
+    Round2FinalResponse {
         commitments: String,
-        commitments_map: String,
     },
-    Round1FinalRequest {
-        phase1: String,
+    /// Signer to AS: `culprit` sent this signer a message in the session that did not decode
+    /// or failed a protocol check. `evidence` is the envelope the culprit signed it in. The
+    /// signer already dropped the session.
+    Blame {
+        culprit: ParticipantId,
+        reason: String,
+        evidence: Option<String>,
+    },
+    /// Signer to AS: the signer could not go on with the session, e.g. because a step did not
+    /// fit the state the session was in. The signer already dropped the session.
+    Error {
+        reason: String,
     },
-    Round1FinalResponse {
-        round1: String,
+    /// AS to signer: the session was aborted, drop its state.
+    Abort,
+    /// AS to signer: sign the token with `attributes`, JSON of `TokenAttributes`, with
+    /// presignature `index` of `batch`, together with `quorum`. The signer derives the messages
+    /// from the attributes.
+    SignRequest {
+        batch: u64,
+        index: u32,
+        quorum: Vec<ParticipantId>,
+        attributes: String,
     },
-    Round2Request {
-        masked_signing_key_share: String,
-        masked_rs: String,
+    /// Signer to AS: the signer's share of the signature.
+    SignResponse {
+        share: String,
     },
-    Round2Response {
-        phase2: String,
-        map: String,
+    /// Signer to AS: the token is against the signer's issuance policy, which it did not sign.
+    /// The AS keeps the signed envelope as evidence.
+    Refusal {
+        reason: String,
     },
-   
+    /// AS to client: the login succeeded, with the token's attributes and signature.
+    LoginResponse {
+        attributes: String,
+        signature: String,
+    },
+    /// AS to client: the login failed, e.g. because no quorum of signers answered in time.
+    LoginError {
+        reason: String,
+    },
+}
+
+impl Message {
+    /// Name of the protocol step the message belongs to, which the sender signs along with it.
+    pub fn round(&self) -> &'static str {
+        match self {
+            Self::Start { .. } => "Start",
+            Self::SkShares { .. } => "SkShares",
+            Self::KeyStatusRequest => "KeyStatusRequest",
+            Self::KeyStatus { .. } => "KeyStatus",
+            Self::KeygenRequest => "KeygenRequest",
+            Self::KeygenShare { .. } => "KeygenShare",
+            Self::KeygenResponse { .. } => "KeygenResponse",
+            Self::RefreshRequest { .. } => "RefreshRequest",
+            Self::RefreshShare { .. } => "RefreshShare",
+            Self::RefreshResponse { .. } => "RefreshResponse",
+            Self::BaseOTRestart => "BaseOTRestart",
+            Self::BaseOTSenderPubKey { .. } => "BaseOTSenderPubKey",
+            Self::BaseOTReceiverPubKey { .. } => "BaseOTReceiverPubKey",
+            Self::BaseOTChallenges { .. } => "BaseOTChallenges",
+            Self::BaseOTResponses { .. } => "BaseOTResponses",
+            Self::BaseOTHashedKeys { .. } => "BaseOTHashedKeys",
+            Self::Reshare { .. } => "Reshare",
+            Self::ReshareRequest { .. } => "ReshareRequest",
+            Self::ReshareShare { .. } => "ReshareShare",
+            Self::ReshareResponse { .. } => "ReshareResponse",
+            Self::Heartbeat => "Heartbeat",
+            Self::Round1Request { .. } => "Round1Request",
+            Self::Round1Commitment { .. } => "Round1Commitment",
+            Self::Round1Shares { .. } => "Round1Shares",
+            Self::Round1FinalResponse => "Round1FinalResponse",
+            Self::Round2Message1 { .. } => "Round2Message1",
+            Self::Round2Message2 { .. } => "Round2Message2",
+            Self::Round2FinalResponse { .. } => "Round2FinalResponse",
+            Self::Blame { .. } => "Blame",
+            Self::Error { .. } => "Error",
+            Self::Abort => "Abort",
+            Self::SignRequest { .. } => "SignRequest",
+            Self::SignResponse { .. } => "SignResponse",
+            Self::Refusal { .. } => "Refusal",
+            Self::LoginResponse { .. } => "LoginResponse",
+            Self::LoginError { .. } => "LoginError",
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Payload {
     pub sender: ParticipantId,
+    pub session: SessionId,
     // pub receiver: ParticipantId,
     pub msg: Message,
 }
@@ -0,0 +1,352 @@
+use argon2::{Algorithm, Argon2, Params, Version};
+use ark_bls12_381::{Bls12_381, Fr};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use ark_std::rand::RngCore;
+use base64::{engine::general_purpose, Engine as _};
+use bbs_plus::setup::PublicKeyG2;
+use blake2::{Blake2b512, Digest};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
+
+const KEYSTORE_VERSION: u32 = 1;
+const SALT_SIZE: usize = 16;
+const NONCE_SIZE: usize = 24;
+
+#[derive(Debug)]
+pub enum KeystoreError {
+    Io(std::io::Error),
+    Format(String),
+    /// Wrong passphrase, or the file was tampered with.
+    Decrypt,
+    /// The file holds the share of another signer.
+    WrongNode(u16),
+}
+
+impl std::fmt::Display for KeystoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Format(e) => write!(f, "Invalid keystore: {}", e),
+            Self::Decrypt => write!(f, "Wrong passphrase or corrupted keystore"),
+            Self::WrongNode(id) => write!(f, "Keystore belongs to signer {}", id),
+        }
+    }
+}
+
+impl From<std::io::Error> for KeystoreError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// Everything about a stored share but the share itself. Kept in the clear but bound to the
+/// ciphertext, so a share never loads under another key, epoch or committee.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KeyMetadata {
+    /// Identifies the threshold key the share belongs to, see `key_id`.
+    pub key_id: String,
+    pub node_id: u16,
+    pub epoch: u64,
+    pub threshold: u16,
+    pub committee: Vec<u16>,
+    pub public_key: String,
+}
+
+/// Argon2id parameters the key encrypting the share was derived with.
+#[derive(Serialize, Deserialize, Debug)]
+struct KdfParams {
+    salt: String,
+    memory_kib: u32,
+    iterations: u32,
+    parallelism: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct KeystoreFile {
+    version: u32,
+    metadata: KeyMetadata,
+    kdf: KdfParams,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// A signer's key share on disk, encrypted with XChaCha20-Poly1305 under a key derived from a
+/// passphrase with Argon2id.
+pub struct Keystore {
+    path: PathBuf,
+    passphrase: Zeroizing<String>,
+}
+
+/// Short ID of the threshold key behind `public_key`. Refresh keeps it, a new keygen does not.
+pub fn key_id(public_key: &PublicKeyG2<Bls12_381>) -> String {
+    let mut bytes = Vec::new();
+    public_key.serialize_compressed(&mut bytes).unwrap();
+    Blake2b512::digest(&bytes)[..8]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+impl Keystore {
+    pub fn new(path: impl AsRef<Path>, passphrase: Zeroizing<String>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            passphrase,
+        }
+    }
+
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    /// Encrypts `share` with a fresh salt and nonce and replaces the stored share. The file is
+    /// written next to the old one and renamed over it, so a crash never leaves half a share.
+    pub fn save<R: RngCore>(
+        &self,
+        rng: &mut R,
+        share: &Fr,
+        metadata: &KeyMetadata,
+    ) -> Result<(), KeystoreError> {
+        let mut salt = [0u8; SALT_SIZE];
+        let mut nonce = [0u8; NONCE_SIZE];
+        rng.fill_bytes(&mut salt);
+        rng.fill_bytes(&mut nonce);
+        let params = Params::default();
+        let kdf = KdfParams {
+            salt: general_purpose::STANDARD.encode(salt),
+            memory_kib: params.m_cost(),
+            iterations: params.t_cost(),
+            parallelism: params.p_cost(),
+        };
+
+        let mut plaintext = Zeroizing::new(Vec::new());
+        share
+            .serialize_compressed(&mut *plaintext)
+            .map_err(|e| KeystoreError::Format(e.to_string()))?;
+        let aad = serde_json::to_vec(metadata).map_err(|e| KeystoreError::Format(e.to_string()))?;
+        let cipher = self.cipher(&salt, &kdf)?;
+        let ciphertext = cipher
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: &plaintext,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| KeystoreError::Decrypt)?;
+
+        let file = KeystoreFile {
+            version: KEYSTORE_VERSION,
+            metadata: metadata.clone(),
+            kdf,
+            nonce: general_purpose::STANDARD.encode(nonce),
+            ciphertext: general_purpose::STANDARD.encode(ciphertext),
+        };
+        let json =
+            serde_json::to_vec_pretty(&file).map_err(|e| KeystoreError::Format(e.to_string()))?;
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let tmp = self.path.with_extension("tmp");
+        write_synced(&tmp, &json)?;
+        std::fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    /// Decrypts the stored share of signer `node_id`.
+    pub fn load(&self, node_id: u16) -> Result<(Fr, KeyMetadata), KeystoreError> {
+        let json = std::fs::read(&self.path)?;
+        let file: KeystoreFile =
+            serde_json::from_slice(&json).map_err(|e| KeystoreError::Format(e.to_string()))?;
+        if file.version != KEYSTORE_VERSION {
+            return Err(KeystoreError::Format(format!(
+                "Unsupported keystore version {}",
+                file.version
+            )));
+        }
+        if file.metadata.node_id != node_id {
+            return Err(KeystoreError::WrongNode(file.metadata.node_id));
+        }
+
+        let salt = decode(&file.kdf.salt)?;
+        let nonce = decode(&file.nonce)?;
+        if nonce.len() != NONCE_SIZE {
+            return Err(KeystoreError::Format("Invalid nonce".to_string()));
+        }
+        let ciphertext = decode(&file.ciphertext)?;
+        let aad =
+            serde_json::to_vec(&file.metadata).map_err(|e| KeystoreError::Format(e.to_string()))?;
+        let cipher = self.cipher(&salt, &file.kdf)?;
+        let plaintext = Zeroizing::new(
+            cipher
+                .decrypt(
+                    XNonce::from_slice(&nonce),
+                    Payload {
+                        msg: &ciphertext,
+                        aad: &aad,
+                    },
+                )
+                .map_err(|_| KeystoreError::Decrypt)?,
+        );
+        let share = Fr::deserialize_compressed(&plaintext[..])
+            .map_err(|e| KeystoreError::Format(e.to_string()))?;
+        Ok((share, file.metadata))
+    }
+
+    /// Deletes the stored share, e.g. once this signer left the committee.
+    pub fn delete(&self) -> Result<(), KeystoreError> {
+        match std::fs::remove_file(&self.path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn cipher(&self, salt: &[u8], kdf: &KdfParams) -> Result<XChaCha20Poly1305, KeystoreError> {
+        let params = Params::new(kdf.memory_kib, kdf.iterations, kdf.parallelism, Some(32))
+            .map_err(|e| KeystoreError::Format(e.to_string()))?;
+        let mut key = Zeroizing::new([0u8; 32]);
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(self.passphrase.as_bytes(), salt, &mut *key)
+            .map_err(|e| KeystoreError::Format(e.to_string()))?;
+        Ok(XChaCha20Poly1305::new(Key::from_slice(&*key)))
+    }
+}
+
+/// Writes `contents` to a new file only the owner can read, and flushes it to disk so the
+/// rename over the old share never exposes an empty file. A leftover of a crashed save is
+/// removed first, it may have been created with looser permissions.
+fn write_synced(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
+fn decode(b64: &str) -> Result<Vec<u8>, KeystoreError> {
+    general_purpose::STANDARD
+        .decode(b64)
+        .map_err(|e| KeystoreError::Format(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ark_std::rand::{rngs::StdRng, SeedableRng};
+    use ark_std::UniformRand;
+
+    fn keystore(test: &str, passphrase: &str) -> (PathBuf, Keystore) {
+        let dir =
+            std::env::temp_dir().join(format!("verisso-keystore-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let keystore = Keystore::new(dir.join("share.json"), Zeroizing::new(passphrase.into()));
+        (dir, keystore)
+    }
+
+    fn metadata() -> KeyMetadata {
+        KeyMetadata {
+            key_id: "0011223344556677".into(),
+            node_id: 2,
+            epoch: 3,
+            threshold: 2,
+            committee: vec![1, 2, 3],
+            public_key: "key".into(),
+        }
+    }
+
+    /// Saves a random share for signer 2, returns it.
+    fn save(keystore: &Keystore) -> Fr {
+        let mut rng = StdRng::seed_from_u64(0);
+        let share = Fr::rand(&mut rng);
+        keystore.save(&mut rng, &share, &metadata()).unwrap();
+        share
+    }
+
+    fn edit_file(keystore: &Keystore, edit: impl FnOnce(&mut serde_json::Value)) {
+        let mut file: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&keystore.path).unwrap()).unwrap();
+        edit(&mut file);
+        std::fs::write(&keystore.path, serde_json::to_vec(&file).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn loads_the_saved_share() {
+        let (dir, keystore) = keystore("round-trip", "secret");
+        assert!(!keystore.exists());
+        let share = save(&keystore);
+        assert!(keystore.exists());
+        assert!(!keystore.path.with_extension("tmp").exists());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&keystore.path)
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let (loaded, loaded_metadata) = keystore.load(2).unwrap();
+        assert_eq!(loaded, share);
+        assert_eq!(loaded_metadata.epoch, 3);
+        assert_eq!(loaded_metadata.committee, vec![1, 2, 3]);
+
+        keystore.delete().unwrap();
+        assert!(!keystore.exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_a_wrong_passphrase() {
+        let (dir, keystore) = keystore("passphrase", "secret");
+        save(&keystore);
+        let wrong = Keystore::new(&keystore.path, Zeroizing::new("guess".into()));
+        assert!(matches!(wrong.load(2), Err(KeystoreError::Decrypt)));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_the_share_of_another_node() {
+        let (dir, keystore) = keystore("wrong-node", "secret");
+        save(&keystore);
+        assert!(matches!(keystore.load(1), Err(KeystoreError::WrongNode(2))));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_edited_metadata() {
+        let (dir, keystore) = keystore("metadata", "secret");
+        save(&keystore);
+        // The metadata is in the clear but authenticated as associated data
+        edit_file(&keystore, |file| file["metadata"]["epoch"] = 4.into());
+        assert!(matches!(keystore.load(2), Err(KeystoreError::Decrypt)));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_an_unsupported_version() {
+        let (dir, keystore) = keystore("version", "secret");
+        save(&keystore);
+        edit_file(&keystore, |file| {
+            file["version"] = (KEYSTORE_VERSION + 1).into()
+        });
+        match keystore.load(2) {
+            Err(KeystoreError::Format(e)) => assert!(e.contains("Unsupported keystore version")),
+            other => panic!("Expected a format error, got {:?}", other.map(|(_, m)| m)),
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -3,8 +3,23 @@ mod tbbs_sign;
 mod ot;
 mod exp_utils;
 mod signer;
+mod presignature;
+mod keystore;
+mod rng;
+mod rounds;
+mod policy;
+mod token;
+mod audit;
+mod envelope;
+mod transport;
+mod attributes;
+mod schema;
 mod constant;
 mod config;
+mod helper {
+    pub mod encoder;
+    pub mod message;
+}
 
 const HTML_DIR: &str = "html";
 
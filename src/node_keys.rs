@@ -0,0 +1,44 @@
+mod helper {
+    pub mod message;
+}
+mod envelope;
+mod rng;
+mod transport;
+
+use envelope::SigningKeys;
+use transport::NodeKeys;
+
+/// Generates the static transport keys and the envelope signing keys of nodes 0 to
+/// TOTAL_NODES - 1 into NODE_KEYS_DIR. Nodes that already have keys keep them. Each node needs
+/// its own `node<id>` directory of private keys and the `public` directory.
+fn main() {
+    let dir = std::env::var("NODE_KEYS_DIR").unwrap_or_else(|_| "./node_keys".to_string());
+    let total_nodes = std::env::var("TOTAL_NODES")
+        .unwrap_or_else(|_| {
+            eprintln!("TOTAL_NODES must be set. TOTAL_NODES=<total_nodes> ...");
+            std::process::exit(1);
+        })
+        .parse::<u16>()
+        .unwrap_or_else(|_| {
+            eprintln!("TOTAL_NODES must be a number.");
+            std::process::exit(1);
+        });
+
+    let generated = NodeKeys::generate(&dir, total_nodes).and_then(|transport| {
+        let signing = SigningKeys::generate(&mut rng::os_rng(), &dir, total_nodes)?;
+        Ok((transport, signing))
+    });
+    match generated {
+        Ok((transport, signing)) if transport.is_empty() && signing.is_empty() => {
+            println!("All nodes already have keys in {}", dir)
+        }
+        Ok((transport, signing)) => println!(
+            "Generated transport keys of nodes {:?} and signing keys of nodes {:?} in {}",
+            transport, signing, dir
+        ),
+        Err(e) => {
+            eprintln!("Failed to generate node keys in {}: {}", dir, e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -1,18 +1,28 @@
 // Ref: https://github.com/docknetwork/crypto
 
-use ark_bls12_381::Bls12_381;
+use ark_bls12_381::{Bls12_381, G1Affine};
 use ark_ec::pairing::Pairing;
-use ark_std::{rand::prelude::StdRng, UniformRand};
+use ark_std::{
+    rand::{prelude::StdRng, RngCore},
+    UniformRand,
+};
 use blake2::Blake2b512;
+use dock_crypto_utils::hashing_utils::affine_group_elem_from_try_and_incr;
 use oblivious_transfer_protocols::{
-    base_ot::simplest_ot::{OneOfTwoROTSenderKeys, ROTReceiverKeys},
+    base_ot::simplest_ot::{
+        Challenges, HashedKey, OneOfTwoROTSenderKeys, ROTReceiverKeys, ReceiverPubKeys, Responses,
+    },
+    error::OTError,
     ot_based_multiplication::base_ot_multi_party_pairwise::{
-        BaseOTOutput, Participant as BaseOTParty,
+        BaseOTOutput, Participant as BaseOTParty, SenderPubKeyAndProof,
     },
     Bit, ParticipantId,
 };
 use std::collections::{BTreeMap, BTreeSet};
 
+// Label the base point of the networked base OT is derived from, so every pair agrees on it
+const BASE_OT_LABEL: &[u8] = b"verisso-base-ot";
+
 pub fn check_base_ot_keys(
     choices: &[Bit],
     receiver_keys: &ROTReceiverKeys,
@@ -105,3 +115,284 @@ pub fn do_pairwise_base_ot<const KEY_SIZE: u16>(
     }
     base_ot_outputs
 }
+
+/// Base OT of one signer with each other signer, run over the network one message at a time.
+/// The signer with the lower id acts as the OT sender. Finished runs are kept in `output` and
+/// reused by every later Phase 2, so each pair pays for base OT only once.
+pub struct PairwiseBaseOT {
+    pub id: ParticipantId,
+    pub num_base_ot: u16,
+    base_point: G1Affine,
+    running: BTreeMap<ParticipantId, BaseOTParty<G1Affine>>,
+    pub output: BaseOTOutput,
+}
+
+impl PairwiseBaseOT {
+    pub fn new(id: ParticipantId, num_base_ot: u16) -> Self {
+        Self {
+            id,
+            num_base_ot,
+            base_point: affine_group_elem_from_try_and_incr::<G1Affine, Blake2b512>(BASE_OT_LABEL),
+            running: BTreeMap::new(),
+            output: BaseOTOutput {
+                id,
+                sender_keys: BTreeMap::new(),
+                receiver: BTreeMap::new(),
+            },
+        }
+    }
+
+    pub fn has_output_with(&self, other: &ParticipantId) -> bool {
+        self.output.sender_keys.contains_key(other) || self.output.receiver.contains_key(other)
+    }
+
+    pub fn is_running_with(&self, other: &ParticipantId) -> bool {
+        self.running.contains_key(other)
+    }
+
+    fn init_with<R: RngCore>(
+        &mut self,
+        rng: &mut R,
+        other: ParticipantId,
+    ) -> Result<Option<SenderPubKeyAndProof<G1Affine>>, OTError> {
+        let others = BTreeSet::from([other]);
+        let (base_ot, mut sender_pks) = BaseOTParty::init::<_, Blake2b512>(
+            rng,
+            self.id,
+            others,
+            self.num_base_ot,
+            &self.base_point,
+        )?;
+        self.running.insert(other, base_ot);
+        Ok(sender_pks.remove(&other))
+    }
+
+    /// Starts base OT with `other`. Returns the public key to send when this signer is the OT
+    /// sender. The OT receiver starts when that public key arrives.
+    pub fn start<R: RngCore>(
+        &mut self,
+        rng: &mut R,
+        other: ParticipantId,
+    ) -> Result<Option<SenderPubKeyAndProof<G1Affine>>, OTError> {
+        if self.id > other || self.has_output_with(&other) || self.is_running_with(&other) {
+            return Ok(None);
+        }
+        self.init_with(rng, other)
+    }
+
+    /// Whether this signer is the OT receiver of `other` and has nothing with it, e.g. after a
+    /// restart, so it has to ask `other` to start a new run.
+    pub fn needs_restart_from(&self, other: &ParticipantId) -> bool {
+        self.id > *other && !self.has_output_with(other) && !self.is_running_with(other)
+    }
+
+    /// Forgets the output of a finished run with `other`, and any run in progress.
+    pub fn reset(&mut self, other: &ParticipantId) {
+        self.running.remove(other);
+        self.output.sender_keys.remove(other);
+        self.output.receiver.remove(other);
+    }
+
+    /// Starts a new run with `other` when it lost its output. A run already in progress is
+    /// kept, `other` takes part in it.
+    pub fn restart<R: RngCore>(
+        &mut self,
+        rng: &mut R,
+        other: ParticipantId,
+    ) -> Result<Option<SenderPubKeyAndProof<G1Affine>>, OTError> {
+        if self.id > other {
+            return Err(OTError::UnexpectedParticipant(other));
+        }
+        if self.is_running_with(&other) {
+            return Ok(None);
+        }
+        self.reset(&other);
+        self.init_with(rng, other)
+    }
+
+    /// Takes part in the run `sender` starts. A sender only starts a run when it has nothing
+    /// with this signer, so whatever is left of an earlier run is dropped.
+    pub fn receive_sender_pubkey<R: RngCore, const KEY_SIZE: u16>(
+        &mut self,
+        rng: &mut R,
+        sender: ParticipantId,
+        pk: SenderPubKeyAndProof<G1Affine>,
+    ) -> Result<ReceiverPubKeys<G1Affine>, OTError> {
+        if self.id < sender {
+            return Err(OTError::UnexpectedParticipant(sender));
+        }
+        self.reset(&sender);
+        self.init_with(rng, sender)?;
+        let base_ot = self.running.get_mut(&sender).unwrap();
+        base_ot.receive_sender_pubkey::<_, Blake2b512, KEY_SIZE>(rng, sender, pk, &self.base_point)
+    }
+
+    pub fn receive_receiver_pubkey<const KEY_SIZE: u16>(
+        &mut self,
+        sender: ParticipantId,
+        pk: ReceiverPubKeys<G1Affine>,
+    ) -> Result<Challenges, OTError> {
+        self.running_with(sender)?
+            .receive_receiver_pubkey::<KEY_SIZE>(sender, pk)
+    }
+
+    pub fn receive_challenges(
+        &mut self,
+        sender: ParticipantId,
+        challenges: Challenges,
+    ) -> Result<Responses, OTError> {
+        self.running_with(sender)?
+            .receive_challenges(sender, challenges)
+    }
+
+    /// Last step on the OT sender's side, after which its keys for `sender` are cached.
+    pub fn receive_responses(
+        &mut self,
+        sender: ParticipantId,
+        responses: Responses,
+    ) -> Result<Vec<(HashedKey, HashedKey)>, OTError> {
+        let hashed_keys = self
+            .running_with(sender)?
+            .receive_responses(sender, responses)?;
+        self.finish_with(sender);
+        Ok(hashed_keys)
+    }
+
+    /// Last step on the OT receiver's side, after which its keys for `sender` are cached.
+    pub fn receive_hashed_keys(
+        &mut self,
+        sender: ParticipantId,
+        hashed_keys: Vec<(HashedKey, HashedKey)>,
+    ) -> Result<(), OTError> {
+        self.running_with(sender)?
+            .receive_hashed_keys(sender, hashed_keys)?;
+        self.finish_with(sender);
+        Ok(())
+    }
+
+    fn running_with(
+        &mut self,
+        other: ParticipantId,
+    ) -> Result<&mut BaseOTParty<G1Affine>, OTError> {
+        self.running
+            .get_mut(&other)
+            .ok_or(OTError::UnexpectedParticipant(other))
+    }
+
+    fn finish_with(&mut self, other: ParticipantId) {
+        if let Some(base_ot) = self.running.remove(&other) {
+            let out = base_ot.finish();
+            self.output.sender_keys.extend(out.sender_keys);
+            self.output.receiver.extend(out.receiver);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constant::BASE_OT_KEY_SIZE;
+    use ark_std::rand::SeedableRng;
+
+    /// Passes the messages of a run `sender` started with `pk` until both sides finished it.
+    fn finish_run(
+        rng: &mut StdRng,
+        sender: &mut PairwiseBaseOT,
+        receiver: &mut PairwiseBaseOT,
+        pk: SenderPubKeyAndProof<G1Affine>,
+    ) {
+        let receiver_pk = receiver
+            .receive_sender_pubkey::<_, BASE_OT_KEY_SIZE>(rng, sender.id, pk)
+            .unwrap();
+        let challenges = sender
+            .receive_receiver_pubkey::<BASE_OT_KEY_SIZE>(receiver.id, receiver_pk)
+            .unwrap();
+        let responses = receiver.receive_challenges(sender.id, challenges).unwrap();
+        let hashed_keys = sender.receive_responses(receiver.id, responses).unwrap();
+        assert!(sender.has_output_with(&receiver.id));
+        receiver
+            .receive_hashed_keys(sender.id, hashed_keys)
+            .unwrap();
+        assert!(!sender.is_running_with(&receiver.id));
+        assert!(!receiver.is_running_with(&sender.id));
+    }
+
+    /// Checks the cached keys of both sides match and returns the sender's.
+    fn matching_keys(sender: &PairwiseBaseOT, receiver: &PairwiseBaseOT) -> OneOfTwoROTSenderKeys {
+        let sender_keys = &sender.output.sender_keys[&receiver.id];
+        let (choices, receiver_keys) = &receiver.output.receiver[&sender.id];
+        assert_eq!(sender_keys.len(), sender.num_base_ot as usize);
+        assert_eq!(receiver_keys.len(), sender_keys.len());
+        check_base_ot_keys(choices, receiver_keys, sender_keys);
+        sender_keys.clone()
+    }
+
+    #[test]
+    fn runs_base_ot_over_messages() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut sender = PairwiseBaseOT::new(1, 32);
+        let mut receiver = PairwiseBaseOT::new(2, 32);
+
+        // Only the lower id starts
+        assert!(receiver.start(&mut rng, 1).unwrap().is_none());
+        assert!(receiver.needs_restart_from(&1));
+        let pk = sender.start(&mut rng, 2).unwrap().unwrap();
+        assert!(sender.is_running_with(&2));
+        assert!(sender.start(&mut rng, 2).unwrap().is_none());
+        finish_run(&mut rng, &mut sender, &mut receiver, pk);
+        let first = matching_keys(&sender, &receiver);
+        assert!(!receiver.needs_restart_from(&1));
+        assert!(sender.start(&mut rng, 2).unwrap().is_none());
+
+        // The receiver lost its keys, the sender throws its own away and runs again
+        receiver.reset(&1);
+        assert!(receiver.needs_restart_from(&1));
+        let pk = sender.restart(&mut rng, 2).unwrap().unwrap();
+        assert!(!sender.has_output_with(&2));
+        assert!(sender.restart(&mut rng, 2).unwrap().is_none());
+        finish_run(&mut rng, &mut sender, &mut receiver, pk);
+        let second = matching_keys(&sender, &receiver);
+        assert_ne!(first.0, second.0);
+
+        // A new run from the sender replaces the keys the receiver still had
+        let pk = sender.restart(&mut rng, 2).unwrap().unwrap();
+        finish_run(&mut rng, &mut sender, &mut receiver, pk);
+        assert_ne!(matching_keys(&sender, &receiver).0, second.0);
+    }
+
+    #[test]
+    fn rejects_messages_in_the_wrong_direction() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut sender = PairwiseBaseOT::new(1, 32);
+        let mut receiver = PairwiseBaseOT::new(2, 32);
+
+        assert!(matches!(
+            receiver.restart(&mut rng, 1),
+            Err(OTError::UnexpectedParticipant(1))
+        ));
+        // Signer 2 is the OT receiver of signer 1, so it never sends it a sender public key
+        let pk = PairwiseBaseOT::new(0, 32)
+            .start(&mut rng, 1)
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            sender.receive_sender_pubkey::<_, BASE_OT_KEY_SIZE>(&mut rng, 2, pk),
+            Err(OTError::UnexpectedParticipant(2))
+        ));
+        assert!(!sender.is_running_with(&2));
+
+        // Nothing is running yet
+        let pk = sender.start(&mut rng, 2).unwrap().unwrap();
+        assert!(matches!(
+            receiver.receive_hashed_keys(1, vec![]),
+            Err(OTError::UnexpectedParticipant(1))
+        ));
+        let receiver_pk = receiver
+            .receive_sender_pubkey::<_, BASE_OT_KEY_SIZE>(&mut rng, 1, pk)
+            .unwrap();
+        assert!(matches!(
+            sender.receive_receiver_pubkey::<BASE_OT_KEY_SIZE>(3, receiver_pk),
+            Err(OTError::UnexpectedParticipant(3))
+        ));
+    }
+}
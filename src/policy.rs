@@ -0,0 +1,215 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::config::Config;
+use crate::constant::CLOCK_SKEW_SECS;
+use crate::token::TokenAttributes;
+
+/// What a signer is willing to sign, whatever the AS asks for. Each signer is configured on
+/// its own, so a compromised AS cannot issue tokens outside the policy of a threshold of
+/// signers.
+pub struct IssuancePolicy {
+    max_lifetime: u64,
+    /// Any audience if empty.
+    audiences: Vec<String>,
+    /// Only checks that a credential proof is attached. Signers have no schema registry or
+    /// issuer keys, so verifying the presentation is left to the verifier of the token.
+    require_credential_present: bool,
+    /// Tokens per subject within `quota_window` seconds, unlimited if 0.
+    quota: u32,
+    quota_window: u64,
+    /// Tokens signed for each subject, with the time they were signed at.
+    issued: BTreeMap<String, VecDeque<(u64, TokenAttributes)>>,
+}
+
+impl IssuancePolicy {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            max_lifetime: config.policy_max_lifetime,
+            audiences: config.policy_audiences.clone(),
+            require_credential_present: config.policy_require_credential,
+            quota: config.policy_quota,
+            quota_window: config.policy_quota_window,
+            issued: BTreeMap::new(),
+        }
+    }
+
+    /// Checks `attributes` at time `now`, or says why this signer declines to sign them.
+    pub fn check(&mut self, attributes: &TokenAttributes, now: u64) -> Result<(), String> {
+        if attributes.issued_at.abs_diff(now) > CLOCK_SKEW_SECS {
+            return Err(format!(
+                "Issued at {}, {} seconds off this signer's clock",
+                attributes.issued_at,
+                attributes.issued_at.abs_diff(now)
+            ));
+        }
+        if attributes.expires_at <= attributes.issued_at {
+            return Err("Expires before it is issued".into());
+        }
+        if attributes.lifetime() > self.max_lifetime {
+            return Err(format!(
+                "Lifetime of {} seconds exceeds the maximum of {}",
+                attributes.lifetime(),
+                self.max_lifetime
+            ));
+        }
+        if !self.audiences.is_empty() && !self.audiences.contains(&attributes.audience) {
+            return Err(format!("Audience {} is not allowed", attributes.audience));
+        }
+        if self.require_credential_present && attributes.credential_proof.is_none() {
+            return Err("No credential proof attached".into());
+        }
+
+        if self.quota > 0 {
+            self.prune(now);
+            let issued = self.issued.get(&attributes.subject);
+            // A login retried with another presignature is the same token, not a new one
+            let retried =
+                issued.is_some_and(|issued| issued.iter().any(|(_, signed)| signed == attributes));
+            let count = issued.map_or(0, VecDeque::len);
+            if !retried && count >= self.quota as usize {
+                return Err(format!(
+                    "Subject {} already got {} tokens in {} seconds",
+                    attributes.subject, self.quota, self.quota_window
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Forgets the tokens signed more than `quota_window` seconds before `now`, and the
+    /// subjects left without any.
+    fn prune(&mut self, now: u64) {
+        let window = self.quota_window;
+        self.issued.retain(|_, issued| {
+            while issued
+                .front()
+                .is_some_and(|(at, _)| at.saturating_add(window) <= now)
+            {
+                issued.pop_front();
+            }
+            !issued.is_empty()
+        });
+    }
+
+    /// Counts a token this signer signed towards the quota of its subject.
+    pub fn record(&mut self, attributes: &TokenAttributes, now: u64) {
+        if self.quota == 0 {
+            return;
+        }
+        let issued = self.issued.entry(attributes.subject.clone()).or_default();
+        if !issued.iter().any(|(_, signed)| signed == attributes) {
+            issued.push_back((now, attributes.clone()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> IssuancePolicy {
+        IssuancePolicy {
+            max_lifetime: 60,
+            audiences: vec![],
+            require_credential_present: false,
+            quota: 1,
+            quota_window: 10,
+            issued: BTreeMap::new(),
+        }
+    }
+
+    fn attributes(subject: &str, token_id: u64, issued_at: u64) -> TokenAttributes {
+        TokenAttributes {
+            subject: subject.into(),
+            audience: "app".into(),
+            token_id,
+            issued_at,
+            expires_at: issued_at + 60,
+            credential_proof: None,
+        }
+    }
+
+    #[test]
+    fn forgets_subjects_once_their_window_is_over() {
+        let mut policy = policy();
+        let first = attributes("alice", 1, 100);
+        policy.check(&first, 100).unwrap();
+        policy.record(&first, 100);
+        // A retry of the same token is fine, another one is over the quota
+        policy.check(&first, 105).unwrap();
+        assert!(policy.check(&attributes("alice", 2, 105), 105).is_err());
+
+        policy.check(&attributes("bob", 3, 110), 110).unwrap();
+        assert!(policy.issued.is_empty());
+    }
+
+    #[test]
+    fn declines_tokens_issued_off_its_clock() {
+        let mut policy = policy();
+        policy
+            .check(&attributes("alice", 1, 100), 100 + CLOCK_SKEW_SECS)
+            .unwrap();
+        let early = policy.check(&attributes("alice", 1, 100), 101 + CLOCK_SKEW_SECS);
+        assert!(early.unwrap_err().contains("off this signer's clock"));
+        let late = policy.check(&attributes("alice", 1, 101 + CLOCK_SKEW_SECS), 0);
+        assert!(late.unwrap_err().contains("off this signer's clock"));
+    }
+
+    #[test]
+    fn declines_tokens_expiring_before_they_are_issued() {
+        let mut policy = policy();
+        let mut expired = attributes("alice", 1, 100);
+        expired.expires_at = 100;
+        assert_eq!(
+            policy.check(&expired, 100),
+            Err("Expires before it is issued".into())
+        );
+        expired.expires_at = 99;
+        assert!(policy.check(&expired, 100).is_err());
+    }
+
+    #[test]
+    fn declines_lifetimes_over_the_maximum() {
+        let mut policy = policy();
+        let mut token = attributes("alice", 1, 100);
+        policy.check(&token, 100).unwrap();
+        token.expires_at += 1;
+        assert_eq!(
+            policy.check(&token, 100),
+            Err("Lifetime of 61 seconds exceeds the maximum of 60".into())
+        );
+    }
+
+    #[test]
+    fn signs_for_allowed_audiences_only() {
+        let mut policy = policy();
+        policy.quota = 0;
+        let mut token = attributes("alice", 1, 100);
+        token.audience = "other".into();
+        // Any audience while the allow-list is empty
+        policy.check(&token, 100).unwrap();
+
+        policy.audiences = vec!["app".into()];
+        policy.check(&attributes("alice", 2, 100), 100).unwrap();
+        assert_eq!(
+            policy.check(&token, 100),
+            Err("Audience other is not allowed".into())
+        );
+    }
+
+    #[test]
+    fn requires_a_credential_proof_if_configured() {
+        let mut policy = policy();
+        policy.quota = 0;
+        let mut token = attributes("alice", 1, 100);
+        policy.check(&token, 100).unwrap();
+
+        policy.require_credential_present = true;
+        assert_eq!(
+            policy.check(&token, 100),
+            Err("No credential proof attached".into())
+        );
+        token.credential_proof = Some("proof".into());
+        policy.check(&token, 100).unwrap();
+    }
+}
@@ -0,0 +1,250 @@
+use ark_bls12_381::{Bls12_381, Fr, G1Affine, G2Affine};
+use ark_ec::CurveGroup;
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use bbs_plus::error::BBSPlusError;
+use bbs_plus::setup::SignatureParams23G1;
+use bbs_plus::threshold::multiplication_phase::Phase2Output;
+use bbs_plus::threshold::threshold_bbs::{BBSSignatureShare, Phase1Output};
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Debug)]
+pub enum PresignatureError {
+    UnknownBatch(u64),
+    IndexOutOfRange(u64, u32),
+    AlreadyUsed(u64, u32),
+    BBSPlus(BBSPlusError),
+}
+
+impl From<BBSPlusError> for PresignatureError {
+    fn from(e: BBSPlusError) -> Self {
+        Self::BBSPlus(e)
+    }
+}
+
+/// What a signer reports to the AS about its part of every presignature in a batch once Phase 2
+/// is done, before any of them signs. `params.g2 * r` and `params.g1 * r` commit to its share
+/// `r` of the randomness, `params.g1 * u` to the `u` its signature share will carry, and `e` is
+/// the presignature's `e`.
+#[derive(Clone, Debug, PartialEq, CanonicalSerialize, CanonicalDeserialize)]
+pub struct BatchCommitments {
+    pub r: Vec<G2Affine>,
+    pub r_g1: Vec<G1Affine>,
+    pub u: Vec<G1Affine>,
+    pub e: Vec<Fr>,
+}
+
+/// This signer's outputs of Phase 1 and Phase 2 for a batch of signatures, computed ahead of
+/// time with one quorum. Signing with it only needs `BBSSignatureShare::new`.
+pub struct PresignatureBatch {
+    round1_output: Phase1Output<Fr>,
+    round2_output: Phase2Output<Fr>,
+    used: BTreeSet<u32>,
+}
+
+impl PresignatureBatch {
+    pub fn new(round1_output: Phase1Output<Fr>, round2_output: Phase2Output<Fr>) -> Self {
+        Self {
+            round1_output,
+            round2_output,
+            used: BTreeSet::new(),
+        }
+    }
+
+    /// Commits to this signer's `r` and `u` of every presignature. Unlike `R`, `u` does not
+    /// depend on the messages, so it is the same as in `BBSSignatureShare::new`.
+    pub fn commitments(&self, params: &SignatureParams23G1<Bls12_381>) -> BatchCommitments {
+        let output = &self.round1_output;
+        let u = (0..output.batch_size as usize)
+            .map(|i| {
+                output.masked_rs[i] * (output.e[i] + output.masked_signing_key_shares[i])
+                    + self.round2_output.0.compute_u(i)
+            })
+            .map(|u| (params.g1 * u).into_affine())
+            .collect();
+        BatchCommitments {
+            r: output
+                .r
+                .iter()
+                .map(|r| (params.g2 * r).into_affine())
+                .collect(),
+            r_g1: output
+                .r
+                .iter()
+                .map(|r| (params.g1 * r).into_affine())
+                .collect(),
+            u,
+            e: output.e.clone(),
+        }
+    }
+
+    fn is_used_up(&self) -> bool {
+        self.used.len() as u32 == self.round1_output.batch_size
+    }
+}
+
+/// Presignature batches by batch id. Batch ids only grow, so the oldest batch has the lowest id.
+pub struct PresignaturePool {
+    batches: BTreeMap<u64, PresignatureBatch>,
+    max_batches: usize,
+}
+
+impl PresignaturePool {
+    pub fn new(max_batches: usize) -> Self {
+        Self {
+            batches: BTreeMap::new(),
+            max_batches,
+        }
+    }
+
+    /// Adds a batch, evicting the oldest ones beyond `max_batches`.
+    pub fn insert(&mut self, batch_id: u64, batch: PresignatureBatch) {
+        self.batches.insert(batch_id, batch);
+        while self.batches.len() > self.max_batches {
+            self.batches.pop_first();
+        }
+    }
+
+    /// The signers that computed `batch_id` together, and so sign with it.
+    pub fn quorum(&self, batch_id: u64) -> Option<BTreeSet<u16>> {
+        let round1_output = &self.batches.get(&batch_id)?.round1_output;
+        let mut quorum = round1_output
+            .others
+            .iter()
+            .copied()
+            .collect::<BTreeSet<_>>();
+        quorum.insert(round1_output.id);
+        Some(quorum)
+    }
+
+    /// Signs `messages` with presignature `index` of `batch_id`. Signing two messages with the
+    /// same presignature would leak the signing key, so every index is marked used before it is
+    /// touched and is never handed out again. A batch is dropped once all of it is used.
+    pub fn sign(
+        &mut self,
+        batch_id: u64,
+        index: u32,
+        messages: &[Fr],
+        params: &SignatureParams23G1<Bls12_381>,
+    ) -> Result<BBSSignatureShare<Bls12_381>, PresignatureError> {
+        let batch = self
+            .batches
+            .get_mut(&batch_id)
+            .ok_or(PresignatureError::UnknownBatch(batch_id))?;
+        if index >= batch.round1_output.batch_size {
+            return Err(PresignatureError::IndexOutOfRange(batch_id, index));
+        }
+        if !batch.used.insert(index) {
+            return Err(PresignatureError::AlreadyUsed(batch_id, index));
+        }
+
+        let share = BBSSignatureShare::new(
+            messages,
+            index as usize,
+            &batch.round1_output,
+            &batch.round2_output,
+            params,
+        );
+        if batch.is_used_up() {
+            self.batches.remove(&batch_id);
+        }
+        Ok(share?)
+    }
+
+    /// Drops every batch, e.g. once the key shares they were made from are gone.
+    pub fn clear(&mut self) {
+        self.batches.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ark_std::rand::{rngs::StdRng, SeedableRng};
+    use ark_std::UniformRand;
+    use blake2::Blake2b512;
+    use oblivious_transfer_protocols::ot_based_multiplication::batch_mul_multi_party::ParticipantOutput;
+
+    const BATCH_SIZE: u32 = 2;
+
+    /// A batch of signer 1 with signer 2, of made-up Phase 1 and Phase 2 outputs. Signing with
+    /// it gives shares that do not add up to a signature, which the pool does not look at.
+    fn batch(rng: &mut StdRng) -> PresignatureBatch {
+        let mut random = || (0..BATCH_SIZE).map(|_| Fr::rand(rng)).collect::<Vec<_>>();
+        let round1_output = Phase1Output {
+            id: 1,
+            batch_size: BATCH_SIZE,
+            r: random(),
+            e: random(),
+            masked_signing_key_shares: random(),
+            masked_rs: random(),
+            others: vec![2],
+        };
+        let round2_output = Phase2Output(ParticipantOutput {
+            z_A: BTreeMap::new(),
+            z_B: BTreeMap::new(),
+        });
+        PresignatureBatch::new(round1_output, round2_output)
+    }
+
+    fn params() -> SignatureParams23G1<Bls12_381> {
+        SignatureParams23G1::new::<Blake2b512>(b"test", 2)
+    }
+
+    #[test]
+    fn signs_with_each_presignature_once() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let params = params();
+        let messages = [Fr::from(1u64), Fr::from(2u64)];
+        let mut pool = PresignaturePool::new(2);
+        pool.insert(7, batch(&mut rng));
+        assert_eq!(pool.quorum(7), Some(BTreeSet::from([1, 2])));
+
+        pool.sign(7, 1, &messages, &params).unwrap();
+        assert!(matches!(
+            pool.sign(7, 1, &messages, &params),
+            Err(PresignatureError::AlreadyUsed(7, 1))
+        ));
+        assert!(matches!(
+            pool.sign(7, BATCH_SIZE, &messages, &params),
+            Err(PresignatureError::IndexOutOfRange(7, BATCH_SIZE))
+        ));
+
+        // A used up batch is dropped, so none of it is handed out again
+        pool.sign(7, 0, &messages, &params).unwrap();
+        assert_eq!(pool.quorum(7), None);
+        assert!(matches!(
+            pool.sign(7, 0, &messages, &params),
+            Err(PresignatureError::UnknownBatch(7))
+        ));
+    }
+
+    #[test]
+    fn keeps_an_index_used_when_signing_fails() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let params = params();
+        let mut pool = PresignaturePool::new(2);
+        pool.insert(7, batch(&mut rng));
+        assert!(matches!(
+            pool.sign(7, 0, &[Fr::from(1u64)], &params),
+            Err(PresignatureError::BBSPlus(_))
+        ));
+        assert!(matches!(
+            pool.sign(7, 0, &[Fr::from(1u64), Fr::from(2u64)], &params),
+            Err(PresignatureError::AlreadyUsed(7, 0))
+        ));
+    }
+
+    #[test]
+    fn evicts_the_oldest_batches() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut pool = PresignaturePool::new(2);
+        for batch_id in [3, 5, 4] {
+            pool.insert(batch_id, batch(&mut rng));
+        }
+        assert_eq!(pool.quorum(3), None);
+        assert!(pool.quorum(4).is_some() && pool.quorum(5).is_some());
+
+        pool.clear();
+        assert_eq!(pool.quorum(5), None);
+    }
+}
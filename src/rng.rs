@@ -0,0 +1,30 @@
+use ark_std::rand::{rngs::StdRng, SeedableRng};
+
+/// Seed of the benchmarks, so that every run signs the same messages with the same keys.
+pub const BENCH_SEED: u64 = 0;
+
+/// Randomness from OS entropy. Every node that holds key material draws from this.
+pub fn os_rng() -> StdRng {
+    StdRng::from_entropy()
+}
+
+/// Deterministic randomness for reproducible benchmarks and tests. Two nodes with the same seed
+/// draw the same "random" values, including their key shares.
+pub fn seeded_rng(seed: u64) -> StdRng {
+    StdRng::seed_from_u64(seed)
+}
+
+/// The randomness of a node: OS entropy, or a seeded RNG in a test run. `Config` only takes a
+/// seed in builds with the `insecure-rng` feature.
+pub fn node_rng(seed: Option<u64>) -> StdRng {
+    match seed {
+        None => os_rng(),
+        Some(seed) => {
+            eprintln!(
+                "Using the fixed RNG seed {}, never do this in production.",
+                seed
+            );
+            seeded_rng(seed)
+        }
+    }
+}
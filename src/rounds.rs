@@ -0,0 +1,265 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{Duration, Instant};
+
+use crate::constant::{CLOSED_SESSIONS_KEPT, SESSION_TIMEOUT_SECS};
+use crate::helper::message::{Message, ParticipantId, Payload, SessionId, NO_SESSION};
+
+/// The rounds a signer has taken in each session, to turn away messages it does not expect.
+/// Envelope sequence numbers already keep a recorded envelope from being replayed; this catches
+/// messages that are signed and new but duplicate a round, come before the request that opens
+/// their session, or belong to a session that is over.
+#[derive(Default)]
+pub struct Rounds {
+    open: BTreeMap<SessionId, OpenSession>,
+    /// Sessions that finished, failed or were aborted here. Only the latest
+    /// `CLOSED_SESSIONS_KEPT` are kept, anything older that is not open counts as over too.
+    closed: BTreeSet<SessionId>,
+    stale_below: SessionId,
+}
+
+struct OpenSession {
+    /// The request from the AS that opens a session of this kind.
+    request: &'static str,
+    requested: bool,
+    /// Rounds taken so far, by sender.
+    seen: BTreeSet<(ParticipantId, &'static str)>,
+    started: Instant,
+}
+
+/// For every round sent within a session: the request that opens such a session, and whether
+/// the round may come before that request reaches this signer. A signer deals its keygen
+/// shares and Phase 1 commitments as soon as the AS asks it to, which may be before the AS
+/// got to this signer; anything later is only sent once this signer took part.
+fn expected(round: &str) -> Option<(&'static str, bool)> {
+    match round {
+        "KeyStatusRequest" => Some(("KeyStatusRequest", false)),
+        "KeygenRequest" => Some(("KeygenRequest", false)),
+        "KeygenShare" => Some(("KeygenRequest", true)),
+        "RefreshRequest" => Some(("RefreshRequest", false)),
+        "RefreshShare" => Some(("RefreshRequest", true)),
+        "ReshareRequest" => Some(("ReshareRequest", false)),
+        "ReshareShare" => Some(("ReshareRequest", true)),
+        "Round1Request" => Some(("Round1Request", false)),
+        "Round1Commitment" => Some(("Round1Request", true)),
+        "Round1Shares" | "Round2Message1" | "Round2Message2" => Some(("Round1Request", false)),
+        "SignRequest" => Some(("SignRequest", false)),
+        _ => None,
+    }
+}
+
+impl Rounds {
+    /// Takes `payload` as the next round of its session, or says why it is not expected.
+    pub fn accept(&mut self, payload: &Payload) -> Result<(), String> {
+        let round = payload.msg.round();
+        let from_as = payload.sender == 0;
+        match &payload.msg {
+            Message::SkShares { .. } | Message::Abort if !from_as => {
+                return Err("Only the AS sends it".into())
+            }
+            Message::SkShares { .. } if payload.session != NO_SESSION => {
+                return Err("Key shares are dealt outside of sessions".into())
+            }
+            Message::SkShares { .. } => return Ok(()),
+            // Ending a session twice does no harm, the signer may have ended it first
+            Message::Abort => return Ok(()),
+            Message::BaseOTRestart
+            | Message::BaseOTSenderPubKey { .. }
+            | Message::BaseOTReceiverPubKey { .. }
+            | Message::BaseOTChallenges { .. }
+            | Message::BaseOTResponses { .. }
+            | Message::BaseOTHashedKeys { .. } => {
+                if from_as {
+                    return Err("Base OT only runs between signers".into());
+                }
+                if payload.session != NO_SESSION {
+                    return Err("Base OT runs outside of sessions".into());
+                }
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        if payload.session == NO_SESSION {
+            return Err("No session".into());
+        }
+        self.close_timed_out();
+        let session = payload.session;
+        if !self.open.contains_key(&session)
+            && (self.closed.contains(&session) || session < self.stale_below)
+        {
+            return Err("Session is over".into());
+        }
+        let Some((request, early)) = expected(round) else {
+            return Err("Signers take no part in this round".into());
+        };
+        if from_as != (round == request) {
+            return Err(if from_as {
+                "Only signers send it".into()
+            } else {
+                "Only the AS sends it".into()
+            });
+        }
+        let state = self.open.entry(session).or_insert_with(|| OpenSession {
+            request,
+            requested: false,
+            seen: BTreeSet::new(),
+            started: Instant::now(),
+        });
+        if state.request != request {
+            return Err(format!("Session runs {}", state.request));
+        }
+        if !from_as && !early && !state.requested {
+            return Err(format!("Came before the {}", request));
+        }
+        if !state.seen.insert((payload.sender, round)) {
+            return Err("Duplicate".into());
+        }
+        state.requested |= from_as;
+        Ok(())
+    }
+
+    /// Ends `session` here, so anything that still comes for it is stale.
+    pub fn close(&mut self, session: SessionId) {
+        if session == NO_SESSION {
+            return;
+        }
+        self.open.remove(&session);
+        self.closed.insert(session);
+        while self.closed.len() > CLOSED_SESSIONS_KEPT {
+            if let Some(oldest) = self.closed.pop_first() {
+                self.stale_below = self.stale_below.max(oldest + 1);
+            }
+        }
+    }
+
+    /// Closes sessions that did not finish in time, like `Signer` drops their state.
+    fn close_timed_out(&mut self) {
+        let timeout = Duration::from_secs(SESSION_TIMEOUT_SECS);
+        let timed_out: Vec<SessionId> = self
+            .open
+            .iter()
+            .filter(|(_, state)| state.started.elapsed() >= timeout)
+            .map(|(session, _)| *session)
+            .collect();
+        for session in timed_out {
+            self.close(session);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AS: ParticipantId = 0;
+    const SESSION: SessionId = 42;
+
+    fn payload(sender: ParticipantId, session: SessionId, msg: Message) -> Payload {
+        Payload {
+            sender,
+            session,
+            msg,
+        }
+    }
+
+    fn request() -> Message {
+        Message::Round1Request {
+            batch_size: 1,
+            quorum: vec![1, 2, 3],
+        }
+    }
+
+    fn commitment() -> Message {
+        Message::Round1Commitment {
+            commitments: String::new(),
+            commitment_zero_share: String::new(),
+        }
+    }
+
+    fn shares() -> Message {
+        Message::Round1Shares {
+            shares: String::new(),
+            zero_shares: String::new(),
+        }
+    }
+
+    #[test]
+    fn takes_rounds_after_the_request() {
+        let mut rounds = Rounds::default();
+        rounds.accept(&payload(AS, SESSION, request())).unwrap();
+        rounds.accept(&payload(2, SESSION, commitment())).unwrap();
+        rounds.accept(&payload(2, SESSION, shares())).unwrap();
+        rounds.accept(&payload(3, SESSION, shares())).unwrap();
+    }
+
+    #[test]
+    fn takes_only_early_rounds_before_the_request() {
+        let mut rounds = Rounds::default();
+        // Another signer may deal its commitments before the request reaches this one
+        rounds.accept(&payload(2, SESSION, commitment())).unwrap();
+        assert!(rounds.accept(&payload(2, SESSION, shares())).is_err());
+        rounds.accept(&payload(AS, SESSION, request())).unwrap();
+        rounds.accept(&payload(2, SESSION, shares())).unwrap();
+    }
+
+    #[test]
+    fn rejects_duplicate_rounds() {
+        let mut rounds = Rounds::default();
+        rounds.accept(&payload(AS, SESSION, request())).unwrap();
+        assert!(rounds.accept(&payload(AS, SESSION, request())).is_err());
+        rounds.accept(&payload(2, SESSION, commitment())).unwrap();
+        assert!(rounds.accept(&payload(2, SESSION, commitment())).is_err());
+    }
+
+    #[test]
+    fn rejects_rounds_from_the_wrong_side() {
+        let mut rounds = Rounds::default();
+        assert!(rounds.accept(&payload(2, SESSION, request())).is_err());
+        assert!(rounds.accept(&payload(AS, SESSION, commitment())).is_err());
+        assert!(rounds
+            .accept(&payload(2, NO_SESSION, Message::Abort))
+            .is_err());
+        assert!(rounds
+            .accept(&payload(AS, NO_SESSION, Message::BaseOTRestart))
+            .is_err());
+    }
+
+    #[test]
+    fn rejects_rounds_of_another_kind_of_session() {
+        let mut rounds = Rounds::default();
+        rounds.accept(&payload(AS, SESSION, request())).unwrap();
+        let keygen_share = Message::KeygenShare {
+            share: String::new(),
+            commitments: String::new(),
+        };
+        assert!(rounds.accept(&payload(2, SESSION, keygen_share)).is_err());
+    }
+
+    #[test]
+    fn rejects_rounds_of_closed_sessions() {
+        let mut rounds = Rounds::default();
+        rounds.accept(&payload(AS, SESSION, request())).unwrap();
+        rounds.close(SESSION);
+        assert!(rounds.accept(&payload(2, SESSION, shares())).is_err());
+        assert!(rounds.accept(&payload(2, SESSION, commitment())).is_err());
+    }
+
+    #[test]
+    fn forgets_old_closed_sessions_but_keeps_them_over() {
+        let mut rounds = Rounds::default();
+        for session in 100..100 + CLOSED_SESSIONS_KEPT as SessionId + 10 {
+            rounds.close(session);
+        }
+        assert_eq!(rounds.closed.len(), CLOSED_SESSIONS_KEPT);
+        assert!(rounds.accept(&payload(AS, 100, request())).is_err());
+        // Never seen, but older than every session still kept
+        assert!(rounds.accept(&payload(AS, 5, request())).is_err());
+        rounds
+            .accept(&payload(
+                AS,
+                100 + CLOSED_SESSIONS_KEPT as SessionId + 10,
+                request(),
+            ))
+            .unwrap();
+    }
+}
@@ -0,0 +1,310 @@
+use ark_bls12_381::Bls12_381;
+use bbs_plus::setup::SignatureParams23G1;
+use blake2::{Blake2b512, Digest};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use crate::attributes::AttributeSchema;
+
+const SCHEMA_ID_TAG: &[u8] = b"verisso-credential-schema";
+const SCHEMA_PARAMS_TAG: &[u8] = b"verisso-credential-params";
+
+#[derive(Debug)]
+pub enum SchemaError {
+    Io(std::io::Error),
+    Format(String),
+    /// Another schema of that name already has that version.
+    VersionTaken(String, u32),
+    /// A version of a schema that is not newer than the latest one registered.
+    Outdated {
+        name: String,
+        version: u32,
+        latest: u32,
+    },
+    UnknownSchema(String),
+    /// Signature parameters whose generators are not the schema's.
+    WrongParams(String),
+    /// A presentation revealing an attribute the schema keeps hidden, or one it does not have.
+    NotRevealable(usize),
+}
+
+impl std::fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Format(e) => write!(f, "Invalid schema registry: {}", e),
+            Self::VersionTaken(name, version) => {
+                write!(f, "Schema {} already has a version {}", name, version)
+            }
+            Self::Outdated {
+                name,
+                version,
+                latest,
+            } => write!(
+                f,
+                "Version {} of schema {} is not newer than version {}",
+                version, name, latest
+            ),
+            Self::UnknownSchema(id) => write!(f, "No schema {} in the registry", id),
+            Self::WrongParams(id) => {
+                write!(f, "Signature parameters are not the ones of schema {}", id)
+            }
+            Self::NotRevealable(index) => write!(f, "Message {} may not be revealed", index),
+        }
+    }
+}
+
+impl From<std::io::Error> for SchemaError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// A version of a credential type: what each message of its credentials means, how it is
+/// encoded and whether it may be revealed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CredentialSchema {
+    pub name: String,
+    pub version: u32,
+    pub attributes: AttributeSchema,
+}
+
+impl CredentialSchema {
+    pub fn new(name: &str, version: u32, attributes: AttributeSchema) -> Self {
+        Self {
+            name: name.to_string(),
+            version,
+            attributes,
+        }
+    }
+
+    /// ID of the schema, the hex of a hash of all its fields, so no two schemas share one.
+    pub fn id(&self) -> String {
+        let attributes = serde_json::to_vec(&self.attributes).unwrap();
+        let digest = Blake2b512::new()
+            .chain_update(SCHEMA_ID_TAG)
+            .chain_update((self.name.len() as u64).to_be_bytes())
+            .chain_update(self.name.as_bytes())
+            .chain_update(self.version.to_be_bytes())
+            .chain_update(attributes)
+            .finalize();
+        digest.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    pub fn message_count(&self) -> u32 {
+        self.attributes.len() as u32
+    }
+
+    /// Signature parameters of credentials of this schema. Their generators are derived from
+    /// the schema ID, so a credential only verifies under the schema it was issued under.
+    pub fn params(&self) -> SignatureParams23G1<Bls12_381> {
+        let label = [SCHEMA_PARAMS_TAG, self.id().as_bytes()].concat();
+        SignatureParams23G1::<Bls12_381>::new::<Blake2b512>(&label, self.message_count())
+    }
+
+    /// Checks that `params` are this schema's.
+    pub fn check_params(&self, params: &SignatureParams23G1<Bls12_381>) -> Result<(), SchemaError> {
+        if *params != self.params() {
+            return Err(SchemaError::WrongParams(self.id()));
+        }
+        Ok(())
+    }
+
+    /// Checks that a presentation may reveal the messages at `indices`.
+    pub fn check_revealed(&self, indices: &BTreeSet<usize>) -> Result<(), SchemaError> {
+        let attributes = self.attributes.attributes();
+        match indices
+            .iter()
+            .find(|index| !attributes.get(**index).is_some_and(|a| a.revealable))
+        {
+            Some(index) => Err(SchemaError::NotRevealable(*index)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Every known version of every credential schema, by schema ID. A schema is never changed
+/// once registered, changing it takes a new version.
+#[derive(Default)]
+pub struct SchemaRegistry {
+    schemas: BTreeMap<String, CredentialSchema>,
+}
+
+impl SchemaRegistry {
+    /// Loads the registry stored at `path`, or an empty one if there is none yet.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SchemaError> {
+        let path = path.as_ref();
+        let mut registry = Self::default();
+        if !path.exists() {
+            return Ok(registry);
+        }
+        let schemas: Vec<CredentialSchema> = serde_json::from_str(&std::fs::read_to_string(path)?)
+            .map_err(|e| SchemaError::Format(e.to_string()))?;
+        for schema in schemas {
+            registry.register(schema)?;
+        }
+        Ok(registry)
+    }
+
+    /// Stores every schema at `path`, oldest version first.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SchemaError> {
+        let mut schemas = self.schemas.values().collect::<Vec<_>>();
+        schemas.sort_by(|a, b| (&a.name, a.version).cmp(&(&b.name, b.version)));
+        let json = serde_json::to_string_pretty(&schemas)
+            .map_err(|e| SchemaError::Format(e.to_string()))?;
+        std::fs::write(path, json)?;
+        Ok(())
+    }
+
+    /// Adds `schema` as the newest version of its name and returns its ID. Registering a
+    /// schema again is a no-op.
+    pub fn register(&mut self, schema: CredentialSchema) -> Result<String, SchemaError> {
+        let id = schema.id();
+        if self.schemas.contains_key(&id) {
+            return Ok(id);
+        }
+        if let Some(latest) = self.latest(&schema.name) {
+            if latest.version == schema.version {
+                return Err(SchemaError::VersionTaken(schema.name, schema.version));
+            }
+            if latest.version > schema.version {
+                return Err(SchemaError::Outdated {
+                    name: schema.name,
+                    version: schema.version,
+                    latest: latest.version,
+                });
+            }
+        }
+        self.schemas.insert(id.clone(), schema);
+        Ok(id)
+    }
+
+    pub fn get(&self, id: &str) -> Result<&CredentialSchema, SchemaError> {
+        self.schemas
+            .get(id)
+            .ok_or_else(|| SchemaError::UnknownSchema(id.to_string()))
+    }
+
+    /// The newest version of schema `name`.
+    pub fn latest(&self, name: &str) -> Option<&CredentialSchema> {
+        self.schemas
+            .values()
+            .filter(|schema| schema.name == name)
+            .max_by_key(|schema| schema.version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attributes::AttributeType;
+    use std::path::PathBuf;
+
+    fn schema(name: &str, version: u32) -> CredentialSchema {
+        let attributes = AttributeSchema::of(&[
+            ("name", AttributeType::String),
+            ("born", AttributeType::Date),
+        ])
+        .unwrap();
+        CredentialSchema::new(name, version, attributes)
+    }
+
+    /// A registry file in a fresh directory for `test`.
+    fn registry_path(test: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("verisso-schema-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join("schemas.json")
+    }
+
+    #[test]
+    fn ids_cover_every_field() {
+        let id = schema("passport", 1).id();
+        assert_eq!(id.len(), 128);
+        assert_eq!(id, schema("passport", 1).id());
+        assert_ne!(id, schema("passport", 2).id());
+        assert_ne!(id, schema("license", 1).id());
+
+        let mut other = schema("passport", 1);
+        other.attributes = AttributeSchema::of(&[("name", AttributeType::String)]).unwrap();
+        assert_ne!(id, other.id());
+    }
+
+    #[test]
+    fn registers_newer_versions_only() {
+        let mut registry = SchemaRegistry::default();
+        assert!(registry.latest("passport").is_none());
+        let v1 = registry.register(schema("passport", 1)).unwrap();
+        // Registering a schema again is a no-op
+        assert_eq!(registry.register(schema("passport", 1)).unwrap(), v1);
+        let v2 = registry.register(schema("passport", 2)).unwrap();
+        assert_eq!(registry.latest("passport"), Some(&schema("passport", 2)));
+        assert_eq!(registry.get(&v1).unwrap(), &schema("passport", 1));
+        assert_eq!(registry.get(&v2).unwrap().version, 2);
+
+        let mut changed = schema("passport", 2);
+        changed.attributes = AttributeSchema::of(&[("name", AttributeType::String)]).unwrap();
+        assert!(matches!(
+            registry.register(changed),
+            Err(SchemaError::VersionTaken(_, 2))
+        ));
+        assert!(matches!(
+            registry.register(CredentialSchema::new(
+                "passport",
+                0,
+                schema("passport", 1).attributes
+            )),
+            Err(SchemaError::Outdated { latest: 2, .. })
+        ));
+        assert!(matches!(
+            registry.get("unknown"),
+            Err(SchemaError::UnknownSchema(_))
+        ));
+    }
+
+    #[test]
+    fn loads_what_it_saved() {
+        let path = registry_path("save");
+        assert!(SchemaRegistry::load(&path)
+            .unwrap()
+            .latest("passport")
+            .is_none());
+
+        let mut registry = SchemaRegistry::default();
+        let ids = [
+            registry.register(schema("passport", 1)).unwrap(),
+            registry.register(schema("passport", 2)).unwrap(),
+            registry.register(schema("license", 1)).unwrap(),
+        ];
+        registry.save(&path).unwrap();
+        let loaded = SchemaRegistry::load(&path).unwrap();
+        for id in &ids {
+            assert_eq!(loaded.get(id).unwrap(), registry.get(id).unwrap());
+        }
+        assert_eq!(loaded.latest("passport").unwrap().version, 2);
+
+        std::fs::write(&path, "not json").unwrap();
+        assert!(matches!(
+            SchemaRegistry::load(&path),
+            Err(SchemaError::Format(_))
+        ));
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn checks_params_of_the_schema() {
+        let schema = schema("passport", 1);
+        assert!(schema.check_params(&schema.params()).is_ok());
+        assert!(matches!(
+            schema.check_params(
+                &CredentialSchema::new("passport", 2, schema.attributes.clone()).params()
+            ),
+            Err(SchemaError::WrongParams(_))
+        ));
+        let same_count =
+            SignatureParams23G1::<Bls12_381>::new::<Blake2b512>(b"other", schema.message_count());
+        assert!(schema.check_params(&same_count).is_err());
+    }
+}
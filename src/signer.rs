@@ -1,109 +1,1435 @@
 // use crate::constant::*;
+use crate::audit::AuditLog;
 use crate::config::Config;
 use crate::constant::*;
-use crate::ot::do_pairwise_base_ot;
-use ark_bls12_381::Fr;
-use ark_std::rand::{rngs::StdRng, SeedableRng};
+use crate::helper::encoder::Encoder;
+use crate::helper::message::{SessionId, NO_SESSION};
+use crate::keystore::{key_id, KeyMetadata, Keystore, KeystoreError};
+use crate::ot::PairwiseBaseOT;
+use crate::policy::IssuancePolicy;
+use crate::presignature::{
+    BatchCommitments, PresignatureBatch, PresignatureError, PresignaturePool,
+};
+use crate::rng::node_rng;
+use crate::rounds::Rounds;
+use ark_bls12_381::{Bls12_381, Fr, G1Affine, G2Affine, G2Projective};
+use ark_ec::{AffineRepr, CurveGroup};
+use ark_ff::Zero;
+use ark_std::rand::rngs::StdRng;
+use bbs_plus::error::BBSPlusError;
+use bbs_plus::setup::{PublicKeyG2, SignatureParams23G1};
 use bbs_plus::threshold::multiplication_phase::Phase2;
 use bbs_plus::threshold::randomness_generation_phase::Phase1;
-use bbs_plus::threshold::threshold_bbs::Phase1Output;
+use bbs_plus::threshold::threshold_bbs::{BBSSignatureShare, Phase1Output};
 use blake2::Blake2b512;
+use oblivious_transfer_protocols::base_ot::simplest_ot::ReceiverPubKeys;
 use oblivious_transfer_protocols::cointoss::Commitments;
-use oblivious_transfer_protocols::ot_based_multiplication::batch_mul_multi_party::Message1;
+use oblivious_transfer_protocols::error::OTError;
+use oblivious_transfer_protocols::ot_based_multiplication::base_ot_multi_party_pairwise::SenderPubKeyAndProof;
+use oblivious_transfer_protocols::ot_based_multiplication::batch_mul_multi_party::{
+    Message1, Message2,
+};
 use oblivious_transfer_protocols::ot_based_multiplication::{
     dkls18_mul_2p::MultiplicationOTEParams, dkls19_batch_mul_2p::GadgetVector,
 };
 use oblivious_transfer_protocols::ParticipantId;
-use std::collections::{BTreeMap, BTreeSet};
+use secret_sharing_and_dkg::common::{
+    lagrange_basis_at_0, CommitmentToCoefficients, Share, SharesAccumulator,
+};
+use secret_sharing_and_dkg::error::SSError;
+use secret_sharing_and_dkg::feldman_vss::{self, deal_random_secret};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::time::{Duration, Instant};
+use zeroize::{Zeroize, Zeroizing};
+
+/// Shares (and their salts) revealed to another signer once all Phase 1 commitments are in.
+pub type SharesAndSalts = Vec<(Fr, [u8; 256])>;
+
+/// Feldman shares this signer dealt to the other signers, with the commitments to its polynomial.
+pub type KeygenShares = (
+    BTreeMap<ParticipantId, Share<Fr>>,
+    CommitmentToCoefficients<G2Affine>,
+);
+
+/// A resharing of the key to a new committee, as announced by the AS.
+pub struct Reshare {
+    pub epoch: u64,
+    /// Current signers that deal their shares. Any `threshold` of them determine the key.
+    pub dealers: BTreeSet<ParticipantId>,
+    pub committee: BTreeSet<ParticipantId>,
+    pub threshold: u16,
+    /// The public key, which the new shares must still match.
+    pub public_key: PublicKeyG2<Bls12_381>,
+}
+
+/// `Message1`s this signer sends as multiplication receiver and `Message2`s it sends back as
+/// multiplication sender.
+pub type Round2Messages = (
+    BTreeMap<ParticipantId, Message1<Fr>>,
+    BTreeMap<ParticipantId, Message2<Fr>>,
+);
+
+/// Encodes the deployment, the session and its signers after `tag`, each length-prefixed so
+/// that no two sessions share an encoding.
+fn session_domain(
+    tag: &[u8],
+    deployment_id: &str,
+    session: SessionId,
+    quorum: &BTreeSet<ParticipantId>,
+) -> Vec<u8> {
+    let mut domain = tag.to_vec();
+    domain.extend_from_slice(&(deployment_id.len() as u32).to_be_bytes());
+    domain.extend_from_slice(deployment_id.as_bytes());
+    domain.extend_from_slice(&session.to_be_bytes());
+    domain.extend_from_slice(&(quorum.len() as u16).to_be_bytes());
+    for id in quorum {
+        domain.extend_from_slice(&id.to_be_bytes());
+    }
+    domain
+}
+
+/// Why a step of a presignature session could not be taken.
+#[derive(Debug)]
+pub enum PresignError {
+    UnknownSession(SessionId),
+    /// `step` does not follow from the state the session is in, e.g. Phase 2 before Phase 1
+    /// finished.
+    WrongState {
+        step: &'static str,
+        state: &'static str,
+    },
+    /// Phase 1 needs the key share, which has not been dealt or generated yet.
+    NoKeyShare,
+    Protocol(BBSPlusError),
+}
+
+impl From<BBSPlusError> for PresignError {
+    fn from(e: BBSPlusError) -> Self {
+        Self::Protocol(e)
+    }
+}
+
+/// Where a presignature session stands on this signer. Every step checks that it follows from
+/// the current state, and only `finish_round2` leaves a batch behind.
+enum PresignState {
+    /// Waiting for the request from the AS. Messages of other signers that come first are kept.
+    Idle,
+    /// Committed to the Phase 1 randomness, collecting the commitments and then the shares of
+    /// the other signers.
+    Round1Committed(Phase1<Fr, 256>),
+    /// Phase 1 finished. Phase 2 starts once base OT with every other signer is done.
+    Round1Done(Phase1Output<Fr>),
+    /// Running the Phase 2 multiplications with the other signers.
+    Round2Started(Phase1Output<Fr>, Phase2<Fr, 256, 80>),
+    /// The presignature batch is in the pool and its commitments went to the AS. Kept until the
+    /// session times out, so a late message finds the session over instead of a new one.
+    ShareIssued,
+}
+
+impl PresignState {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Idle => "Idle",
+            Self::Round1Committed(_) => "Round1Committed",
+            Self::Round1Done(_) => "Round1Done",
+            Self::Round2Started(..) => "Round2Started",
+            Self::ShareIssued => "ShareIssued",
+        }
+    }
+
+    fn wrong(&self, step: &'static str) -> PresignError {
+        PresignError::WrongState {
+            step,
+            state: self.name(),
+        }
+    }
+}
+
+/// Phase 1 and Phase 2 state of one presignature session. Never leaves this signer.
+struct PresignSession {
+    state: PresignState,
+    /// The signers of the session, picked by the AS among the live signers. Empty until the
+    /// request from the AS arrives.
+    quorum: BTreeSet<ParticipantId>,
+    /// Gadget vector of the session's multiplications, derived along with the protocol ID once
+    /// the quorum is known.
+    gadget_vector: Option<GadgetVector<Fr, KAPPA, STATISTICAL_SECURITY_PARAMETER>>,
+    /// Commitments from other signers that arrived before this signer started Phase 1.
+    pending_commitments: BTreeMap<ParticipantId, (Commitments, Commitments)>,
+    /// `Message1`s from other signers that arrived before this signer started Phase 2.
+    pending_message1s: BTreeMap<ParticipantId, Message1<Fr>>,
+    /// When the first message of the session arrived, to drop sessions that never finish.
+    started: Instant,
+}
+
+impl PresignSession {
+    fn new() -> Self {
+        Self {
+            state: PresignState::Idle,
+            quorum: BTreeSet::new(),
+            gadget_vector: None,
+            pending_commitments: BTreeMap::new(),
+            pending_message1s: BTreeMap::new(),
+            started: Instant::now(),
+        }
+    }
+
+    fn others(&self, id: ParticipantId) -> BTreeSet<ParticipantId> {
+        let mut others = self.quorum.clone();
+        others.remove(&id);
+        others
+    }
+}
 
 pub struct Signer {
     pub id: u16,
     pub sk_share: Option<Fr>,
+    /// Number of share refreshes since keygen. Shares of different epochs never combine.
+    pub epoch: u64,
+    /// Threshold public key, derived from the DKG commitments or handed out by a trusted dealer.
+    pub public_key: Option<PublicKeyG2<Bls12_381>>,
+    /// OS entropy, unless a test build was given a seed.
     pub rng: StdRng,
     pub all_party_set: BTreeSet<u16>,
-    pub threshold_party_set: BTreeSet<u16>,
-    pub protocol_id: Vec<u8>,
+    /// Number of signers needed to sign, the threshold of the Shamir sharing of the key.
+    pub threshold: u16,
+    /// Every presignature session's protocol ID and gadget vector are derived from it.
+    pub deployment_id: String,
+    pub params: SignatureParams23G1<Bls12_381>,
+    pub ote_params: MultiplicationOTEParams<KAPPA, STATISTICAL_SECURITY_PARAMETER>,
+    /// Shares received during DKG. Created by whichever comes first, dealing or the first share.
+    keygen: Option<SharesAccumulator<G2Affine, Share<Fr>>>,
+    /// Zero shares received during the refresh into the given epoch.
+    refresh: Option<(u64, SharesAccumulator<G2Affine, Share<Fr>>)>,
+    /// The running resharing and the shares dealt to this signer so far, which may arrive before
+    /// the request from the AS does.
+    reshare: Option<Reshare>,
+    reshare_shares: BTreeMap<ParticipantId, (u64, Share<Fr>, CommitmentToCoefficients<G2Affine>)>,
+    /// Session of the last keygen, refresh or resharing this signer was asked to run.
+    key_session: SessionId,
+    /// Base OT with the other signers, run once and reused by every Phase 2.
+    pub base_ot: PairwiseBaseOT,
+    /// Running presignature sessions. Each computes the batch named after its session.
+    sessions: HashMap<SessionId, PresignSession>,
+    /// Finished presignature batches.
+    pub presignatures: PresignaturePool,
+    /// Rounds taken in every session, to reject duplicated, early and stale messages.
+    pub rounds: Rounds,
+    /// What this signer signs tokens for, whatever the AS asks.
+    pub policy: IssuancePolicy,
+    /// Record of every session this signer completed.
+    pub audit: AuditLog,
+    /// Where the key share is kept across restarts, if a passphrase was configured.
+    keystore: Option<Keystore>,
 }
 
 impl Signer {
     pub fn new(config: Config) -> Self {
+        let ote_params = MultiplicationOTEParams::<KAPPA, STATISTICAL_SECURITY_PARAMETER> {};
+
         Signer {
-            rng: StdRng::seed_from_u64(0u64),
-            all_party_set: (1..=config.total_nodes)
-                .into_iter()
-                .collect::<BTreeSet<_>>(),
+            rng: node_rng(config.rng_seed),
+            all_party_set: (1..=config.signers).collect::<BTreeSet<_>>(),
             // THRESHOLD_SIGNERS -> TOTAL_SIGNERS
-            threshold_party_set: (1..=config.threshold_signers)
-                .into_iter()
-                .collect::<BTreeSet<_>>(),
-            protocol_id: b"test".to_vec(),
+            threshold: config.threshold_signers,
+            deployment_id: config.deployment_id.clone(),
+            params: SignatureParams23G1::<Bls12_381>::new::<Blake2b512>(
+                SIG_PARAMS_LABEL,
+                config.message_count,
+            ),
+            ote_params,
+            base_ot: PairwiseBaseOT::new(config.node_id, ote_params.num_base_ot()),
             id: config.node_id,
             sk_share: None,
+            public_key: None,
+            keygen: None,
+            epoch: 0,
+            refresh: None,
+            reshare: None,
+            reshare_shares: BTreeMap::new(),
+            key_session: NO_SESSION,
+            sessions: HashMap::new(),
+            presignatures: PresignaturePool::new(PRESIGNATURE_MAX_BATCHES),
+            rounds: Rounds::default(),
+            policy: IssuancePolicy::new(&config),
+            audit: AuditLog::new(&config.audit_log_path, config.node_id),
+            keystore: config
+                .keystore_passphrase
+                .map(|passphrase| Keystore::new(&config.keystore_path, passphrase)),
         }
     }
 
-    pub fn set_sk_share(&mut self, sk_share: Fr) {
+    /// Replaces the key share. The old one is wiped from memory.
+    pub fn set_sk_share(&mut self, sk_share: Fr, public_key: PublicKeyG2<Bls12_381>) {
+        self.delete_sk_share();
         self.sk_share = Some(sk_share);
+        self.public_key = Some(public_key);
+    }
+
+    fn delete_sk_share(&mut self) {
+        if let Some(sk_share) = self.sk_share.as_mut() {
+            sk_share.zeroize();
+        }
+        self.sk_share = None;
+    }
+
+    pub fn has_keystore(&self) -> bool {
+        self.keystore.is_some()
+    }
+
+    /// Loads the key share stored by an earlier run, along with its epoch and committee.
+    /// Returns false if there is none.
+    pub fn load_key_share(&mut self) -> Result<bool, KeystoreError> {
+        let Some(keystore) = self.keystore.as_ref().filter(|keystore| keystore.exists()) else {
+            return Ok(false);
+        };
+        let (sk_share, metadata) = keystore.load(self.id)?;
+        let public_key = Encoder::decode_public_key(&metadata.public_key)
+            .map_err(|e| KeystoreError::Format(e.to_string()))?;
+        if key_id(&public_key) != metadata.key_id {
+            return Err(KeystoreError::Format(
+                "Key ID does not match the public key".into(),
+            ));
+        }
+
+        self.set_sk_share(sk_share, public_key);
+        self.epoch = metadata.epoch;
+        self.threshold = metadata.threshold;
+        self.all_party_set = metadata.committee.into_iter().collect();
+        Ok(true)
+    }
+
+    /// Stores the current key share, encrypted, or deletes the stored one once this signer holds
+    /// none. Does nothing without a keystore.
+    pub fn store_key_share(&mut self) -> Result<(), KeystoreError> {
+        let Some(keystore) = self.keystore.as_ref() else {
+            return Ok(());
+        };
+        let (Some(sk_share), Some(public_key)) = (self.sk_share.as_ref(), self.public_key.as_ref())
+        else {
+            return keystore.delete();
+        };
+        let metadata = KeyMetadata {
+            key_id: key_id(public_key),
+            node_id: self.id,
+            epoch: self.epoch,
+            threshold: self.threshold,
+            committee: self.all_party_set.iter().copied().collect(),
+            public_key: Encoder::encode_public_key(public_key)
+                .map_err(|e| KeystoreError::Format(e.to_string()))?,
+        };
+        keystore.save(&mut self.rng, sk_share, &metadata)
+    }
+
+    /// This signer's public key share, `params.g2 * sk_share`. Any `threshold` of them
+    /// interpolate to the public key.
+    pub fn public_key_share(&self) -> Option<PublicKeyG2<Bls12_381>> {
+        self.sk_share
+            .as_ref()
+            .map(|sk_share| PublicKeyG2((self.params.g2 * sk_share).into_affine()))
+    }
+
+    /// Starts DKG by dealing a random secret among all signers with Feldman VSS. The commitments
+    /// are over `params.g2`, so the commitments to the constant terms add up to the public key.
+    pub fn start_keygen(&mut self, session: SessionId) -> Result<KeygenShares, SSError> {
+        self.key_session = session;
+        // Deal up to the largest committee id, the committee need not be contiguous
+        let total = *self.all_party_set.last().unwrap();
+        let threshold = self.threshold;
+        let (_, shares, comms, _) =
+            deal_random_secret::<_, G2Affine>(&mut self.rng, threshold, total, &self.params.g2)?;
+
+        let mut others = BTreeMap::new();
+        for share in shares.0 {
+            if share.id == self.id {
+                self.keygen_accumulator()
+                    .add_self_share(share, comms.clone());
+            } else if self.all_party_set.contains(&share.id) {
+                others.insert(share.id, share);
+            }
+        }
+        Ok((others, comms))
+    }
+
+    /// Verifies the share dealt by `sender` against its commitments. Returns true once a share
+    /// from every signer, including this one, is in.
+    pub fn receive_keygen_share(
+        &mut self,
+        sender: ParticipantId,
+        share: Share<Fr>,
+        comms: CommitmentToCoefficients<G2Affine>,
+    ) -> Result<bool, SSError> {
+        let g2 = self.params.g2;
+        self.keygen_accumulator()
+            .add_received_share(sender, share, comms, &g2)?;
+        Ok(self.has_all_keygen_shares())
+    }
+
+    pub fn has_all_keygen_shares(&self) -> bool {
+        match &self.keygen {
+            Some(keygen) => keygen.shares.len() == self.all_party_set.len(),
+            None => false,
+        }
+    }
+
+    /// Combines the received shares into this signer's secret key share and derives the
+    /// threshold public key from the commitments. The full secret key never exists anywhere.
+    pub fn finish_keygen(&mut self) -> Result<PublicKeyG2<Bls12_381>, SSError> {
+        let keygen = self
+            .keygen
+            .take()
+            .ok_or(SSError::BelowThreshold(self.threshold, 0))?;
+        let (share, _, threshold_pk) = keygen.finalize(&self.params.g2)?;
+
+        let public_key = PublicKeyG2(threshold_pk);
+        self.set_sk_share(share.share, public_key.clone());
+        Ok(public_key)
+    }
+
+    fn keygen_accumulator(&mut self) -> &mut SharesAccumulator<G2Affine, Share<Fr>> {
+        let (id, threshold) = (self.id, self.threshold);
+        self.keygen
+            .get_or_insert_with(|| SharesAccumulator::new(id, threshold))
+    }
+
+    /// Starts the refresh into `epoch` by dealing a Feldman sharing of zero among all signers.
+    /// Adding up everyone's zero shares rerandomizes the key shares but keeps the secret, and so
+    /// the public key, unchanged.
+    pub fn start_refresh(
+        &mut self,
+        session: SessionId,
+        epoch: u64,
+    ) -> Result<KeygenShares, SSError> {
+        self.check_refresh_epoch(epoch)?;
+        self.key_session = session;
+        // Deal up to the largest committee id, the committee need not be contiguous
+        let total = *self.all_party_set.last().unwrap();
+        let threshold = self.threshold;
+        let (shares, comms, _) = feldman_vss::deal_secret::<_, G2Affine>(
+            &mut self.rng,
+            Fr::zero(),
+            threshold,
+            total,
+            &self.params.g2,
+        )?;
+
+        let mut others = BTreeMap::new();
+        for share in shares.0 {
+            if share.id == self.id {
+                self.refresh_accumulator(epoch)
+                    .add_self_share(share, comms.clone());
+            } else if self.all_party_set.contains(&share.id) {
+                others.insert(share.id, share);
+            }
+        }
+        Ok((others, comms))
+    }
+
+    /// Verifies the zero share dealt by `sender` for the refresh into `epoch`. Returns true once
+    /// a zero share from every signer, including this one, is in.
+    pub fn receive_refresh_share(
+        &mut self,
+        epoch: u64,
+        sender: ParticipantId,
+        share: Share<Fr>,
+        comms: CommitmentToCoefficients<G2Affine>,
+    ) -> Result<bool, SSError> {
+        self.check_refresh_epoch(epoch)?;
+        // A dealer that shares anything but zero would change the key
+        if !comms.commitment_to_secret().is_zero() {
+            return Err(SSError::InvalidShare);
+        }
+        let g2 = self.params.g2;
+        self.refresh_accumulator(epoch)
+            .add_received_share(sender, share, comms, &g2)?;
+        Ok(self.has_all_refresh_shares())
+    }
+
+    pub fn has_all_refresh_shares(&self) -> bool {
+        match &self.refresh {
+            Some((_, refresh)) => refresh.shares.len() == self.all_party_set.len(),
+            None => false,
+        }
+    }
+
+    /// Adds the zero shares to the key share in place, so the old share is gone, and moves to
+    /// the new epoch. Returns the new epoch.
+    pub fn finish_refresh(&mut self) -> Result<u64, SSError> {
+        let (epoch, refresh) = self
+            .refresh
+            .take()
+            .ok_or(SSError::BelowThreshold(self.threshold, 0))?;
+        let (delta, _, zero_pk) = refresh.finalize(&self.params.g2)?;
+        if !zero_pk.is_zero() {
+            return Err(SSError::InvalidShare);
+        }
+
+        let sk_share = self
+            .sk_share
+            .as_mut()
+            .ok_or(SSError::BelowThreshold(self.threshold, 0))?;
+        *sk_share += delta.share;
+        self.epoch = epoch;
+        // Presignatures embed the old shares
+        self.sessions.clear();
+        self.presignatures.clear();
+        Ok(epoch)
+    }
+
+    /// Only the refresh into the next epoch is accepted.
+    fn check_refresh_epoch(&self, epoch: u64) -> Result<(), SSError> {
+        if epoch != self.epoch + 1 || self.sk_share.is_none() {
+            return Err(SSError::InvalidShare);
+        }
+        Ok(())
+    }
+
+    fn refresh_accumulator(&mut self, epoch: u64) -> &mut SharesAccumulator<G2Affine, Share<Fr>> {
+        let (id, threshold) = (self.id, self.threshold);
+        &mut self
+            .refresh
+            .get_or_insert_with(|| (epoch, SharesAccumulator::new(id, threshold)))
+            .1
+    }
+
+    /// Starts resharing. A dealer shares its key share among the new committee with the new
+    /// threshold, and returns the shares for the other committee members.
+    pub fn start_reshare(
+        &mut self,
+        session: SessionId,
+        reshare: Reshare,
+    ) -> Result<Option<KeygenShares>, SSError> {
+        // A signer outside the committee skipped the refreshes since it left, so only require
+        // the epoch to move forward
+        if reshare.epoch <= self.epoch {
+            return Err(SSError::InvalidShare);
+        }
+        if reshare.threshold == 0 || reshare.threshold as usize > reshare.committee.len() {
+            return Err(SSError::InvalidThresholdOrTotal(
+                reshare.threshold,
+                reshare.committee.len() as u16,
+            ));
+        }
+
+        // The dealers come from the committee this signer belongs to
+        if self.sk_share.is_some() {
+            if let Some(dealer) = reshare
+                .dealers
+                .iter()
+                .find(|dealer| !self.all_party_set.contains(dealer))
+            {
+                return Err(SSError::InvalidParticipantId(*dealer));
+            }
+        }
+
+        self.key_session = session;
+        // Shares that came before the request count only if they are from a dealer to a
+        // member of the new committee
+        if reshare.committee.contains(&self.id) {
+            self.reshare_shares
+                .retain(|dealer, _| reshare.dealers.contains(dealer));
+        } else {
+            self.reshare_shares.clear();
+        }
+        let mut dealt = None;
+        if reshare.dealers.contains(&self.id) {
+            // `deal_secret` takes the share by value, so the copy is wiped once dealt
+            let sk_share = self
+                .sk_share
+                .map(Zeroizing::new)
+                .ok_or(SSError::InvalidParticipantId(self.id))?;
+            // Shares are dealt for ids up to the largest member, then picked by member id, so
+            // the committee does not need contiguous ids
+            let total = *reshare.committee.last().unwrap();
+            let (shares, comms, _) = feldman_vss::deal_secret::<_, G2Affine>(
+                &mut self.rng,
+                *sk_share,
+                reshare.threshold,
+                total,
+                &self.params.g2,
+            )?;
+
+            let mut others = BTreeMap::new();
+            for share in shares.0 {
+                if share.id == self.id {
+                    self.reshare_shares
+                        .insert(self.id, (reshare.epoch, share, comms.clone()));
+                } else if reshare.committee.contains(&share.id) {
+                    others.insert(share.id, share);
+                }
+            }
+            dealt = Some((others, comms));
+        }
+
+        self.reshare = Some(reshare);
+        Ok(dealt)
+    }
+
+    /// Verifies the share `sender` dealt to this signer for the resharing into `epoch`. Once
+    /// the request from the AS is in, only its dealers may deal, and only to members of the new
+    /// committee. Returns true once resharing can be finished.
+    pub fn receive_reshare_share(
+        &mut self,
+        epoch: u64,
+        sender: ParticipantId,
+        share: Share<Fr>,
+        comms: CommitmentToCoefficients<G2Affine>,
+    ) -> Result<bool, SSError> {
+        if epoch <= self.epoch || share.id != self.id {
+            return Err(SSError::InvalidShare);
+        }
+        if let Some(reshare) = &self.reshare {
+            if !reshare.dealers.contains(&sender) {
+                return Err(SSError::InvalidParticipantId(sender));
+            }
+            if !reshare.committee.contains(&self.id) {
+                return Err(SSError::InvalidParticipantId(self.id));
+            }
+        }
+        share.verify(&comms, &self.params.g2)?;
+        self.reshare_shares.insert(sender, (epoch, share, comms));
+        Ok(self.has_all_reshare_shares())
+    }
+
+    /// True once resharing has started and, for a member of the new committee, a share from
+    /// every dealer is in. Signers leaving the committee are done once they have dealt.
+    pub fn has_all_reshare_shares(&self) -> bool {
+        match &self.reshare {
+            Some(reshare) if reshare.committee.contains(&self.id) => {
+                reshare.dealers.iter().all(|dealer| {
+                    self.reshare_shares
+                        .get(dealer)
+                        .is_some_and(|(epoch, _, _)| *epoch == reshare.epoch)
+                })
+            }
+            Some(_) => true,
+            None => false,
+        }
+    }
+
+    /// Interpolates the dealt shares into this signer's share for the new committee and checks
+    /// that it still belongs to the same public key. A signer leaving the committee deletes its
+    /// share. Returns the new epoch.
+    pub fn finish_reshare(&mut self) -> Result<u64, SSError> {
+        let reshare = self
+            .reshare
+            .take()
+            .ok_or(SSError::InvalidParticipantId(self.id))?;
+        let received = std::mem::take(&mut self.reshare_shares);
+
+        if reshare.committee.contains(&self.id) {
+            let dealer_ids = reshare.dealers.iter().copied().collect::<Vec<_>>();
+            let mut new_share = Fr::zero();
+            let mut commitment_to_key = G2Projective::zero();
+            for dealer in &dealer_ids {
+                let (_, share, comms) = received
+                    .get(dealer)
+                    .ok_or(SSError::MissingSomeParticipants(*dealer))?;
+                let lagrange = lagrange_basis_at_0::<Fr>(&dealer_ids, *dealer)?;
+                new_share += lagrange * share.share;
+                commitment_to_key += *comms.commitment_to_secret() * lagrange;
+            }
+            // The dealt secrets must interpolate to the secret key behind the public key
+            if commitment_to_key.into_affine() != reshare.public_key.0 {
+                return Err(SSError::InvalidShare);
+            }
+
+            self.set_sk_share(new_share, reshare.public_key);
+        } else {
+            self.delete_sk_share();
+        }
+
+        self.threshold = reshare.threshold;
+        self.all_party_set = reshare.committee;
+        self.epoch = reshare.epoch;
+        self.sessions.clear();
+        self.presignatures.clear();
+        Ok(self.epoch)
+    }
+
+    /// Starts base OT with `other` unless it already ran. Returns the public key to send when
+    /// this signer is the OT sender.
+    pub fn start_base_ot(
+        &mut self,
+        other: ParticipantId,
+    ) -> Result<Option<SenderPubKeyAndProof<G1Affine>>, OTError> {
+        self.base_ot.start(&mut self.rng, other)
+    }
+
+    /// Starts base OT with `other` again when `other`, the OT receiver, lost its output.
+    pub fn restart_base_ot(
+        &mut self,
+        other: ParticipantId,
+    ) -> Result<Option<SenderPubKeyAndProof<G1Affine>>, OTError> {
+        self.base_ot.restart(&mut self.rng, other)
+    }
+
+    pub fn receive_base_ot_sender_pubkey(
+        &mut self,
+        sender: ParticipantId,
+        pk: SenderPubKeyAndProof<G1Affine>,
+    ) -> Result<ReceiverPubKeys<G1Affine>, OTError> {
+        self.base_ot
+            .receive_sender_pubkey::<_, BASE_OT_KEY_SIZE>(&mut self.rng, sender, pk)
+    }
+
+    /// Checks the signers of a session. The AS picks them among the live committee members,
+    /// so they are not always the same.
+    pub fn check_quorum(&self, quorum: &BTreeSet<ParticipantId>) -> Result<(), BBSPlusError> {
+        if !quorum.contains(&self.id) {
+            return Err(BBSPlusError::UnexpectedParticipant(self.id));
+        }
+        if let Some(id) = quorum.iter().find(|id| !self.all_party_set.contains(id)) {
+            return Err(BBSPlusError::UnexpectedParticipant(*id));
+        }
+        if quorum.len() != self.threshold as usize {
+            return Err(BBSPlusError::IncorrectNoOfShares(
+                self.threshold as usize,
+                quorum.len(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// The signers of presignature `session`, this one included.
+    pub fn quorum(&self, session: SessionId) -> BTreeSet<ParticipantId> {
+        self.sessions
+            .get(&session)
+            .map(|state| state.quorum.clone())
+            .unwrap_or_default()
+    }
+
+    /// The signers taking part in the running keygen, refresh or resharing: the committee, and
+    /// when resharing also the dealers of the old one.
+    pub fn key_session_parties(&self) -> BTreeSet<ParticipantId> {
+        match &self.reshare {
+            Some(reshare) => reshare.dealers.union(&reshare.committee).copied().collect(),
+            None => self.all_party_set.clone(),
+        }
+    }
+
+    /// The other signers this signer runs `session` with.
+    pub fn others(&self, session: SessionId) -> BTreeSet<ParticipantId> {
+        self.sessions
+            .get(&session)
+            .map(|state| state.others(self.id))
+            .unwrap_or_default()
     }
 
+    /// The state of `session`, created by whichever comes first, the request from the AS or
+    /// a message from another signer.
+    fn session(&mut self, session: SessionId) -> &mut PresignSession {
+        self.sessions
+            .entry(session)
+            .or_insert_with(PresignSession::new)
+    }
+
+    /// Drops the state of a session that was aborted, either by the AS or because another
+    /// signer misbehaved in it. Finished presignature batches are kept, anything that still
+    /// comes for the session is rejected.
+    pub fn abort(&mut self, session: SessionId) {
+        self.sessions.remove(&session);
+        self.rounds.close(session);
+        self.audit.discard(session);
+        if session != NO_SESSION && session == self.key_session {
+            self.keygen = None;
+            self.refresh = None;
+            self.reshare = None;
+            self.reshare_shares.clear();
+        }
+    }
+
+    /// Drops sessions that did not finish in time, e.g. because a signer went away, and
+    /// finished ones that no message can come for anymore.
+    fn drop_stale_sessions(&mut self) {
+        let timeout = Duration::from_secs(SESSION_TIMEOUT_SECS);
+        self.sessions
+            .retain(|_, state| state.started.elapsed() < timeout);
+    }
+
+    /// Starts Phase 1 of `session` with `quorum` and returns the commitments to send to the
+    /// other signers. Commitments that arrived before this call are processed right away.
     pub fn do_round1(
+        &mut self,
+        session: SessionId,
+        quorum: BTreeSet<ParticipantId>,
+        batch_size: u32,
+    ) -> Result<(Commitments, BTreeMap<ParticipantId, Commitments>), PresignError> {
+        self.check_quorum(&quorum)?;
+        self.drop_stale_sessions();
+
+        let id = self.id;
+        // Phase 1 commitments hash the protocol ID and Phase 2 uses the gadget vector, so
+        // messages of this session are of no use in any other session or deployment
+        let protocol_id = session_domain(PROTOCOL_ID_TAG, &self.deployment_id, session, &quorum);
+        let gadget_label = session_domain(GADGET_VECTOR_TAG, &self.deployment_id, session, &quorum);
+        let ote_params = self.ote_params;
+        let state = self
+            .sessions
+            .entry(session)
+            .or_insert_with(PresignSession::new);
+        // A session is run once, a second request for it is a replay
+        if !matches!(state.state, PresignState::Idle) || !state.quorum.is_empty() {
+            return Err(state.state.wrong("do_round1"));
+        }
+        state.quorum = quorum;
+        state.gadget_vector = Some(GadgetVector::new::<Blake2b512>(ote_params, &gadget_label));
+
+        let (mut round1, comm, comm_zero) = Phase1::<Fr, 256>::init_for_bbs(
+            &mut self.rng,
+            batch_size,
+            id,
+            state.others(id),
+            protocol_id,
+        )?;
+
+        for (sender, (comm, comm_zero_share)) in std::mem::take(&mut state.pending_commitments) {
+            round1.receive_commitment(sender, comm, comm_zero_share)?;
+        }
+
+        state.state = PresignState::Round1Committed(round1);
+        Ok((comm, comm_zero))
+    }
+
+    pub fn receive_round1_commitment(
+        &mut self,
+        session: SessionId,
+        sender: ParticipantId,
+        comm: Commitments,
+        comm_zero_share: Commitments,
+    ) -> Result<(), PresignError> {
+        let state = self.session(session);
+        match &mut state.state {
+            PresignState::Idle => {
+                state
+                    .pending_commitments
+                    .insert(sender, (comm, comm_zero_share));
+                Ok(())
+            }
+            PresignState::Round1Committed(round1) => {
+                Ok(round1.receive_commitment(sender, comm, comm_zero_share)?)
+            }
+            other => Err(other.wrong("receive_round1_commitment")),
+        }
+    }
+
+    /// True once Phase 1 of `session` has started and every other signer's commitment is in,
+    /// so the shares can be revealed.
+    pub fn has_all_round1_commitments(&self, session: SessionId) -> bool {
+        match self.sessions.get(&session) {
+            Some(PresignSession {
+                state: PresignState::Round1Committed(round1),
+                ..
+            }) => self
+                .others(session)
+                .iter()
+                .all(|id| round1.commitment_protocol.has_commitment_from(id)),
+            _ => false,
+        }
+    }
+
+    /// The shares and salts to reveal to `other`, for the joint randomness and for the zero
+    /// sharing run with `other`.
+    pub fn round1_shares_for(
         &self,
-    ) -> (
-        Phase1<Fr, 256>,
-        Commitments,
-        BTreeMap<ParticipantId, Commitments>,
-    ) {
-        let mut rng = StdRng::seed_from_u64(0u64);
-        let mut others = self.threshold_party_set.clone();
-        others.remove(&self.id);
-
-        Phase1::<Fr, 256>::init_for_bbs(
-            &mut rng,
-            SIG_BATCH_SIZE,
-            self.id,
-            others.clone(),
-            self.protocol_id.clone(),
-        )
-        .unwrap()
+        session: SessionId,
+        other: &ParticipantId,
+    ) -> Option<(SharesAndSalts, SharesAndSalts)> {
+        let PresignState::Round1Committed(round1) = &self.sessions.get(&session)?.state else {
+            return None;
+        };
+        Some((
+            round1.get_comm_shares_and_salts(),
+            round1.get_comm_shares_and_salts_for_zero_sharing_protocol_with_other(other),
+        ))
     }
 
-    pub fn finish_round1(&self, round1: Phase1<Fr, 256>) -> Phase1Output<Fr> {
-        let sk_share = &self.sk_share.unwrap();
-        let result = round1.finish_for_bbs::<Blake2b512>(sk_share).unwrap();
-        result
+    /// Processes the shares revealed by `sender`. Returns true once shares from all other
+    /// signers are in and Phase 1 can be finished.
+    pub fn receive_round1_shares(
+        &mut self,
+        session: SessionId,
+        sender: ParticipantId,
+        shares: SharesAndSalts,
+        zero_shares: SharesAndSalts,
+    ) -> Result<bool, PresignError> {
+        let ready = self.has_all_round1_commitments(session);
+        let state = self
+            .sessions
+            .get_mut(&session)
+            .ok_or(PresignError::UnknownSession(session))?;
+        let PresignState::Round1Committed(round1) = &mut state.state else {
+            return Err(state.state.wrong("receive_round1_shares"));
+        };
+        round1.receive_shares(sender, shares, zero_shares)?;
+        Ok(ready && round1.ready_to_compute_randomness_and_arguments_for_multiplication())
     }
 
+    /// Round1Committed to Round1Done, once the shares of every other signer are in.
+    pub fn finish_round1(&mut self, session: SessionId) -> Result<(), PresignError> {
+        let sk_share = self.sk_share.as_ref().ok_or(PresignError::NoKeyShare)?;
+        let state = self
+            .sessions
+            .get_mut(&session)
+            .ok_or(PresignError::UnknownSession(session))?;
+        match std::mem::replace(&mut state.state, PresignState::Idle) {
+            PresignState::Round1Committed(round1) => {
+                let output = round1.finish_for_bbs::<Blake2b512>(sk_share)?;
+                state.state = PresignState::Round1Done(output);
+                Ok(())
+            }
+            other => {
+                let e = other.wrong("finish_round1");
+                state.state = other;
+                Err(e)
+            }
+        }
+    }
+
+    /// Starts Phase 2 of `session` once base OT with every other signer is done, or leaves it
+    /// in Round1Done until then. Phase 2 does not depend on the messages, so it runs right
+    /// after Phase 1.
     pub fn do_round2(
-        &self,
-        masked_signing_key_share: Vec<Fr>,
-        masked_r: Vec<Fr>,
-    ) -> (Phase2<Fr, 256, 80>, BTreeMap<ParticipantId, Message1<Fr>>) {
-        let mut rng = StdRng::seed_from_u64(0u64);
-        let mut others = self.threshold_party_set.clone();
-        others.remove(&self.id);
+        &mut self,
+        session: SessionId,
+    ) -> Result<Option<Round2Messages>, PresignError> {
+        let state = self
+            .sessions
+            .get(&session)
+            .ok_or(PresignError::UnknownSession(session))?;
+        if !matches!(state.state, PresignState::Round1Done(_)) {
+            return Err(state.state.wrong("do_round2"));
+        }
+        self.resume_round2(session)
+    }
 
-        let ote_params = MultiplicationOTEParams::<KAPPA, STATISTICAL_SECURITY_PARAMETER> {};
-        let gadget_vector = GadgetVector::<Fr, KAPPA, STATISTICAL_SECURITY_PARAMETER>::new::<
-            Blake2b512,
-        >(ote_params, b"test-gadget-vector");
-
-        let base_ot_outputs = do_pairwise_base_ot::<BASE_OT_KEY_SIZE>(
-            &mut rng,
-            ote_params.num_base_ot(),
-            TOTAL_SIGNERS,
-            self.all_party_set.clone(),
+    /// Starts Phase 2 of every session that was waiting for base OT that is now done.
+    pub fn resume_waiting_round2s(
+        &mut self,
+    ) -> Result<Vec<(SessionId, Round2Messages)>, PresignError> {
+        let waiting = self
+            .sessions
+            .iter()
+            .filter(|(_, state)| matches!(state.state, PresignState::Round1Done(_)))
+            .map(|(session, _)| *session)
+            .collect::<Vec<_>>();
+
+        let mut started = vec![];
+        for session in waiting {
+            if let Some(messages) = self.resume_round2(session)? {
+                started.push((session, messages));
+            }
+        }
+        Ok(started)
+    }
+
+    /// Round1Done to Round2Started, if base OT with every other signer is done. Returns the
+    /// `Message1`s to send to the other signers, along with the `Message2` replies to any
+    /// `Message1` that arrived before Phase 2 started here.
+    fn resume_round2(
+        &mut self,
+        session: SessionId,
+    ) -> Result<Option<Round2Messages>, PresignError> {
+        let id = self.id;
+        let others = self.others(session);
+        if !others
+            .iter()
+            .all(|other| self.base_ot.has_output_with(other))
+        {
+            return Ok(None);
+        }
+        let base_ot_output = self.base_ot.output.clone();
+        let state = self
+            .sessions
+            .get_mut(&session)
+            .ok_or(PresignError::UnknownSession(session))?;
+        let Some(gadget_vector) = state.gadget_vector.as_ref() else {
+            return Err(state.state.wrong("do_round2"));
+        };
+        let round1_output = match std::mem::replace(&mut state.state, PresignState::Idle) {
+            PresignState::Round1Done(round1_output) => round1_output,
+            other => {
+                let e = other.wrong("do_round2");
+                state.state = other;
+                return Err(e);
+            }
+        };
+
+        let (mut round2, msg_1s) = Phase2::init(
+            &mut self.rng,
+            id,
+            round1_output.masked_signing_key_shares.clone(),
+            round1_output.masked_rs.clone(),
+            base_ot_output,
+            others,
+            self.ote_params,
+            gadget_vector,
+        )?;
+
+        let mut msg_2s = BTreeMap::new();
+        for (sender, m1) in std::mem::take(&mut state.pending_message1s) {
+            let m2 = round2.receive_message1::<Blake2b512>(sender, m1, gadget_vector)?;
+            msg_2s.insert(sender, m2);
+        }
+
+        state.state = PresignState::Round2Started(round1_output, round2);
+        Ok(Some((msg_1s, msg_2s)))
+    }
+
+    /// Processes a `Message1` from `sender` and returns the `Message2` to send back, or `None`
+    /// if Phase 2 has not started here yet and the message was kept for later.
+    pub fn receive_round2_message1(
+        &mut self,
+        session: SessionId,
+        sender: ParticipantId,
+        message1: Message1<Fr>,
+    ) -> Result<Option<Message2<Fr>>, PresignError> {
+        let state = self.session(session);
+        match (&mut state.state, state.gadget_vector.as_ref()) {
+            (PresignState::Round2Started(_, round2), Some(gadget_vector)) => Ok(Some(
+                round2.receive_message1::<Blake2b512>(sender, message1, gadget_vector)?,
+            )),
+            (
+                PresignState::Idle | PresignState::Round1Committed(_) | PresignState::Round1Done(_),
+                _,
+            ) => {
+                state.pending_message1s.insert(sender, message1);
+                Ok(None)
+            }
+            (other, _) => Err(other.wrong("receive_round2_message1")),
+        }
+    }
+
+    pub fn receive_round2_message2(
+        &mut self,
+        session: SessionId,
+        sender: ParticipantId,
+        message2: Message2<Fr>,
+    ) -> Result<(), PresignError> {
+        let state = self
+            .sessions
+            .get_mut(&session)
+            .ok_or(PresignError::UnknownSession(session))?;
+        match (&mut state.state, state.gadget_vector.as_ref()) {
+            (PresignState::Round2Started(_, round2), Some(gadget_vector)) => {
+                Ok(round2.receive_message2::<Blake2b512>(sender, message2, gadget_vector)?)
+            }
+            (other, _) => Err(other.wrong("receive_round2_message2")),
+        }
+    }
+
+    /// True once the multiplication with every other signer of `session` is done, either as
+    /// OT sender or as OT receiver.
+    pub fn has_all_round2_messages(&self, session: SessionId) -> bool {
+        match self.sessions.get(&session) {
+            Some(PresignSession {
+                state: PresignState::Round2Started(_, round2),
+                ..
+            }) => self
+                .others(session)
+                .iter()
+                .all(|id| round2.0.z_A.contains_key(id) || round2.0.z_B.contains_key(id)),
+            _ => false,
+        }
+    }
+
+    /// Round2Started to ShareIssued: keeps the outputs of both phases as the presignature
+    /// batch of `session`, and returns the commitments to this signer's `r` and `u` of every
+    /// presignature in it. They let the AS check the batch before it is used, and each
+    /// signature share on arrival.
+    pub fn finish_round2(&mut self, session: SessionId) -> Result<BatchCommitments, PresignError> {
+        let state = self
+            .sessions
+            .get_mut(&session)
+            .ok_or(PresignError::UnknownSession(session))?;
+        let (round1_output, round2) =
+            match std::mem::replace(&mut state.state, PresignState::ShareIssued) {
+                PresignState::Round2Started(round1_output, round2) => (round1_output, round2),
+                other => {
+                    let e = other.wrong("finish_round2");
+                    state.state = other;
+                    return Err(e);
+                }
+            };
+
+        let batch = PresignatureBatch::new(round1_output, round2.finish());
+        let commitments = batch.commitments(&self.params);
+        self.presignatures.insert(session, batch);
+        Ok(commitments)
+    }
+
+    /// Creates this signer's share of the signature on `messages` from a presignature. Only the
+    /// share leaves the signer.
+    pub fn sign(
+        &mut self,
+        batch_id: u64,
+        index: u32,
+        messages: &[Fr],
+    ) -> Result<BBSSignatureShare<Bls12_381>, PresignatureError> {
+        self.presignatures
+            .sign(batch_id, index, messages, &self.params)
+    }
+}
+
+impl Drop for Signer {
+    fn drop(&mut self) {
+        self.delete_sk_share();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ark_std::rand::SeedableRng;
+
+    const SESSION: SessionId = 7;
+
+    fn config(node_id: u16) -> Config {
+        Config {
+            node_id,
+            total_nodes: 4,
+            message_count: 2,
+            threshold_signers: 3,
+            signers: 3,
+            current_run: 0,
+            trusted_dealer: false,
+            refresh_interval: 0,
+            deployment_id: "test".into(),
+            rng_seed: None,
+            keystore_path: String::new(),
+            keystore_passphrase: None,
+            audit_log_path: String::new(),
+            output_dir: String::new(),
+            node_keys_dir: String::new(),
+            token_lifetime: 60,
+            policy_max_lifetime: 60,
+            policy_audiences: vec![],
+            policy_require_credential: false,
+            policy_quota: 0,
+            policy_quota_window: 0,
+        }
+    }
+
+    /// Signers 1 to 3 with a key share each, which Phase 1 needs.
+    fn signers() -> BTreeMap<ParticipantId, Signer> {
+        (1..=3)
+            .map(|id| {
+                let mut signer = Signer::new(config(id));
+                signer.sk_share = Some(Fr::from(id as u64));
+                (id, signer)
+            })
+            .collect()
+    }
+
+    fn state(signer: &Signer) -> &'static str {
+        signer.sessions[&SESSION].state.name()
+    }
+
+    fn assert_wrong_state<T: std::fmt::Debug>(
+        result: Result<T, PresignError>,
+        expected_step: &str,
+        expected_state: &str,
+    ) {
+        match result {
+            Err(PresignError::WrongState { step, state }) => {
+                assert_eq!((step, state), (expected_step, expected_state))
+            }
+            other => panic!("expected a wrong state, got {:?}", other),
+        }
+    }
+
+    type Round1Commitments = (Commitments, BTreeMap<ParticipantId, Commitments>);
+
+    fn deliver_commitment(
+        signers: &mut BTreeMap<ParticipantId, Signer>,
+        commitments: &BTreeMap<ParticipantId, Round1Commitments>,
+        sender: ParticipantId,
+        receiver: ParticipantId,
+    ) {
+        let (comm, comm_zero) = &commitments[&sender];
+        signers
+            .get_mut(&receiver)
+            .unwrap()
+            .receive_round1_commitment(SESSION, sender, comm.clone(), comm_zero[&receiver].clone())
+            .unwrap();
+    }
+
+    /// Runs Phase 1 of `SESSION` on all signers. Signer 3 gets the commitments of the others
+    /// before its own request, which it keeps until then.
+    fn run_round1(signers: &mut BTreeMap<ParticipantId, Signer>) {
+        let quorum = BTreeSet::from([1, 2, 3]);
+        let mut commitments = BTreeMap::new();
+        for id in [1, 2] {
+            let signer = signers.get_mut(&id).unwrap();
+            commitments.insert(id, signer.do_round1(SESSION, quorum.clone(), 1).unwrap());
+        }
+        for sender in [1, 2] {
+            deliver_commitment(signers, &commitments, sender, 3);
+            assert_eq!(state(&signers[&3]), "Idle");
+        }
+        let signer = signers.get_mut(&3).unwrap();
+        commitments.insert(3, signer.do_round1(SESSION, quorum, 1).unwrap());
+        assert!(signers[&3].has_all_round1_commitments(SESSION));
+        for (sender, receiver) in [(1, 2), (2, 1), (3, 1), (3, 2)] {
+            deliver_commitment(signers, &commitments, sender, receiver);
+        }
+
+        for sender in 1..=3 {
+            for receiver in 1..=3 {
+                if sender == receiver {
+                    continue;
+                }
+                let (shares, zero_shares) = signers[&sender]
+                    .round1_shares_for(SESSION, &receiver)
+                    .unwrap();
+                signers
+                    .get_mut(&receiver)
+                    .unwrap()
+                    .receive_round1_shares(SESSION, sender, shares, zero_shares)
+                    .unwrap();
+            }
+        }
+        for signer in signers.values_mut() {
+            signer.finish_round1(SESSION).unwrap();
+            assert_eq!(state(signer), "Round1Done");
+        }
+    }
+
+    #[test]
+    fn runs_phase1_in_order() {
+        let mut signers = signers();
+        run_round1(&mut signers);
+        // Phase 2 waits for base OT, which never ran here
+        let signer = signers.get_mut(&1).unwrap();
+        assert!(signer.do_round2(SESSION).unwrap().is_none());
+        assert_eq!(state(signer), "Round1Done");
+    }
+
+    #[test]
+    fn rejects_steps_of_unknown_sessions() {
+        let mut signer = Signer::new(config(1));
+        signer.sk_share = Some(Fr::from(1u64));
+        assert!(matches!(
+            signer.finish_round1(SESSION),
+            Err(PresignError::UnknownSession(SESSION))
+        ));
+        assert!(matches!(
+            signer.do_round2(SESSION),
+            Err(PresignError::UnknownSession(SESSION))
+        ));
+        assert!(matches!(
+            signer.finish_round2(SESSION),
+            Err(PresignError::UnknownSession(SESSION))
+        ));
+    }
+
+    #[test]
+    fn rejects_steps_ahead_of_the_state() {
+        let mut signers = signers();
+        let signer = signers.get_mut(&1).unwrap();
+        signer
+            .do_round1(SESSION, BTreeSet::from([1, 2, 3]), 1)
+            .unwrap();
+
+        assert_wrong_state(signer.do_round2(SESSION), "do_round2", "Round1Committed");
+        assert_wrong_state(
+            signer.finish_round2(SESSION),
+            "finish_round2",
+            "Round1Committed",
         );
+        // A failed step leaves the session where it was
+        assert_eq!(state(signer), "Round1Committed");
+    }
 
-        Phase2::init(
-            &mut rng,
-            self.id,
-            masked_signing_key_share,
-            masked_r,
-            base_ot_outputs[self.id as usize - 1].clone(),
-            others.clone(),
-            ote_params,
-            &gadget_vector,
+    /// Runs Phase 2 of `SESSION` on all signers after `run_round1`, with base OT done up front.
+    fn run_round2(signers: &mut BTreeMap<ParticipantId, Signer>) -> Vec<BatchCommitments> {
+        let outputs = crate::ot::do_pairwise_base_ot::<BASE_OT_KEY_SIZE>(
+            &mut StdRng::seed_from_u64(0),
+            signers[&1].ote_params.num_base_ot(),
+            3,
+            BTreeSet::from([1, 2, 3]),
+        );
+        for (signer, output) in signers.values_mut().zip(outputs) {
+            signer.base_ot.output = output;
+        }
+
+        let mut message1s = BTreeMap::new();
+        for (id, signer) in signers.iter_mut() {
+            let (msg_1s, _) = signer.do_round2(SESSION).unwrap().unwrap();
+            message1s.insert(*id, msg_1s);
+        }
+        for (sender, msg_1s) in message1s {
+            for (receiver, message1) in msg_1s {
+                let message2 = signers
+                    .get_mut(&receiver)
+                    .unwrap()
+                    .receive_round2_message1(SESSION, sender, message1)
+                    .unwrap()
+                    .unwrap();
+                signers
+                    .get_mut(&sender)
+                    .unwrap()
+                    .receive_round2_message2(SESSION, receiver, message2)
+                    .unwrap();
+            }
+        }
+        signers
+            .values_mut()
+            .map(|signer| {
+                assert!(signer.has_all_round2_messages(SESSION));
+                signer.finish_round2(SESSION).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn commits_to_the_presignatures_it_signs_with() {
+        use ark_bls12_381::G1Projective;
+        use ark_ec::pairing::Pairing;
+
+        let mut signers = signers();
+        // Key shares on 5 + x, so the key is 5
+        for (id, signer) in signers.iter_mut() {
+            signer.sk_share = Some(Fr::from(5 + *id as u64));
+        }
+        run_round1(&mut signers);
+        let commitments = run_round2(&mut signers);
+        let params = signers[&1].params.clone();
+        let e = commitments[0].e[0];
+        assert!(commitments.iter().all(|c| c.e == commitments[0].e));
+
+        // The `u` add up to `r * (e + sk)`
+        let (r, u) = commitments
+            .iter()
+            .fold((G1Projective::zero(), G1Projective::zero()), |(r, u), c| {
+                (r + c.r_g1[0], u + c.u[0])
+            });
+        let key = params.g2 * (e + Fr::from(5u64));
+        assert_eq!(Bls12_381::pairing(u, params.g2), Bls12_381::pairing(r, key));
+
+        // Each signature share carries the committed `u` and `R = B * r`
+        let messages = [Fr::from(1u64), Fr::from(2u64)];
+        let base = params.b(messages.iter().enumerate()).unwrap();
+        for (signer, commitments) in signers.values_mut().zip(&commitments) {
+            let share = signer.sign(SESSION, 0, &messages).unwrap();
+            assert_eq!((params.g1 * share.u).into_affine(), commitments.u[0]);
+            assert_eq!(
+                Bls12_381::pairing(share.R, params.g2),
+                Bls12_381::pairing(base, commitments.r[0])
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_steps_behind_the_state() {
+        let mut signers = signers();
+        run_round1(&mut signers);
+        let signer = signers.get_mut(&1).unwrap();
+
+        assert_wrong_state(
+            signer.do_round1(SESSION, BTreeSet::from([1, 2, 3]), 1),
+            "do_round1",
+            "Round1Done",
+        );
+        assert_wrong_state(signer.finish_round1(SESSION), "finish_round1", "Round1Done");
+        assert_eq!(state(signer), "Round1Done");
+    }
+
+    /// Signer `id` in a resharing of epoch 1 from dealers 1 and 2 to committee 1 to 3.
+    fn resharing(id: ParticipantId) -> Signer {
+        let mut signer = Signer::new(config(id));
+        let reshare = Reshare {
+            epoch: 1,
+            dealers: BTreeSet::from([1, 2]),
+            committee: BTreeSet::from([1, 2, 3]),
+            threshold: 2,
+            public_key: PublicKeyG2(G2Affine::zero()),
+        };
+        signer.start_reshare(SESSION, reshare).unwrap();
+        signer
+    }
+
+    /// A share of a random secret for `receiver`, with the commitments to check it.
+    fn dealt_share(
+        signer: &mut Signer,
+        receiver: ParticipantId,
+    ) -> (Share<Fr>, CommitmentToCoefficients<G2Affine>) {
+        let (shares, comms, _) = feldman_vss::deal_secret::<_, G2Affine>(
+            &mut signer.rng,
+            Fr::from(5u64),
+            2,
+            4,
+            &signer.params.g2,
         )
-        .unwrap()
+        .unwrap();
+        (shares.0[receiver as usize - 1].clone(), comms)
+    }
+
+    #[test]
+    fn takes_reshare_shares_from_dealers() {
+        let mut signer = resharing(3);
+        for dealer in [1, 2] {
+            let (share, comms) = dealt_share(&mut signer, 3);
+            signer
+                .receive_reshare_share(1, dealer, share, comms)
+                .unwrap();
+        }
+        assert!(signer.has_all_reshare_shares());
+    }
+
+    #[test]
+    fn rejects_reshare_shares_from_outside_the_committee() {
+        let mut signer = resharing(3);
+        let (share, comms) = dealt_share(&mut signer, 3);
+        assert!(matches!(
+            signer.receive_reshare_share(1, 4, share, comms),
+            Err(SSError::InvalidParticipantId(4))
+        ));
+
+        // Signer 4 is not in the new committee, so no one deals to it
+        let mut signer = resharing(4);
+        let (share, comms) = dealt_share(&mut signer, 4);
+        assert!(matches!(
+            signer.receive_reshare_share(1, 1, share, comms),
+            Err(SSError::InvalidParticipantId(4))
+        ));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn drops_early_reshare_shares_of_non_dealers() {
+        let mut signer = Signer::new(config(3));
+        for sender in [1, 4] {
+            let (share, comms) = dealt_share(&mut signer, 3);
+            signer
+                .receive_reshare_share(1, sender, share, comms)
+                .unwrap();
+        }
+        let reshare = Reshare {
+            epoch: 1,
+            dealers: BTreeSet::from([1, 2]),
+            committee: BTreeSet::from([1, 2, 3]),
+            threshold: 2,
+            public_key: PublicKeyG2(G2Affine::zero()),
+        };
+        signer.start_reshare(SESSION, reshare).unwrap();
+        assert_eq!(
+            signer.reshare_shares.keys().copied().collect::<Vec<_>>(),
+            vec![1]
+        );
+    }
+
+    #[test]
+    fn rejects_phase1_without_key_share() {
+        let mut signers = signers();
+        let signer = signers.get_mut(&1).unwrap();
+        signer
+            .do_round1(SESSION, BTreeSet::from([1, 2, 3]), 1)
+            .unwrap();
+        signer.sk_share = None;
+        assert!(matches!(
+            signer.finish_round1(SESSION),
+            Err(PresignError::NoKeyShare)
+        ));
+        assert_eq!(state(signer), "Round1Committed");
+    }
+}
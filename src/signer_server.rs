@@ -1,6 +1,6 @@
+use std::collections::{BTreeSet, HashMap};
 use std::sync::Arc;
 
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::net::TcpListener;
 use tokio::sync::Mutex;
 
@@ -9,23 +9,97 @@ mod helper {
     pub mod message;
 }
 // mod auth_service;
+mod attributes;
+mod audit;
 mod auth_service;
 mod config;
 mod constant;
+mod envelope;
 mod exp_utils;
+mod keystore;
 mod ot;
+mod policy;
+mod presignature;
+mod rng;
+mod rounds;
+mod schema;
 mod signer;
+mod token;
+mod transport;
 
 use config::Config;
+use constant::{
+    BASE_OT_KEY_SIZE, HEARTBEAT_INTERVAL_SECS, PEER_RECONNECT_ATTEMPTS, PEER_RECONNECT_BACKOFF_MS,
+};
+use envelope::{Envelope, EnvelopeWriter, SigningKeys};
 use helper::encoder::Encoder;
-use helper::message::{Message, Payload};
-use signer::Signer;
+use helper::message::{Message, ParticipantId, Payload, SessionId, NO_SESSION};
+use signer::{PresignError, Reshare, Round2Messages, Signer};
+use token::TokenAttributes;
+use transport::{NodeKeys, Peer};
+
+/// Connections to the other signers, opened again when one breaks, e.g. because the other
+/// signer restarted.
+struct PeerLinks {
+    keys: Arc<NodeKeys>,
+    signing_keys: Arc<SigningKeys>,
+    streams: Mutex<HashMap<u16, Arc<Mutex<Option<EnvelopeWriter>>>>>,
+}
+
+impl PeerLinks {
+    /// The connection to `node_id`, which only one task at a time sends on or opens.
+    async fn link(&self, node_id: u16) -> Arc<Mutex<Option<EnvelopeWriter>>> {
+        let mut streams = self.streams.lock().await;
+        Arc::clone(streams.entry(node_id).or_default())
+    }
+
+    /// Drops the connection to `node_id`, so that the next message opens a new one.
+    async fn close(&self, node_id: u16) {
+        let link = self.link(node_id).await;
+        *link.lock().await = None;
+    }
+}
+
+type Peers = Arc<PeerLinks>;
+
+/// Why a payload could not be handled. `Misbehaved` is the sender's fault: its message did not
+/// decode or failed a protocol check, and the AS is told so it can retry without the sender.
+/// `Rejected` messages are duplicated, out of order or stale, and dropped before any handling.
+enum PayloadError {
+    Misbehaved(String),
+    Rejected(String),
+    Local(String),
+}
+
+impl From<String> for PayloadError {
+    fn from(e: String) -> Self {
+        Self::Local(e)
+    }
+}
+
+fn misbehaved<E: std::fmt::Debug>(e: E) -> PayloadError {
+    PayloadError::Misbehaved(format!("{:?}", e))
+}
+
+fn undecodable<E: std::fmt::Display>(e: E) -> PayloadError {
+    PayloadError::Misbehaved(format!("Decode error: {}", e))
+}
+
+/// Only a failed protocol check is the sender's fault. A message that does not fit the state
+/// of its session here, e.g. for a session that was aborted, is late rather than wrong.
+fn presign_failed(e: PresignError) -> PayloadError {
+    match e {
+        PresignError::Protocol(e) => misbehaved(e),
+        e => PayloadError::Local(format!("{:?}", e)),
+    }
+}
 
 async fn handle_listener(
     listener: TcpListener,
     config: Arc<Config>,
     signer: Arc<Mutex<Signer>>,
-    main_stream: Arc<Mutex<tokio::net::TcpStream>>,
+    main_stream: Arc<Mutex<EnvelopeWriter>>,
+    peers: Peers,
 ) -> tokio::io::Result<()> {
     loop {
         let (socket, addr) = listener.accept().await?;
@@ -33,11 +107,25 @@ async fn handle_listener(
 
         let signer = Arc::clone(&signer);
         let main_stream = Arc::clone(&main_stream);
+        let peers = Arc::clone(&peers);
 
         let config = Arc::clone(&config);
+        let keys = Arc::clone(&peers.keys);
+        let signing_keys = Arc::clone(&peers.signing_keys);
 
         tokio::task::spawn(async move {
-            let mut reader = BufReader::new(socket);
+            // Only the AS and the other signers ever connect to a signer
+            let (peer, mut reader) = match keys.accept(socket).await {
+                Ok((Peer::Node(peer), reader, _)) => (peer, reader),
+                Ok((Peer::Client, _, _)) => {
+                    eprintln!("Refused connection from {}, not a known node", addr);
+                    return;
+                }
+                Err(e) => {
+                    eprintln!("Handshake with {} failed: {}", addr, e);
+                    return;
+                }
+            };
             let mut line = String::new();
 
             // println!("Buffer: {:?}", buffer);
@@ -47,6 +135,8 @@ async fn handle_listener(
                 match reader.read_line(&mut line).await {
                     Ok(0) => {
                         println!("Connection closed by {}", addr);
+                        // The other direction most likely went down with it
+                        peers.close(peer).await;
                         break;
                     }
                     Ok(_) => {
@@ -54,20 +144,71 @@ async fn handle_listener(
                         if text.is_empty() {
                             continue;
                         }
-                        let payload: Payload = match serde_json::from_str(text) {
+                        let envelope: Envelope = match serde_json::from_str(text) {
+                            Ok(envelope) => envelope,
+                            Err(e) => {
+                                eprintln!("Failed to deserialize envelope from {}: {}", addr, e);
+                                continue;
+                            }
+                        };
+                        let payload = match signing_keys.open(envelope) {
                             Ok(payload) => payload,
                             Err(e) => {
-                                eprintln!("Failed to deserialize payload from {}: {}", addr, e);
+                                eprintln!("Rejected envelope from node {}: {}", peer, e);
                                 continue;
                             }
                         };
+                        if payload.sender != peer {
+                            eprintln!(
+                                "Dropped message claiming sender {} from node {}",
+                                payload.sender, peer
+                            );
+                            continue;
+                        }
 
-                        if let Err(e) =
-                            handle_payload(payload, &config, &signer, &main_stream).await
+                        let (session, sender) = (payload.session, payload.sender);
+                        let round = payload.msg.round();
+                        match handle_payload(payload, text, &config, &signer, &main_stream, &peers)
+                            .await
                         {
-                            eprintln!("Failed to handle payload from {}: {}", addr, e);
-                            continue;
-                        };
+                            Ok(()) => {}
+                            Err(PayloadError::Rejected(reason)) => {
+                                eprintln!(
+                                    "Rejected {} from {} in session {}: {}",
+                                    round, sender, session, reason
+                                );
+                            }
+                            Err(PayloadError::Local(e)) => {
+                                eprintln!("Failed to handle payload from {}: {}", addr, e);
+                                // Rather than leave the AS waiting for the session to time out
+                                if session != NO_SESSION {
+                                    if let Err(e) =
+                                        send_error(&config, &signer, &main_stream, session, e).await
+                                    {
+                                        eprintln!("Failed to report error to the AS: {}", e);
+                                    }
+                                }
+                            }
+                            Err(PayloadError::Misbehaved(reason)) => {
+                                eprintln!(
+                                    "Signer {} misbehaved in session {}: {}",
+                                    sender, session, reason
+                                );
+                                if let Err(e) = send_blame(
+                                    &config,
+                                    &signer,
+                                    &main_stream,
+                                    session,
+                                    sender,
+                                    reason,
+                                    text.to_string(),
+                                )
+                                .await
+                                {
+                                    eprintln!("Failed to report signer {}: {}", sender, e);
+                                }
+                            }
+                        }
 
                         // if let Err(e) = socket.write_all(&buffer[..n]).await {
                         //     eprintln!("Failed to send response to {}: {}", addr, e);
@@ -76,6 +217,7 @@ async fn handle_listener(
                     }
                     Err(e) => {
                         eprintln!("Error reading from {}: {}", addr, e);
+                        peers.close(peer).await;
                         break;
                     }
                 }
@@ -84,111 +226,1050 @@ async fn handle_listener(
     }
 }
 
-async fn send_message(
-    stream: &mut tokio::net::TcpStream,
-    payload: &Payload,
-) -> tokio::io::Result<()> {
-    let mut serialized = serde_json::to_vec(payload)?;
-    serialized.push(b'\n');
-    stream.write_all(&serialized).await?;
+async fn send_message(stream: &mut EnvelopeWriter, payload: &Payload) -> tokio::io::Result<()> {
+    stream.send(payload).await
+}
+
+/// Sends `payload` to signer `node_id`, connecting to it again first if the connection broke.
+async fn send_to_peer(peers: &Peers, node_id: u16, payload: &Payload) -> Result<(), String> {
+    // Only the link is locked while writing, not the peers map
+    let link = peers.link(node_id).await;
+    let mut link = link.lock().await;
+    if let Some(stream) = link.as_mut() {
+        match send_message(stream, payload).await {
+            Ok(()) => return Ok(()),
+            Err(e) => eprintln!("Lost connection to peer {}: {}", node_id, e),
+        }
+    }
+    let stream = link.insert(connect_to_peer(peers, node_id).await?);
+    send_message(stream, payload)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Reveals this signer's Phase 1 shares of `session` to the other signers of the session.
+async fn send_round1_shares(
+    config: &Arc<Config>,
+    signer: &Arc<Mutex<Signer>>,
+    peers: &Peers,
+    session: SessionId,
+) -> Result<(), String> {
+    let mut payloads = vec![];
+    {
+        let s = signer.lock().await;
+        for other in s.others(session) {
+            let (shares, zero_shares) = s
+                .round1_shares_for(session, &other)
+                .ok_or_else(|| "Round 1 not started".to_string())?;
+            payloads.push((
+                other,
+                Payload {
+                    sender: config.node_id,
+                    session,
+                    msg: Message::Round1Shares {
+                        shares: Encoder::encode_shares_and_salts(&shares)
+                            .map_err(|e| e.to_string())?,
+                        zero_shares: Encoder::encode_shares_and_salts(&zero_shares)
+                            .map_err(|e| e.to_string())?,
+                    },
+                },
+            ));
+        }
+    }
+
+    for (other, payload) in payloads {
+        send_to_peer(peers, other, &payload).await?;
+    }
+    Ok(())
+}
+
+/// Starts base OT with each signer in `others` it has not run with yet, or asks the ones that
+/// are the OT sender of the pair to start it.
+async fn start_base_ot(
+    config: &Arc<Config>,
+    signer: &Arc<Mutex<Signer>>,
+    peers: &Peers,
+    others: BTreeSet<ParticipantId>,
+) -> Result<(), String> {
+    let mut payloads = vec![];
+    {
+        let mut s = signer.lock().await;
+        for other in others {
+            if s.base_ot.needs_restart_from(&other) {
+                payloads.push((
+                    other,
+                    Payload {
+                        sender: config.node_id,
+                        session: NO_SESSION,
+                        msg: Message::BaseOTRestart,
+                    },
+                ));
+            } else if let Some(pubkey) = s.start_base_ot(other).map_err(|e| format!("{:?}", e))? {
+                payloads.push((
+                    other,
+                    Payload {
+                        sender: config.node_id,
+                        session: NO_SESSION,
+                        msg: Message::BaseOTSenderPubKey {
+                            pubkey: Encoder::encode_base_ot_sender_pubkey(&pubkey)
+                                .map_err(|e| e.to_string())?,
+                        },
+                    },
+                ));
+            }
+        }
+    }
+
+    for (other, payload) in payloads {
+        send_to_peer(peers, other, &payload).await?;
+    }
     Ok(())
 }
 
+/// Sends the Phase 2 messages of a freshly started multiplication. A signer that is only ever
+/// the multiplication receiver may already hold everything needed to finish Phase 2.
+async fn send_round2_messages(
+    config: &Arc<Config>,
+    signer: &Arc<Mutex<Signer>>,
+    main_stream: &Arc<Mutex<EnvelopeWriter>>,
+    peers: &Peers,
+    session: SessionId,
+    (msg_1s, msg_2s): Round2Messages,
+) -> Result<(), String> {
+    for (other, m1) in msg_1s {
+        let payload = Payload {
+            sender: config.node_id,
+            session,
+            msg: Message::Round2Message1 {
+                message1: Encoder::encode_msg1(&m1).map_err(|e| e.to_string())?,
+            },
+        };
+        send_to_peer(peers, other, &payload).await?;
+    }
+    for (other, m2) in msg_2s {
+        let payload = Payload {
+            sender: config.node_id,
+            session,
+            msg: Message::Round2Message2 {
+                message2: Encoder::encode_msg2(&m2).map_err(|e| e.to_string())?,
+            },
+        };
+        send_to_peer(peers, other, &payload).await?;
+    }
+
+    let ready = signer.lock().await.has_all_round2_messages(session);
+    if ready {
+        send_round2_final_response(config, signer, main_stream, session).await?;
+    }
+    Ok(())
+}
+
+/// Finishes DKG and reports the derived public key to the AS.
+async fn send_keygen_response(
+    config: &Arc<Config>,
+    signer: &Arc<Mutex<Signer>>,
+    main_stream: &Arc<Mutex<EnvelopeWriter>>,
+    session: SessionId,
+) -> Result<(), String> {
+    let (public_key, public_key_share) = {
+        let mut s = signer.lock().await;
+        let parties = s.key_session_parties();
+        let public_key = s.finish_keygen().map_err(|e| format!("{:?}", e))?;
+        s.rounds.close(session);
+        store_key_share(&mut s);
+        s.audit
+            .append(
+                session,
+                &parties,
+                None,
+                "Key generated".into(),
+                token::now(),
+            )
+            .map_err(|e| e.to_string())?;
+        (public_key, s.public_key_share().ok_or("No key share")?)
+    };
+
+    let payload = Payload {
+        sender: config.node_id,
+        session,
+        msg: Message::KeygenResponse {
+            public_key: Encoder::encode_public_key(&public_key).map_err(|e| e.to_string())?,
+            public_key_share: Encoder::encode_public_key(&public_key_share)
+                .map_err(|e| e.to_string())?,
+        },
+    };
+
+    let mut stream_guard = main_stream.lock().await;
+
+    send_message(&mut stream_guard, &payload)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Stores the signer's new key share, or deletes the stored one if the signer has none left.
+/// The share is still in memory if this fails, only a restart would lose it.
+fn store_key_share(signer: &mut Signer) {
+    if let Err(e) = signer.store_key_share() {
+        eprintln!("Failed to update the keystore: {}", e);
+    }
+}
+
+/// Finishes the share refresh and reports the new epoch to the AS.
+async fn send_refresh_response(
+    config: &Arc<Config>,
+    signer: &Arc<Mutex<Signer>>,
+    main_stream: &Arc<Mutex<EnvelopeWriter>>,
+    session: SessionId,
+) -> Result<(), String> {
+    let (epoch, public_key_share) = {
+        let mut s = signer.lock().await;
+        let parties = s.key_session_parties();
+        let epoch = s.finish_refresh().map_err(|e| format!("{:?}", e))?;
+        s.rounds.close(session);
+        store_key_share(&mut s);
+        let outcome = format!("Refreshed into epoch {}", epoch);
+        s.audit
+            .append(session, &parties, None, outcome, token::now())
+            .map_err(|e| e.to_string())?;
+        (epoch, s.public_key_share().ok_or("No key share")?)
+    };
+    println!("Key share refreshed, epoch {}", epoch);
+
+    let payload = Payload {
+        sender: config.node_id,
+        session,
+        msg: Message::RefreshResponse {
+            epoch,
+            public_key_share: Encoder::encode_public_key(&public_key_share)
+                .map_err(|e| e.to_string())?,
+        },
+    };
+
+    let mut stream_guard = main_stream.lock().await;
+
+    send_message(&mut stream_guard, &payload)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Finishes resharing and reports the new epoch to the AS.
+async fn send_reshare_response(
+    config: &Arc<Config>,
+    signer: &Arc<Mutex<Signer>>,
+    main_stream: &Arc<Mutex<EnvelopeWriter>>,
+    session: SessionId,
+) -> Result<(), String> {
+    let (epoch, public_key_share) = {
+        let mut s = signer.lock().await;
+        let parties = s.key_session_parties();
+        let epoch = s.finish_reshare().map_err(|e| format!("{:?}", e))?;
+        s.rounds.close(session);
+        store_key_share(&mut s);
+        let outcome = format!("Reshared into epoch {}", epoch);
+        s.audit
+            .append(session, &parties, None, outcome, token::now())
+            .map_err(|e| e.to_string())?;
+        (epoch, s.public_key_share())
+    };
+    println!("Key reshared, epoch {}", epoch);
+
+    let public_key_share = match public_key_share {
+        Some(public_key_share) => {
+            Some(Encoder::encode_public_key(&public_key_share).map_err(|e| e.to_string())?)
+        }
+        None => None,
+    };
+    let payload = Payload {
+        sender: config.node_id,
+        session,
+        msg: Message::ReshareResponse {
+            epoch,
+            public_key_share,
+        },
+    };
+
+    let mut stream_guard = main_stream.lock().await;
+
+    send_message(&mut stream_guard, &payload)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Finishes Phase 2, which stores the presignature batch, and reports it to the AS.
+async fn send_round2_final_response(
+    config: &Arc<Config>,
+    signer: &Arc<Mutex<Signer>>,
+    main_stream: &Arc<Mutex<EnvelopeWriter>>,
+    session: SessionId,
+) -> Result<(), String> {
+    let commitments = {
+        let mut s = signer.lock().await;
+        let commitments = s.finish_round2(session).map_err(|e| format!("{:?}", e))?;
+        s.rounds.close(session);
+        let quorum = s.quorum(session);
+        let outcome = format!("Presigned {} signatures", commitments.e.len());
+        s.audit
+            .append(session, &quorum, None, outcome, token::now())
+            .map_err(|e| e.to_string())?;
+        commitments
+    };
+
+    let payload = Payload {
+        sender: config.node_id,
+        session,
+        msg: Message::Round2FinalResponse {
+            commitments: Encoder::encode_batch_commitments(&commitments),
+        },
+    };
+
+    // Lock the main stream and pass a mutable reference to send_message
+    let mut stream_guard = main_stream.lock().await;
+
+    send_message(&mut stream_guard, &payload)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Aborts `session` here and tells the AS that `culprit` misbehaved in it, with the envelope
+/// the culprit signed the message in.
+async fn send_blame(
+    config: &Arc<Config>,
+    signer: &Arc<Mutex<Signer>>,
+    main_stream: &Arc<Mutex<EnvelopeWriter>>,
+    session: SessionId,
+    culprit: ParticipantId,
+    reason: String,
+    evidence: String,
+) -> Result<(), String> {
+    signer.lock().await.abort(session);
+
+    let payload = Payload {
+        sender: config.node_id,
+        session,
+        msg: Message::Blame {
+            culprit,
+            reason,
+            evidence: Some(evidence),
+        },
+    };
+
+    let mut stream_guard = main_stream.lock().await;
+
+    send_message(&mut stream_guard, &payload)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Aborts `session` here and tells the AS that this signer cannot go on with it.
+async fn send_error(
+    config: &Arc<Config>,
+    signer: &Arc<Mutex<Signer>>,
+    main_stream: &Arc<Mutex<EnvelopeWriter>>,
+    session: SessionId,
+    reason: String,
+) -> Result<(), String> {
+    signer.lock().await.abort(session);
+
+    let payload = Payload {
+        sender: config.node_id,
+        session,
+        msg: Message::Error { reason },
+    };
+
+    let mut stream_guard = main_stream.lock().await;
+
+    send_message(&mut stream_guard, &payload)
+        .await
+        .map_err(|e| e.to_string())
+}
+
 // made async so we can await the Tokio mutex
 async fn handle_payload(
     payload: Payload,
+    envelope: &str,
     config: &Arc<Config>,
     signer: &Arc<Mutex<Signer>>,
-    main_stream: &Arc<Mutex<tokio::net::TcpStream>>,
-) -> Result<(), String> {
+    main_stream: &Arc<Mutex<EnvelopeWriter>>,
+    peers: &Peers,
+) -> Result<(), PayloadError> {
+    {
+        let mut s = signer.lock().await;
+        s.rounds.accept(&payload).map_err(PayloadError::Rejected)?;
+        // The request goes into the record of the session once it completes here
+        if payload.sender == 0 && !matches!(payload.msg, Message::Abort) {
+            s.audit.requested(payload.session, envelope);
+        }
+    }
+
     // Process the payload as needed
     match payload.msg {
-        Message::Start => Ok(()),
-        Message::SkShares { shares } => {
+        Message::Start { .. } => Ok(()),
+        Message::Abort => {
+            signer.lock().await.abort(payload.session);
+            Ok(())
+        }
+        Message::SkShares { shares, public_key } => {
             let sk_share_fr = Encoder::decode_sk_share(shares.as_str())
                 .map_err(|e| format!("Decode error: {}", e))?;
+            let public_key = Encoder::decode_public_key(public_key.as_str())
+                .map_err(|e| format!("Decode error: {}", e))?;
             let mut s = signer.lock().await;
-            s.set_sk_share(sk_share_fr);
+            // A dealt share is only taken once, replacing it takes a refresh or resharing
+            if s.sk_share.is_some() {
+                return Err(PayloadError::Rejected("Already holds a key share".into()));
+            }
+            s.set_sk_share(sk_share_fr, public_key);
+            store_key_share(&mut s);
+            Ok(())
+        }
+        Message::KeyStatusRequest => {
+            let msg = {
+                let mut s = signer.lock().await;
+                s.rounds.close(payload.session);
+                let public_key = s
+                    .public_key
+                    .as_ref()
+                    .map(Encoder::encode_public_key)
+                    .transpose()
+                    .map_err(|e| e.to_string())?;
+                let public_key_share = s
+                    .public_key_share()
+                    .as_ref()
+                    .map(Encoder::encode_public_key)
+                    .transpose()
+                    .map_err(|e| e.to_string())?;
+                Message::KeyStatus {
+                    public_key,
+                    public_key_share,
+                    epoch: s.epoch,
+                    threshold: s.threshold,
+                    committee: s.all_party_set.iter().copied().collect(),
+                }
+            };
+            let reply = Payload {
+                sender: config.node_id,
+                session: payload.session,
+                msg,
+            };
+            let mut stream = main_stream.lock().await;
+            send_message(&mut stream, &reply)
+                .await
+                .map_err(|e| e.to_string())?;
             Ok(())
         }
-        Message::Round1Request => {
+        Message::KeygenRequest => {
+            let (payloads, ready) = {
+                let mut s = signer.lock().await;
+                // A new key would replace the stored share, which only a resharing may do
+                if s.sk_share.is_some() {
+                    return Err(PayloadError::Rejected("Already holds a key share".into()));
+                }
+                let (shares, comms) = s
+                    .start_keygen(payload.session)
+                    .map_err(|e| format!("{:?}", e))?;
+                let commitments =
+                    Encoder::encode_coeff_commitments(&comms).map_err(|e| e.to_string())?;
+
+                let mut payloads: Vec<(ParticipantId, Payload)> = vec![];
+                for (other, share) in shares {
+                    payloads.push((
+                        other,
+                        Payload {
+                            sender: config.node_id,
+                            session: payload.session,
+                            msg: Message::KeygenShare {
+                                share: Encoder::encode_keygen_share(&share)
+                                    .map_err(|e| e.to_string())?,
+                                commitments: commitments.clone(),
+                            },
+                        },
+                    ));
+                }
+                (payloads, s.has_all_keygen_shares())
+            };
+
+            for (other, payload) in payloads {
+                send_to_peer(peers, other, &payload).await?;
+            }
+
+            if ready {
+                send_keygen_response(config, signer, main_stream, payload.session).await?;
+            }
+            Ok(())
+        }
+        Message::KeygenShare { share, commitments } => {
+            let share = Encoder::decode_keygen_share(share.as_str()).map_err(undecodable)?;
+            let comms =
+                Encoder::decode_coeff_commitments(commitments.as_str()).map_err(undecodable)?;
+
+            let ready = {
+                let mut s = signer.lock().await;
+                s.receive_keygen_share(payload.sender, share, comms)
+                    .map_err(misbehaved)?
+            };
+
+            if ready {
+                send_keygen_response(config, signer, main_stream, payload.session).await?;
+            }
+            Ok(())
+        }
+        Message::RefreshRequest { epoch } => {
+            let (payloads, ready) = {
+                let mut s = signer.lock().await;
+                let (shares, comms) = s
+                    .start_refresh(payload.session, epoch)
+                    .map_err(|e| format!("{:?}", e))?;
+                let commitments =
+                    Encoder::encode_coeff_commitments(&comms).map_err(|e| e.to_string())?;
+
+                let mut payloads: Vec<(ParticipantId, Payload)> = vec![];
+                for (other, share) in shares {
+                    payloads.push((
+                        other,
+                        Payload {
+                            sender: config.node_id,
+                            session: payload.session,
+                            msg: Message::RefreshShare {
+                                epoch,
+                                share: Encoder::encode_keygen_share(&share)
+                                    .map_err(|e| e.to_string())?,
+                                commitments: commitments.clone(),
+                            },
+                        },
+                    ));
+                }
+                (payloads, s.has_all_refresh_shares())
+            };
+
+            for (other, payload) in payloads {
+                send_to_peer(peers, other, &payload).await?;
+            }
+
+            if ready {
+                send_refresh_response(config, signer, main_stream, payload.session).await?;
+            }
+            Ok(())
+        }
+        Message::RefreshShare {
+            epoch,
+            share,
+            commitments,
+        } => {
+            let share = Encoder::decode_keygen_share(share.as_str()).map_err(undecodable)?;
+            let comms =
+                Encoder::decode_coeff_commitments(commitments.as_str()).map_err(undecodable)?;
+
+            let ready = {
+                let mut s = signer.lock().await;
+                s.receive_refresh_share(epoch, payload.sender, share, comms)
+                    .map_err(misbehaved)?
+            };
+
+            if ready {
+                send_refresh_response(config, signer, main_stream, payload.session).await?;
+            }
+            Ok(())
+        }
+        Message::ReshareRequest {
+            epoch,
+            dealers,
+            committee,
+            threshold,
+            public_key,
+        } => {
+            let reshare = Reshare {
+                epoch,
+                dealers: dealers.into_iter().collect(),
+                committee: committee.into_iter().collect(),
+                threshold,
+                public_key: Encoder::decode_public_key(public_key.as_str())
+                    .map_err(|e| format!("Decode error: {}", e))?,
+            };
+
+            let (payloads, ready) = {
+                let mut s = signer.lock().await;
+                let dealt = s
+                    .start_reshare(payload.session, reshare)
+                    .map_err(|e| format!("{:?}", e))?;
+
+                let mut payloads: Vec<(ParticipantId, Payload)> = vec![];
+                if let Some((shares, comms)) = dealt {
+                    let commitments =
+                        Encoder::encode_coeff_commitments(&comms).map_err(|e| e.to_string())?;
+                    for (other, share) in shares {
+                        payloads.push((
+                            other,
+                            Payload {
+                                sender: config.node_id,
+                                session: payload.session,
+                                msg: Message::ReshareShare {
+                                    epoch,
+                                    share: Encoder::encode_keygen_share(&share)
+                                        .map_err(|e| e.to_string())?,
+                                    commitments: commitments.clone(),
+                                },
+                            },
+                        ));
+                    }
+                }
+                (payloads, s.has_all_reshare_shares())
+            };
+
+            for (other, payload) in payloads {
+                send_to_peer(peers, other, &payload).await?;
+            }
+
+            if ready {
+                send_reshare_response(config, signer, main_stream, payload.session).await?;
+            }
+            Ok(())
+        }
+        Message::ReshareShare {
+            epoch,
+            share,
+            commitments,
+        } => {
+            let share = Encoder::decode_keygen_share(share.as_str()).map_err(undecodable)?;
+            let comms =
+                Encoder::decode_coeff_commitments(commitments.as_str()).map_err(undecodable)?;
+
+            let ready = {
+                let mut s = signer.lock().await;
+                s.receive_reshare_share(epoch, payload.sender, share, comms)
+                    .map_err(misbehaved)?
+            };
+
+            if ready {
+                send_reshare_response(config, signer, main_stream, payload.session).await?;
+            }
+            Ok(())
+        }
+        Message::Round1Request { batch_size, quorum } => {
             // println!("Received Round1Request from {}", payload.sender);
-            let s = signer.lock().await;
-            let (round1, comm, comm_zero) = s.do_round1();
-            // Lock the main stream and pass a mutable reference to send_message
-            let mut stream_guard = main_stream.lock().await;
+            let quorum = quorum.into_iter().collect::<BTreeSet<_>>();
+            // Base OT runs only the first time a pair of signers meets
+            {
+                let s = signer.lock().await;
+                s.check_quorum(&quorum).map_err(|e| format!("{:?}", e))?;
+            }
+            let mut others = quorum.clone();
+            others.remove(&config.node_id);
+            start_base_ot(config, signer, peers, others).await?;
 
-            let payload = Payload {
+            let (payloads, ready) = {
+                let mut s = signer.lock().await;
+                let (comm, comm_zero) = s
+                    .do_round1(payload.session, quorum, batch_size)
+                    .map_err(|e| format!("{:?}", e))?;
+                let commitments = Encoder::encode_commitments(&comm).map_err(|e| e.to_string())?;
+
+                let mut payloads: Vec<(ParticipantId, Payload)> = vec![];
+                for (other, comm_zero_share) in comm_zero {
+                    payloads.push((
+                        other,
+                        Payload {
+                            sender: config.node_id,
+                            session: payload.session,
+                            msg: Message::Round1Commitment {
+                                commitments: commitments.clone(),
+                                commitment_zero_share: Encoder::encode_commitments(
+                                    &comm_zero_share,
+                                )
+                                .map_err(|e| e.to_string())?,
+                            },
+                        },
+                    ));
+                }
+                (payloads, s.has_all_round1_commitments(payload.session))
+            };
+
+            for (other, payload) in payloads {
+                send_to_peer(peers, other, &payload).await?;
+            }
+
+            // Every other signer may have committed before the AS reached this signer
+            if ready {
+                send_round1_shares(config, signer, peers, payload.session).await?;
+            }
+            Ok(())
+        }
+        Message::Round1Commitment {
+            commitments,
+            commitment_zero_share,
+        } => {
+            let commitments =
+                Encoder::decode_commitments(commitments.as_str()).map_err(undecodable)?;
+            let commitment_zero_share =
+                Encoder::decode_commitments(commitment_zero_share.as_str()).map_err(undecodable)?;
+
+            let ready = {
+                let mut s = signer.lock().await;
+                s.receive_round1_commitment(
+                    payload.session,
+                    payload.sender,
+                    commitments,
+                    commitment_zero_share,
+                )
+                .map_err(presign_failed)?;
+                s.has_all_round1_commitments(payload.session)
+            };
+
+            if ready {
+                send_round1_shares(config, signer, peers, payload.session).await?;
+            }
+            Ok(())
+        }
+        Message::Round1Shares {
+            shares,
+            zero_shares,
+        } => {
+            let shares = Encoder::decode_shares_and_salts(shares.as_str()).map_err(undecodable)?;
+            let zero_shares =
+                Encoder::decode_shares_and_salts(zero_shares.as_str()).map_err(undecodable)?;
+
+            let round2 = {
+                let mut s = signer.lock().await;
+                let ready = s
+                    .receive_round1_shares(payload.session, payload.sender, shares, zero_shares)
+                    .map_err(presign_failed)?;
+                if !ready {
+                    return Ok(());
+                }
+                s.finish_round1(payload.session)
+                    .map_err(|e| format!("{:?}", e))?;
+                s.do_round2(payload.session)
+                    .map_err(|e| format!("{:?}", e))?
+            };
+
+            // The Phase 1 output stays here for Phase 2, the AS only learns that it is done
+            let reply = Payload {
                 sender: config.node_id,
-                msg: Message::Round1Response {
-                    phase1: Encoder::encode_phase1(&round1),
-                    commitments: Encoder::encode_commitments(&comm).unwrap(),
-                    commitments_map: Encoder::encode_map_commitments(&comm_zero).unwrap(),
+                session: payload.session,
+                msg: Message::Round1FinalResponse,
+            };
+            {
+                // Lock the main stream and pass a mutable reference to send_message
+                let mut stream_guard = main_stream.lock().await;
+
+                send_message(&mut stream_guard, &reply)
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+
+            // `None` means base OT with some signer is still running, Phase 2 starts once it is done
+            if let Some(round2) = round2 {
+                send_round2_messages(config, signer, main_stream, peers, payload.session, round2)
+                    .await?;
+            }
+            Ok(())
+        }
+        Message::SignRequest {
+            batch,
+            index,
+            quorum,
+            attributes,
+        } => {
+            let attributes: TokenAttributes =
+                serde_json::from_str(&attributes).map_err(|e| format!("Decode error: {}", e))?;
+            // Only messages derived here get signed, so the policy holds for what is signed
+            let messages = attributes.to_messages(config.message_count)?;
+
+            let signed = {
+                let mut s = signer.lock().await;
+                // Whether or not it signs, this signer is done with the session
+                s.rounds.close(payload.session);
+                let now = token::now();
+                // A request for a batch this signer does not hold is refused below, its record
+                // names the quorum the AS asked for
+                let quorum = s
+                    .presignatures
+                    .quorum(batch)
+                    .unwrap_or_else(|| quorum.into_iter().collect());
+                let signed = match s.policy.check(&attributes, now) {
+                    Ok(()) => {
+                        let share = s
+                            .sign(batch, index, &messages)
+                            .map_err(|e| format!("{:?}", e))?;
+                        s.policy.record(&attributes, now);
+                        Ok(share)
+                    }
+                    Err(reason) => Err(reason),
+                };
+                // A share only leaves this signer once its record is on disk
+                let outcome = match &signed {
+                    Ok(_) => "Signed".to_string(),
+                    Err(reason) => format!("Refused: {}", reason),
+                };
+                s.audit
+                    .append(
+                        payload.session,
+                        &quorum,
+                        Some(audit::messages_commitment(&messages)),
+                        outcome,
+                        now,
+                    )
+                    .map_err(|e| e.to_string())?;
+                signed
+            };
+
+            let msg = match signed {
+                Ok(share) => Message::SignResponse {
+                    share: Encoder::encode_signature_share(&share).map_err(|e| e.to_string())?,
                 },
+                Err(reason) => {
+                    println!(
+                        "Refused to sign the token for {} in session {}: {}",
+                        attributes.subject, payload.session, reason
+                    );
+                    Message::Refusal { reason }
+                }
+            };
+            let reply = Payload {
+                sender: config.node_id,
+                session: payload.session,
+                msg,
             };
 
-            // println!("Sending {:?}", payload);
+            let mut stream_guard = main_stream.lock().await;
 
-            send_message(&mut *stream_guard, &payload)
+            send_message(&mut stream_guard, &reply)
                 .await
                 .map_err(|e| e.to_string())?;
             Ok(())
         }
-        Message::Round1FinalRequest { phase1 } => {
-            let phase1 = Encoder::decode_phase1(phase1.as_str())
-                .map_err(|e| format!("Decode error: {}", e))?;
-            let s = signer.lock().await;
-            let out = s.finish_round1(phase1);
+        Message::BaseOTRestart => {
+            let pubkey = {
+                let mut s = signer.lock().await;
+                s.restart_base_ot(payload.sender).map_err(misbehaved)?
+            };
+            // `None` means a run with the sender is already in progress
+            if let Some(pubkey) = pubkey {
+                let reply = Payload {
+                    sender: config.node_id,
+                    session: NO_SESSION,
+                    msg: Message::BaseOTSenderPubKey {
+                        pubkey: Encoder::encode_base_ot_sender_pubkey(&pubkey)
+                            .map_err(|e| e.to_string())?,
+                    },
+                };
+                send_to_peer(peers, payload.sender, &reply).await?;
+            }
+            Ok(())
+        }
+        Message::BaseOTSenderPubKey { pubkey } => {
+            let pubkey =
+                Encoder::decode_base_ot_sender_pubkey(pubkey.as_str()).map_err(undecodable)?;
 
-            // Lock the main stream and pass a mutable reference to send_message
-            let mut stream_guard = main_stream.lock().await;
+            let reply = {
+                let mut s = signer.lock().await;
+                s.receive_base_ot_sender_pubkey(payload.sender, pubkey)
+                    .map_err(misbehaved)?
+            };
 
-            let payload = Payload {
+            let reply = Payload {
                 sender: config.node_id,
-                msg: Message::Round1FinalResponse {
-                    round1: Encoder::encode_phase1_output(&out),
+                session: NO_SESSION,
+                msg: Message::BaseOTReceiverPubKey {
+                    pubkey: Encoder::encode_base_ot_receiver_pubkey(&reply)
+                        .map_err(|e| e.to_string())?,
                 },
             };
+            send_to_peer(peers, payload.sender, &reply).await?;
+            Ok(())
+        }
+        Message::BaseOTReceiverPubKey { pubkey } => {
+            let pubkey =
+                Encoder::decode_base_ot_receiver_pubkey(pubkey.as_str()).map_err(undecodable)?;
 
-            send_message(&mut *stream_guard, &payload)
-                .await
-                .map_err(|e| e.to_string())?;
+            let challenges = {
+                let mut s = signer.lock().await;
+                s.base_ot
+                    .receive_receiver_pubkey::<BASE_OT_KEY_SIZE>(payload.sender, pubkey)
+                    .map_err(misbehaved)?
+            };
+
+            let reply = Payload {
+                sender: config.node_id,
+                session: NO_SESSION,
+                msg: Message::BaseOTChallenges {
+                    challenges: Encoder::encode_base_ot_challenges(&challenges)
+                        .map_err(|e| e.to_string())?,
+                },
+            };
+            send_to_peer(peers, payload.sender, &reply).await?;
             Ok(())
         }
-        Message::Round2Request {
-            masked_signing_key_share,
-            masked_rs,
-        } => {
-            let masked_signing_key_share =
-                Encoder::decode_vec_fr(&masked_signing_key_share).unwrap();
-            let masked_rs = Encoder::decode_vec_fr(&masked_rs).unwrap();
+        Message::BaseOTChallenges { challenges } => {
+            let challenges =
+                Encoder::decode_base_ot_challenges(challenges.as_str()).map_err(undecodable)?;
+
+            let responses = {
+                let mut s = signer.lock().await;
+                s.base_ot
+                    .receive_challenges(payload.sender, challenges)
+                    .map_err(misbehaved)?
+            };
+
+            let reply = Payload {
+                sender: config.node_id,
+                session: NO_SESSION,
+                msg: Message::BaseOTResponses {
+                    responses: Encoder::encode_base_ot_responses(&responses)
+                        .map_err(|e| e.to_string())?,
+                },
+            };
+            send_to_peer(peers, payload.sender, &reply).await?;
+            Ok(())
+        }
+        Message::BaseOTResponses { responses } => {
+            let responses =
+                Encoder::decode_base_ot_responses(responses.as_str()).map_err(undecodable)?;
 
-            let s = signer.lock().await;
-            let (round2, map) = s.do_round2(masked_signing_key_share, masked_rs);
+            let (hashed_keys, round2) = {
+                let mut s = signer.lock().await;
+                let hashed_keys = s
+                    .base_ot
+                    .receive_responses(payload.sender, responses)
+                    .map_err(misbehaved)?;
+                (
+                    hashed_keys,
+                    s.resume_waiting_round2s().map_err(|e| format!("{:?}", e))?,
+                )
+            };
 
-            let payload = Payload {
+            let reply = Payload {
                 sender: config.node_id,
-                msg: Message::Round2Response {
-                    phase2: Encoder::encode_phase2(&round2),
-                    map: Encoder::encode_map(&map).unwrap(),
+                session: NO_SESSION,
+                msg: Message::BaseOTHashedKeys {
+                    hashed_keys: Encoder::encode_base_ot_hashed_keys(&hashed_keys)
+                        .map_err(|e| e.to_string())?,
                 },
             };
+            send_to_peer(peers, payload.sender, &reply).await?;
 
-            // Lock the main stream and pass a mutable reference to send_message
-            let mut stream_guard = main_stream.lock().await;
+            for (session, round2) in round2 {
+                send_round2_messages(config, signer, main_stream, peers, session, round2).await?;
+            }
+            Ok(())
+        }
+        Message::BaseOTHashedKeys { hashed_keys } => {
+            let hashed_keys =
+                Encoder::decode_base_ot_hashed_keys(hashed_keys.as_str()).map_err(undecodable)?;
 
-            send_message(&mut *stream_guard, &payload)
-                .await
-                .map_err(|e| e.to_string())?;
+            let round2 = {
+                let mut s = signer.lock().await;
+                s.base_ot
+                    .receive_hashed_keys(payload.sender, hashed_keys)
+                    .map_err(misbehaved)?;
+                s.resume_waiting_round2s().map_err(|e| format!("{:?}", e))?
+            };
+
+            for (session, round2) in round2 {
+                send_round2_messages(config, signer, main_stream, peers, session, round2).await?;
+            }
+            Ok(())
+        }
+        Message::Round2Message1 { message1 } => {
+            let message1 = Encoder::decode_msg1(message1.as_str()).map_err(undecodable)?;
+
+            let (m2, ready) = {
+                let mut s = signer.lock().await;
+                let m2 = s
+                    .receive_round2_message1(payload.session, payload.sender, message1)
+                    .map_err(presign_failed)?;
+                (m2, s.has_all_round2_messages(payload.session))
+            };
+
+            // `None` means Phase 2 has not started here yet, the reply goes out from `do_round2`
+            if let Some(m2) = m2 {
+                let reply = Payload {
+                    sender: config.node_id,
+                    session: payload.session,
+                    msg: Message::Round2Message2 {
+                        message2: Encoder::encode_msg2(&m2).map_err(|e| e.to_string())?,
+                    },
+                };
+                send_to_peer(peers, payload.sender, &reply).await?;
+            }
+
+            if ready {
+                send_round2_final_response(config, signer, main_stream, payload.session).await?;
+            }
+            Ok(())
+        }
+        Message::Round2Message2 { message2 } => {
+            let message2 = Encoder::decode_msg2(message2.as_str()).map_err(undecodable)?;
+
+            let ready = {
+                let mut s = signer.lock().await;
+                s.receive_round2_message2(payload.session, payload.sender, message2)
+                    .map_err(presign_failed)?;
+                s.has_all_round2_messages(payload.session)
+            };
+
+            if ready {
+                send_round2_final_response(config, signer, main_stream, payload.session).await?;
+            }
             Ok(())
         }
         _ => Ok(()),
     }
 }
 
-async fn connect_to_peer(addr: &str) -> tokio::io::Result<tokio::net::TcpStream> {
-    let stream = tokio::net::TcpStream::connect(addr).await?;
-    Ok(stream)
+/// Connects to signer `node_id`, retrying with a growing backoff while it is not up.
+async fn connect_to_peer(peers: &Peers, node_id: u16) -> Result<EnvelopeWriter, String> {
+    let addr = format!("node{}:{}", node_id, 8000 + node_id);
+    let mut backoff = tokio::time::Duration::from_millis(PEER_RECONNECT_BACKOFF_MS);
+    for attempt in 1..=PEER_RECONNECT_ATTEMPTS {
+        match peers.keys.connect(&addr, node_id).await {
+            Ok((_, stream)) => {
+                println!("Connected to peer {}", addr);
+                return Ok(EnvelopeWriter::new(
+                    stream,
+                    node_id,
+                    Arc::clone(&peers.signing_keys),
+                ));
+            }
+            Err(e) if attempt < PEER_RECONNECT_ATTEMPTS => {
+                eprintln!(
+                    "Failed to connect to peer {}: {}, retrying in {:?}",
+                    addr, e, backoff
+                );
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+            Err(e) => {
+                return Err(format!("Failed to connect to peer {}: {}", addr, e));
+            }
+        }
+    }
+    Err(format!("No connection to peer {}", node_id))
+}
+
+/// Connects to every other signer and returns the ones it reached. Like the connection to the
+/// AS these are only sent on, each signer reads what others send it from its listener.
+async fn connect_to_peers(peers: &Peers, self_id: u16, total_nodes: u16) -> BTreeSet<u16> {
+    let mut connected = BTreeSet::new();
+    for node_id in 1..total_nodes {
+        if node_id == self_id {
+            continue;
+        }
+        let link = peers.link(node_id).await;
+        let mut link = link.lock().await;
+        // A message sent in the meantime may have connected already
+        if link.is_some() {
+            connected.insert(node_id);
+            continue;
+        }
+        match connect_to_peer(peers, node_id).await {
+            Ok(stream) => {
+                *link = Some(stream);
+                connected.insert(node_id);
+            }
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+    connected
 }
 
 #[tokio::main]
@@ -197,7 +1278,39 @@ async fn main() -> tokio::io::Result<()> {
 
     // Save fields we need later before moving `config`
     let node_id = config.node_id;
-    // let total_nodes = config.total_nodes;
+    let total_nodes = config.total_nodes;
+
+    let keys = match NodeKeys::load(
+        &config.node_keys_dir,
+        node_id,
+        total_nodes,
+        &config.deployment_id,
+    ) {
+        Ok(keys) => Arc::new(keys),
+        Err(e) => {
+            eprintln!(
+                "Failed to load node keys from {}: {}. Generate them with the node_keys binary.",
+                config.node_keys_dir, e
+            );
+            std::process::exit(1);
+        }
+    };
+
+    let signing_keys = match SigningKeys::load(
+        &config.node_keys_dir,
+        node_id,
+        total_nodes,
+        &config.deployment_id,
+    ) {
+        Ok(keys) => Arc::new(keys),
+        Err(e) => {
+            eprintln!(
+                "Failed to load signing keys from {}: {}. Generate them with the node_keys binary.",
+                config.node_keys_dir, e
+            );
+            std::process::exit(1);
+        }
+    };
 
     let port = 8000 + node_id;
 
@@ -207,12 +1320,85 @@ async fn main() -> tokio::io::Result<()> {
     // Sleep for a few seconds to allow all nodes to start
     tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
 
-    let main_stream = connect_to_peer("node0:8000").await?;
+    let (_, main_stream) = keys.connect("node0:8000", 0).await?;
+    let main_stream = EnvelopeWriter::new(main_stream, 0, Arc::clone(&signing_keys));
 
-    let signer = Arc::new(Mutex::new(Signer::new((*config).clone())));
+    let mut signer = Signer::new((*config).clone());
+    // A share stored by an earlier run is used right away, without a new keygen
+    match signer.load_key_share() {
+        Ok(true) => println!(
+            "Loaded key share of epoch {} from {}",
+            signer.epoch, config.keystore_path
+        ),
+        Ok(false) if signer.has_keystore() => {
+            println!("No key share in {} yet", config.keystore_path)
+        }
+        Ok(false) => println!("KEYSTORE_PASSPHRASE not set, the key share stays in memory only"),
+        Err(e) => {
+            eprintln!(
+                "Failed to load key share from {}: {}",
+                config.keystore_path, e
+            );
+            std::process::exit(1);
+        }
+    }
+    signer.audit.sign_with(Arc::clone(&signing_keys));
+    match signer.audit.load() {
+        Ok(records) => println!(
+            "Audit log {} holds {} records",
+            config.audit_log_path, records
+        ),
+        Err(e) => {
+            eprintln!("Audit log {} is broken: {}", config.audit_log_path, e);
+            std::process::exit(1);
+        }
+    }
+    let signer = Arc::new(Mutex::new(signer));
     let main_stream = Arc::new(Mutex::new(main_stream));
 
-    handle_listener(listener, config, signer, main_stream).await?;
+    // Accept before connecting out, a handshake only completes once the other end accepts
+    let peers: Peers = Arc::new(PeerLinks {
+        keys: Arc::clone(&keys),
+        signing_keys: Arc::clone(&signing_keys),
+        streams: Mutex::new(HashMap::new()),
+    });
+    let listener_fut = tokio::spawn(handle_listener(
+        listener,
+        Arc::clone(&config),
+        Arc::clone(&signer),
+        Arc::clone(&main_stream),
+        Arc::clone(&peers),
+    ));
+
+    // Phase 1 runs directly between the signers, so each signer also connects to the others.
+    // Messages to a signer that is not connected yet connect to it first
+    let others = connect_to_peers(&peers, node_id, total_nodes).await;
+
+    // Let the AS know this signer is up, so it can be picked for signing sessions
+    let heartbeat_stream = Arc::clone(&main_stream);
+    let heartbeat = Payload {
+        sender: node_id,
+        session: NO_SESSION,
+        msg: Message::Heartbeat,
+    };
+    tokio::spawn(async move {
+        let mut interval =
+            tokio::time::interval(tokio::time::Duration::from_secs(HEARTBEAT_INTERVAL_SECS));
+        loop {
+            interval.tick().await;
+            let mut stream = heartbeat_stream.lock().await;
+            if let Err(e) = send_message(&mut stream, &heartbeat).await {
+                eprintln!("Failed to send heartbeat: {}", e);
+            }
+        }
+    });
+
+    // Run base OT with every reachable signer up front so signing sessions do not pay for it
+    if let Err(e) = start_base_ot(&config, &signer, &peers, others).await {
+        eprintln!("Failed to start base OT: {}", e);
+    }
+
+    listener_fut.await??;
 
     Ok(())
 }
@@ -2,12 +2,10 @@
 
 use crate::exp_utils::{get_as_millis, setup_messages, Timer};
 use crate::ot::*;
-use ark_bls12_381::{Bls12_381, Fr};
+use ark_bls12_381::{Bls12_381, Fr, G2Affine};
 use ark_ff::PrimeField;
-use ark_std::{
-    rand::{rngs::StdRng, SeedableRng},
-    Zero,
-};
+use crate::rng::{seeded_rng, BENCH_SEED};
+use ark_std::Zero;
 use bbs_plus::setup::{PublicKeyG2, SecretKey, SignatureParams23G1};
 use bbs_plus::signature_23::Signature23G1;
 use bbs_plus::threshold::multiplication_phase::Phase2;
@@ -20,6 +18,8 @@ use oblivious_transfer_protocols::ot_based_multiplication::{
 };
 use oblivious_transfer_protocols::*;
 use rand::prelude::*;
+use secret_sharing_and_dkg::common::SharesAccumulator;
+use secret_sharing_and_dkg::feldman_vss;
 use secret_sharing_and_dkg::shamir_ss::deal_random_secret;
 use std::collections::BTreeSet;
 use std::time::{Duration, Instant};
@@ -30,6 +30,8 @@ const STATISTICAL_SECURITY_PARAMETER: u16 = 80;
 const SIG_BATCH_SIZE: u32 = 1;
 const THRESHOLD_SIGNERS: u16 = 5;
 const TOTAL_SIGNERS: u16 = 8;
+// Deal the key from a single party instead of running DKG among the signers
+const TRUSTED_DEALER: bool = false;
 
 pub fn trusted_party_keygen<R: RngCore>(
     rng: &mut R,
@@ -44,6 +46,49 @@ pub fn trusted_party_keygen<R: RngCore>(
     (public_key, secret, secret_shares)
 }
 
+/// Feldman DKG among all signers, run in-process. Each signer deals a random secret and adds up
+/// the shares it receives. The public key comes from the commitments, so no party ever holds the
+/// secret key.
+pub fn distributed_keygen<R: RngCore>(
+    rng: &mut R,
+    threshold: ParticipantId,
+    total: ParticipantId,
+    params: SignatureParams23G1<Bls12_381>,
+) -> (PublicKeyG2<Bls12_381>, Vec<Fr>) {
+    let mut accumulators = (1..=total)
+        .map(|i| SharesAccumulator::<G2Affine, _>::new(i, threshold))
+        .collect::<Vec<_>>();
+
+    for dealer in 1..=total {
+        let (_, shares, comms, _) =
+            feldman_vss::deal_random_secret::<_, G2Affine>(rng, threshold, total, &params.g2)
+                .unwrap();
+        for share in shares.0 {
+            let receiver = share.id;
+            let accumulator = &mut accumulators[receiver as usize - 1];
+            if receiver == dealer {
+                accumulator.add_self_share(share, comms.clone());
+            } else {
+                accumulator
+                    .add_received_share(dealer, share, comms.clone(), &params.g2)
+                    .unwrap();
+            }
+        }
+    }
+
+    let mut public_key = None;
+    let mut secret_shares = vec![];
+    for accumulator in accumulators {
+        let (share, _, threshold_pk) = accumulator.finalize(&params.g2).unwrap();
+        if let Some(pk) = public_key {
+            assert_eq!(pk, threshold_pk);
+        }
+        public_key = Some(threshold_pk);
+        secret_shares.push(share.share);
+    }
+    (PublicKeyG2(public_key.unwrap()), secret_shares)
+}
+
 pub fn setup_public_key<F: PrimeField>(
     secret: Fr,
     params: SignatureParams23G1<Bls12_381>,
@@ -189,7 +234,7 @@ pub fn verify(
 }
 
 pub fn test_token() {
-    let mut rng = StdRng::seed_from_u64(0u64);
+    let mut rng = seeded_rng(BENCH_SEED);
     let message_count = 3;
     let params: SignatureParams23G1<Bls12_381> =
         SignatureParams23G1::<Bls12_381>::generate_using_rng(&mut rng, message_count);
@@ -204,8 +249,13 @@ pub fn test_token() {
     let all_party_set = (1..=TOTAL_SIGNERS).into_iter().collect::<BTreeSet<_>>();
     let threshold_party_set = (1..=THRESHOLD_SIGNERS).into_iter().collect::<BTreeSet<_>>();
     let messages = setup_messages(&mut rng, message_count);
-    let (public_key, sk, sk_shares) =
-        trusted_party_keygen(&mut rng, THRESHOLD_SIGNERS, TOTAL_SIGNERS, params.clone());
+    let (public_key, sk_shares) = if TRUSTED_DEALER {
+        let (public_key, _sk, sk_shares) =
+            trusted_party_keygen(&mut rng, THRESHOLD_SIGNERS, TOTAL_SIGNERS, params.clone());
+        (public_key, sk_shares)
+    } else {
+        distributed_keygen(&mut rng, THRESHOLD_SIGNERS, TOTAL_SIGNERS, params.clone())
+    };
     let base_ot_outputs = do_pairwise_base_ot::<BASE_OT_KEY_SIZE>(
         &mut rng,
         ote_params.num_base_ot(),
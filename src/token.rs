@@ -0,0 +1,114 @@
+use ark_bls12_381::Fr;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::attributes::{AttributeError, AttributeSchema, AttributeType, AttributeValue};
+
+/// The signed attributes of every token, in message order. Any further messages of the
+/// signature parameters are zero.
+pub fn schema() -> AttributeSchema {
+    AttributeSchema::of(&[
+        ("subject", AttributeType::String),
+        ("audience", AttributeType::String),
+        ("issued_at", AttributeType::Integer),
+        ("expires_at", AttributeType::Integer),
+        ("credential_proof", AttributeType::String),
+    ])
+    .unwrap()
+}
+
+/// What a token says about its holder. The AS puts it together for each login, and every
+/// signer derives the signed messages from it itself and checks it against its own policy.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TokenAttributes {
+    pub subject: String,
+    pub audience: String,
+    /// Random, so that two tokens never coincide, even for the same subject in the same second.
+    /// Not signed, it only tells a retried token from a new one at the signers.
+    pub token_id: u64,
+    /// UNIX seconds.
+    pub issued_at: u64,
+    pub expires_at: u64,
+    /// Proof of a verified credential the login was backed by, if any.
+    pub credential_proof: Option<String>,
+}
+
+impl TokenAttributes {
+    /// Attributes of token `token_id` for `subject` and `audience`, valid for `lifetime` seconds
+    /// from now.
+    pub fn new(
+        subject: String,
+        audience: String,
+        token_id: u64,
+        credential_proof: Option<String>,
+        lifetime: u64,
+    ) -> Self {
+        let issued_at = now();
+        Self {
+            subject,
+            audience,
+            token_id,
+            issued_at,
+            expires_at: issued_at.saturating_add(lifetime),
+            credential_proof,
+        }
+    }
+
+    pub fn lifetime(&self) -> u64 {
+        self.expires_at.saturating_sub(self.issued_at)
+    }
+
+    /// The claims the token signature covers, see `schema`. A token without a credential proof
+    /// has it absent.
+    pub fn claims(&self) -> BTreeMap<String, AttributeValue> {
+        let mut claims = BTreeMap::from([
+            (
+                "subject".to_string(),
+                AttributeValue::String(self.subject.clone()),
+            ),
+            (
+                "audience".to_string(),
+                AttributeValue::String(self.audience.clone()),
+            ),
+            (
+                "issued_at".to_string(),
+                AttributeValue::Integer(self.issued_at as i64),
+            ),
+            (
+                "expires_at".to_string(),
+                AttributeValue::Integer(self.expires_at as i64),
+            ),
+        ]);
+        if let Some(proof) = &self.credential_proof {
+            claims.insert(
+                "credential_proof".to_string(),
+                AttributeValue::String(proof.clone()),
+            );
+        }
+        claims
+    }
+
+    /// The `message_count` messages the token signature covers.
+    pub fn to_messages(&self, message_count: u32) -> Result<Vec<Fr>, String> {
+        schema()
+            .encode(&self.claims(), message_count)
+            .map_err(|e| e.to_string())
+    }
+
+    /// Decodes the messages a token presentation reveals. The holder sends the revealed strings
+    /// along in `claimed`.
+    pub fn decode(
+        revealed: &BTreeMap<usize, Fr>,
+        claimed: &BTreeMap<String, AttributeValue>,
+    ) -> Result<BTreeMap<String, Option<AttributeValue>>, AttributeError> {
+        schema().decode(revealed, claimed)
+    }
+}
+
+/// Current UNIX time in seconds.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs())
+}
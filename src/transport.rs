@@ -0,0 +1,482 @@
+use base64::{engine::general_purpose, Engine as _};
+use snow::{Builder, HandshakeState, StatelessTransportState};
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind, Result};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use zeroize::Zeroizing;
+
+use crate::helper::message::ParticipantId;
+
+/// Both sides send their static key, so the same handshake serves nodes, which check the key
+/// against the pinned one of the node, and clients, which have no pinned key.
+const NOISE_PARAMS: &str = "Noise_XX_25519_ChaChaPoly_BLAKE2s";
+/// Bound into every handshake together with the deployment, so nodes of different deployments
+/// never complete one even when they share keys.
+const PROLOGUE_TAG: &[u8] = b"verisso-transport";
+const MAX_FRAME_SIZE: usize = 65535;
+const TAG_SIZE: usize = 16;
+const MAX_CHUNK_SIZE: usize = MAX_FRAME_SIZE - TAG_SIZE;
+/// Longest line a peer may send, well above the largest Phase 2 message. Without a limit a peer
+/// that never sends a newline makes the reader buffer all it sends.
+const MAX_LINE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Who is at the other end of an authenticated connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Peer {
+    /// The node the static key is pinned for. Node 0 is the AS.
+    Node(ParticipantId),
+    /// Anyone else, e.g. a user logging in.
+    Client,
+}
+
+/// The static X25519 key of this node and the pinned public keys of every node.
+pub struct NodeKeys {
+    private: Zeroizing<Vec<u8>>,
+    nodes: BTreeMap<ParticipantId, Vec<u8>>,
+    prologue: Vec<u8>,
+}
+
+fn params() -> snow::params::NoiseParams {
+    NOISE_PARAMS.parse().unwrap()
+}
+
+fn noise_error(e: snow::Error) -> Error {
+    Error::new(ErrorKind::InvalidData, e.to_string())
+}
+
+/// Directory of the private keys of `node_id`, the only part of the key directory that node
+/// needs besides the public keys.
+pub fn private_dir(dir: &Path, node_id: ParticipantId) -> PathBuf {
+    dir.join(format!("node{}", node_id))
+}
+
+/// Directory of the public keys of all nodes, which every node needs.
+pub fn public_dir(dir: &Path) -> PathBuf {
+    dir.join("public")
+}
+
+fn private_key_path(dir: &Path, node_id: ParticipantId) -> PathBuf {
+    private_dir(dir, node_id).join(format!("node{}.key", node_id))
+}
+
+fn public_key_path(dir: &Path, node_id: ParticipantId) -> PathBuf {
+    public_dir(dir).join(format!("node{}.pub", node_id))
+}
+
+fn read_key(path: &Path) -> Result<Vec<u8>> {
+    let encoded = Zeroizing::new(std::fs::read_to_string(path)?);
+    general_purpose::STANDARD
+        .decode(encoded.trim())
+        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))
+}
+
+impl NodeKeys {
+    /// Loads the key of node `node_id` and the public keys of nodes 0 to `total_nodes - 1` from
+    /// `dir`, as written by the `node_keys` binary.
+    pub fn load(
+        dir: impl AsRef<Path>,
+        node_id: ParticipantId,
+        total_nodes: u16,
+        deployment_id: &str,
+    ) -> Result<Self> {
+        let dir = dir.as_ref();
+        let private = Zeroizing::new(read_key(&private_key_path(dir, node_id))?);
+        let mut nodes = BTreeMap::new();
+        for id in 0..total_nodes {
+            nodes.insert(id, read_key(&public_key_path(dir, id))?);
+        }
+        Ok(Self {
+            private,
+            nodes,
+            prologue: prologue(deployment_id),
+        })
+    }
+
+    /// Keys of a client: a fresh static key and the pinned public key of the AS. An operator
+    /// instead loads the keys of the AS, node 0, to be recognised as such.
+    pub fn client(dir: impl AsRef<Path>, deployment_id: &str) -> Result<Self> {
+        let private = Zeroizing::new(
+            Builder::new(params())
+                .generate_keypair()
+                .map_err(noise_error)?
+                .private,
+        );
+        let mut nodes = BTreeMap::new();
+        nodes.insert(0, read_key(&public_key_path(dir.as_ref(), 0))?);
+        Ok(Self {
+            private,
+            nodes,
+            prologue: prologue(deployment_id),
+        })
+    }
+
+    /// Writes a key pair for every node from 0 to `total_nodes - 1` that has none in `dir` yet.
+    /// Returns the nodes that got a new key.
+    pub fn generate(dir: impl AsRef<Path>, total_nodes: u16) -> Result<Vec<ParticipantId>> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(public_dir(dir))?;
+        let mut generated = vec![];
+        for id in 0..total_nodes {
+            if private_key_path(dir, id).exists() {
+                continue;
+            }
+            std::fs::create_dir_all(private_dir(dir, id))?;
+            let keypair = Builder::new(params())
+                .generate_keypair()
+                .map_err(noise_error)?;
+            let private = Zeroizing::new(general_purpose::STANDARD.encode(&keypair.private));
+            write_private(&private_key_path(dir, id), private.as_bytes())?;
+            std::fs::write(
+                public_key_path(dir, id),
+                general_purpose::STANDARD.encode(&keypair.public),
+            )?;
+            generated.push(id);
+        }
+        Ok(generated)
+    }
+
+    /// The node `public_key` is pinned for.
+    fn node_of(&self, public_key: &[u8]) -> Option<ParticipantId> {
+        self.nodes
+            .iter()
+            .find(|(_, key)| key.as_slice() == public_key)
+            .map(|(id, _)| *id)
+    }
+
+    fn builder(&self) -> Builder<'_> {
+        Builder::new(params())
+            .local_private_key(&self.private)
+            .prologue(&self.prologue)
+    }
+
+    /// Connects to node `node_id` at `addr`. Fails unless the other end holds the key pinned for
+    /// that node.
+    pub async fn connect(
+        &self,
+        addr: &str,
+        node_id: ParticipantId,
+    ) -> Result<(SecureReader, SecureWriter)> {
+        let expected = self.nodes.get(&node_id).ok_or_else(|| {
+            Error::new(ErrorKind::NotFound, format!("No key of node {}", node_id))
+        })?;
+        let mut stream = TcpStream::connect(addr).await?;
+        let mut handshake = self.builder().build_initiator().map_err(noise_error)?;
+
+        // -> e
+        write_handshake(&mut stream, &mut handshake).await?;
+        // <- e, ee, s, es
+        read_handshake(&mut stream, &mut handshake).await?;
+        if handshake.get_remote_static() != Some(expected.as_slice()) {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!("{} does not hold the key of node {}", addr, node_id),
+            ));
+        }
+        // -> s, se
+        write_handshake(&mut stream, &mut handshake).await?;
+
+        split(stream, handshake)
+    }
+
+    /// Runs the handshake on an accepted connection and tells which node, if any, is at the
+    /// other end.
+    pub async fn accept(
+        &self,
+        mut stream: TcpStream,
+    ) -> Result<(Peer, SecureReader, SecureWriter)> {
+        let mut handshake = self.builder().build_responder().map_err(noise_error)?;
+
+        // -> e
+        read_handshake(&mut stream, &mut handshake).await?;
+        // <- e, ee, s, es
+        write_handshake(&mut stream, &mut handshake).await?;
+        // -> s, se
+        read_handshake(&mut stream, &mut handshake).await?;
+
+        let peer = handshake
+            .get_remote_static()
+            .and_then(|key| self.node_of(key))
+            .map_or(Peer::Client, Peer::Node);
+        let (reader, writer) = split(stream, handshake)?;
+        Ok((peer, reader, writer))
+    }
+}
+
+fn prologue(deployment_id: &str) -> Vec<u8> {
+    let mut prologue = PROLOGUE_TAG.to_vec();
+    prologue.extend_from_slice(&(deployment_id.len() as u64).to_be_bytes());
+    prologue.extend_from_slice(deployment_id.as_bytes());
+    prologue
+}
+
+/// Writes a new file only the owner can read.
+#[cfg(unix)]
+pub fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(contents)
+}
+
+#[cfg(not(unix))]
+pub fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    std::fs::write(path, contents)
+}
+
+async fn write_frame<W: AsyncWriteExt + Unpin>(stream: &mut W, frame: &[u8]) -> Result<()> {
+    let mut buffer = Vec::with_capacity(2 + frame.len());
+    buffer.extend_from_slice(&(frame.len() as u16).to_be_bytes());
+    buffer.extend_from_slice(frame);
+    stream.write_all(&buffer).await
+}
+
+/// Reads one length-prefixed frame, `None` if the connection was closed between frames.
+async fn read_frame<R: AsyncReadExt + Unpin>(stream: &mut R) -> Result<Option<Vec<u8>>> {
+    let len = match stream.read_u16().await {
+        Ok(len) => len as usize,
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mut frame = vec![0u8; len];
+    stream.read_exact(&mut frame).await?;
+    Ok(Some(frame))
+}
+
+async fn write_handshake(stream: &mut TcpStream, handshake: &mut HandshakeState) -> Result<()> {
+    let mut message = vec![0u8; MAX_FRAME_SIZE];
+    let len = handshake
+        .write_message(&[], &mut message)
+        .map_err(noise_error)?;
+    write_frame(stream, &message[..len]).await
+}
+
+async fn read_handshake(stream: &mut TcpStream, handshake: &mut HandshakeState) -> Result<()> {
+    let message = read_frame(stream)
+        .await?
+        .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "Connection closed in handshake"))?;
+    let mut payload = vec![0u8; MAX_FRAME_SIZE];
+    handshake
+        .read_message(&message, &mut payload)
+        .map_err(noise_error)?;
+    Ok(())
+}
+
+fn split(stream: TcpStream, handshake: HandshakeState) -> Result<(SecureReader, SecureWriter)> {
+    let transport = Arc::new(
+        handshake
+            .into_stateless_transport_mode()
+            .map_err(noise_error)?,
+    );
+    let (reader, writer) = stream.into_split();
+    Ok((
+        SecureReader {
+            inner: reader,
+            transport: Arc::clone(&transport),
+            nonce: 0,
+            buffer: Vec::new(),
+        },
+        SecureWriter {
+            inner: writer,
+            transport,
+            nonce: 0,
+        },
+    ))
+}
+
+/// Receiving half of an authenticated connection. Each half counts its own nonces, so a frame
+/// that was dropped, replayed or reordered fails to decrypt.
+pub struct SecureReader {
+    inner: OwnedReadHalf,
+    transport: Arc<StatelessTransportState>,
+    nonce: u64,
+    /// Decrypted bytes past the last line returned.
+    buffer: Vec<u8>,
+}
+
+impl SecureReader {
+    /// Appends the next line, including its newline, to `line`. Returns 0 once the other end
+    /// closed the connection. A line longer than `MAX_LINE_SIZE` is an error.
+    pub async fn read_line(&mut self, line: &mut String) -> Result<usize> {
+        // Bytes already searched for a newline, so a long line is not searched again per frame
+        let mut searched = 0;
+        loop {
+            if let Some(end) = self.buffer[searched..].iter().position(|b| *b == b'\n') {
+                let rest = self.buffer.split_off(searched + end + 1);
+                let text = String::from_utf8(std::mem::replace(&mut self.buffer, rest))
+                    .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+                line.push_str(&text);
+                return Ok(text.len());
+            }
+            if self.buffer.len() > MAX_LINE_SIZE {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Line longer than {} bytes", MAX_LINE_SIZE),
+                ));
+            }
+            searched = self.buffer.len();
+
+            let Some(frame) = read_frame(&mut self.inner).await? else {
+                return Ok(0);
+            };
+            let mut plaintext = vec![0u8; frame.len()];
+            let len = self
+                .transport
+                .read_message(self.nonce, &frame, &mut plaintext)
+                .map_err(noise_error)?;
+            self.nonce += 1;
+            self.buffer.extend_from_slice(&plaintext[..len]);
+        }
+    }
+}
+
+/// Sending half of an authenticated connection.
+pub struct SecureWriter {
+    inner: OwnedWriteHalf,
+    transport: Arc<StatelessTransportState>,
+    nonce: u64,
+}
+
+impl SecureWriter {
+    /// Encrypts `bytes` and sends them, split into as many frames as needed.
+    pub async fn write_all(&mut self, bytes: &[u8]) -> Result<()> {
+        let mut frames = Vec::new();
+        let mut ciphertext = vec![0u8; MAX_FRAME_SIZE];
+        for chunk in bytes.chunks(MAX_CHUNK_SIZE) {
+            let len = self
+                .transport
+                .write_message(self.nonce, chunk, &mut ciphertext)
+                .map_err(noise_error)?;
+            self.nonce += 1;
+            frames.extend_from_slice(&(len as u16).to_be_bytes());
+            frames.extend_from_slice(&ciphertext[..len]);
+        }
+        self.inner.write_all(&frames).await
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// Keys of nodes 0 to 2, written to a fresh directory for `test`.
+    fn key_dir(test: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("verisso-transport-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        NodeKeys::generate(&dir, 3).unwrap();
+        dir
+    }
+
+    /// Keys of nodes 0 to 2 that pin the public keys of `dir` but hold other private keys.
+    fn impostor_dir(dir: &Path) -> PathBuf {
+        let impostor = dir.join("impostor");
+        NodeKeys::generate(&impostor, 3).unwrap();
+        for id in 0..3 {
+            std::fs::copy(public_key_path(dir, id), public_key_path(&impostor, id)).unwrap();
+        }
+        impostor
+    }
+
+    fn keys(dir: &Path, node_id: ParticipantId) -> NodeKeys {
+        NodeKeys::load(dir, node_id, 3, "test").unwrap()
+    }
+
+    /// Connects `client` to `server`, expecting node `node_id` there. Returns what each side
+    /// made of the handshake.
+    async fn handshake(
+        server: &NodeKeys,
+        client: &NodeKeys,
+        node_id: ParticipantId,
+    ) -> (
+        Result<(Peer, SecureReader, SecureWriter)>,
+        Result<(SecureReader, SecureWriter)>,
+    ) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let accepted = async {
+            let (stream, _) = listener.accept().await?;
+            server.accept(stream).await
+        };
+        tokio::join!(accepted, client.connect(&addr, node_id))
+    }
+
+    fn peer(accepted: Result<(Peer, SecureReader, SecureWriter)>) -> Peer {
+        accepted.unwrap().0
+    }
+
+    #[tokio::test]
+    async fn pins_the_static_key_of_each_node() {
+        let dir = key_dir("pinning");
+        let impostor = impostor_dir(&dir);
+        let (node0, node1) = (keys(&dir, 0), keys(&dir, 1));
+
+        let (accepted, connected) = handshake(&node1, &node0, 1).await;
+        assert_eq!(peer(accepted), Peer::Node(0));
+        connected.unwrap();
+
+        // Node 1 is not who node 0 expected
+        let (_, connected) = handshake(&node1, &node0, 2).await;
+        assert_eq!(connected.err().unwrap().kind(), ErrorKind::PermissionDenied);
+
+        // Someone claiming to be node 1 without its key
+        let (_, connected) = handshake(&keys(&impostor, 1), &node0, 1).await;
+        assert_eq!(connected.err().unwrap().kind(), ErrorKind::PermissionDenied);
+        // and claiming to be node 2 when connecting, which makes it a mere client
+        let (accepted, connected) = handshake(&node1, &keys(&impostor, 2), 1).await;
+        assert_eq!(peer(accepted), Peer::Client);
+        connected.unwrap();
+
+        // Nodes of other deployments share no handshake
+        let other = NodeKeys::load(&dir, 0, 3, "other").unwrap();
+        let (accepted, connected) = handshake(&node1, &other, 1).await;
+        assert!(accepted.is_err() && connected.is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn reads_lines_up_to_the_limit() {
+        let dir = key_dir("lines");
+        let (accepted, connected) = handshake(&keys(&dir, 1), &keys(&dir, 0), 1).await;
+        let (_, mut reader, _) = accepted.unwrap();
+        let (_, mut writer) = connected.unwrap();
+
+        // Written alongside the reads, the socket does not buffer a whole line
+        let write = tokio::spawn(async move {
+            let mut lines = vec![b'a'; MAX_CHUNK_SIZE * 2];
+            lines.extend_from_slice(b"\nshort\n");
+            writer.write_all(&lines).await?;
+            writer.write_all(&vec![b'a'; MAX_LINE_SIZE + 1]).await
+        });
+        let mut line = String::new();
+        assert_eq!(
+            reader.read_line(&mut line).await.unwrap(),
+            MAX_CHUNK_SIZE * 2 + 1
+        );
+        line.clear();
+        reader.read_line(&mut line).await.unwrap();
+        assert_eq!(line, "short\n");
+
+        let error = reader.read_line(&mut String::new()).await.unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        // The rest of the write fails or lands in the socket buffer
+        drop(reader);
+        let _ = write.await.unwrap();
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}